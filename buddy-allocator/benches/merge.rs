@@ -0,0 +1,99 @@
+//! Compare the time to free blocks whose buddies are in long free lists, which the reference allocator finds by
+//! scanning the lists, and the time to find the ranges of the blocks in a heap of many ranges. Run with
+//! `cargo bench`.
+
+// The benchmark does not look at the free lists.
+#[allow(dead_code)]
+#[path = "../tests/reference/mod.rs"]
+mod reference;
+
+use buddy_allocator::BuddyAllocator;
+use core::{alloc::Layout, ptr::NonNull};
+use reference::ReferenceAllocator;
+use std::time::{Duration, Instant};
+
+const HEAP_SIZE: usize = 16 << 20;
+const MIN_BLOCK_SIZE: usize = 16;
+
+/// Allocate count blocks, free every other one, so that they fill a free list without merging, then free the rest,
+/// which merges each of them with a buddy in that list. Return the times of the allocations and the two passes of
+/// frees.
+fn bench<H>(
+    heap: &mut H,
+    count: usize,
+    alloc: fn(&mut H, Layout) -> usize,
+    dealloc: fn(&mut H, usize, Layout),
+) -> [Duration; 3] {
+    let layout = Layout::from_size_align(MIN_BLOCK_SIZE, MIN_BLOCK_SIZE).unwrap();
+    let start = Instant::now();
+    let blocks: Vec<usize> = (0..count).map(|_| alloc(heap, layout)).collect();
+    let alloc_time = start.elapsed();
+    let start = Instant::now();
+    for block in blocks.iter().step_by(2) {
+        dealloc(heap, *block, layout);
+    }
+    let unmerged_time = start.elapsed();
+    let start = Instant::now();
+    for block in blocks.iter().skip(1).step_by(2) {
+        dealloc(heap, *block, layout);
+    }
+    [alloc_time, unmerged_time, start.elapsed()]
+}
+
+fn report(name: &str, count: usize, times: [Duration; 3]) {
+    let per_op = |time: Duration, ops: usize| time.as_nanos() as usize / ops;
+    println!(
+        "{:>10}: {} blocks, alloc {}ns, free without merging {}ns, free with merging {}ns",
+        name,
+        count,
+        per_op(times[0], count),
+        per_op(times[1], count / 2),
+        per_op(times[2], count / 2)
+    );
+}
+
+fn main() {
+    let memory_layout = Layout::from_size_align(HEAP_SIZE, HEAP_SIZE).unwrap();
+    for count in [1 << 10, 1 << 13, 1 << 16] {
+        let memory = unsafe { std::alloc::alloc(memory_layout) };
+        let mut heap = BuddyAllocator::empty();
+        unsafe { heap.init(memory as usize, HEAP_SIZE) };
+        let times = bench(
+            &mut heap,
+            count,
+            |heap, layout| heap.alloc(layout).unwrap().as_ptr() as usize,
+            |heap, block, layout| heap.dealloc(NonNull::new(block as *mut u8).unwrap(), layout),
+        );
+        report("bitmap", count, times);
+
+        let mut reference = ReferenceAllocator::empty();
+        unsafe { reference.init(memory as usize, HEAP_SIZE) };
+        let times = bench(
+            &mut reference,
+            count,
+            |heap, layout| heap.alloc(layout, MIN_BLOCK_SIZE).unwrap(),
+            |heap, block, layout| heap.dealloc(block, layout, MIN_BLOCK_SIZE),
+        );
+        report("reference", count, times);
+        unsafe { std::alloc::dealloc(memory, memory_layout) };
+    }
+    // The heap grows a range at a time, e.g. in the kernel. The ranges are added from the top down, so that each is
+    // inserted first in the array of the ranges.
+    let count = 1 << 16;
+    for range_count in [1, 64, 1024] {
+        let memory = unsafe { std::alloc::alloc(memory_layout) };
+        let range_size = HEAP_SIZE / range_count;
+        let mut heap = BuddyAllocator::empty();
+        for i in (0..range_count).rev() {
+            unsafe { heap.init(memory as usize + i * range_size, range_size) };
+        }
+        let times = bench(
+            &mut heap,
+            count,
+            |heap, layout| heap.alloc(layout).unwrap().as_ptr() as usize,
+            |heap, block, layout| heap.dealloc(NonNull::new(block as *mut u8).unwrap(), layout),
+        );
+        report(&format!("{} ranges", range_count), count, times);
+        unsafe { std::alloc::dealloc(memory, memory_layout) };
+    }
+}
@@ -0,0 +1,83 @@
+//! Run arbitrary allocations and frees on a heap at an arbitrary offset, checking that the blocks are aligned,
+//! inside the heap and disjoint, and that freeing everything merges the heap back. Run with
+//! `cargo fuzz run alloc_dealloc` in buddy-allocator.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use buddy_allocator::BuddyAllocator;
+use core::{alloc::Layout, ptr::NonNull};
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+
+const MEMORY_SIZE: usize = 1 << 16;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Alloc {
+        size: u16,
+        align_shift: u8,
+    },
+    /// Free the live block at index % the number of live blocks.
+    Dealloc {
+        index: u16,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    offset: u16,
+    size: u16,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let memory_layout = Layout::from_size_align(MEMORY_SIZE, 4096).unwrap();
+    let memory = unsafe { std::alloc::alloc(memory_layout) } as usize;
+    let offset = input.offset as usize % MEMORY_SIZE;
+    let size = (input.size as usize).min(MEMORY_SIZE - offset);
+    let (start, end) = (memory + offset, memory + offset + size);
+    let mut heap = BuddyAllocator::empty();
+    unsafe { heap.init(start, size) };
+    let initial = heap.stats();
+
+    // The live blocks by their addresses.
+    let mut blocks: BTreeMap<usize, Layout> = BTreeMap::new();
+    for op in input.ops {
+        match op {
+            Op::Alloc { size, align_shift } => {
+                let layout =
+                    Layout::from_size_align(size as usize, 1 << (align_shift % 16)).unwrap();
+                if let Ok(ptr) = heap.alloc(layout) {
+                    let ptr = ptr.as_ptr() as usize;
+                    assert_eq!(ptr % layout.align(), 0);
+                    assert!(start <= ptr && ptr + layout.size() <= end);
+                    if let Some((prev, prev_layout)) = blocks.range(..ptr).next_back() {
+                        assert!(prev + prev_layout.size() <= ptr);
+                    }
+                    if let Some((next, _)) = blocks.range(ptr..).next() {
+                        assert!(ptr + layout.size() <= *next);
+                    }
+                    blocks.insert(ptr, layout);
+                }
+            }
+            Op::Dealloc { index } => {
+                if blocks.is_empty() {
+                    continue;
+                }
+                let ptr = *blocks.keys().nth(index as usize % blocks.len()).unwrap();
+                let layout = blocks.remove(&ptr).unwrap();
+                heap.dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout);
+            }
+        }
+        let stats = heap.stats();
+        assert_eq!(stats.free + stats.allocated, stats.total);
+    }
+    for (ptr, layout) in blocks {
+        heap.dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout);
+    }
+    let stats = heap.stats();
+    assert_eq!(stats.free_by_class, initial.free_by_class);
+    assert_eq!(stats.allocated, 0);
+    unsafe { std::alloc::dealloc(memory as *mut u8, memory_layout) };
+});
@@ -5,15 +5,99 @@ use core::{
     alloc::{GlobalAlloc, Layout},
     cmp::{max, min},
     ops::Deref,
-    ptr::NonNull,
+    ptr::{null_mut, NonNull},
 };
 use spin::Mutex;
 
 mod linked_list;
 
+/// The number of size classes, which are the powers of 2 below 1 << CLASS_COUNT.
+pub const CLASS_COUNT: usize = 32;
+/// Each free block holds a node of its free list.
+const MIN_BLOCK_SIZE: usize = LinkedList::NODE_SIZE;
+const MIN_CLASS: usize = MIN_BLOCK_SIZE.trailing_zeros() as usize;
+
 pub struct BuddyAllocator {
-    /// free_list[i] contains the blocks of size (core::sizeof::<usize>() << i).
-    free_list: [LinkedList; 32],
+    /// free_list[i] contains the blocks of size (1 << i).
+    free_list: [LinkedList; CLASS_COUNT],
+    /// The headers of the ranges added by init, sorted by address, so that the range of a block is found by a binary
+    /// search. The array is kept after the bitmaps of the latest range, which copies it from the one before.
+    regions: *mut *mut Region,
+    region_count: usize,
+    /// The bytes of the blocks added by init.
+    total: usize,
+    /// The bytes asked for by the live allocations, and the bytes of the blocks given to them.
+    requested: usize,
+    allocated: usize,
+    peak_allocated: usize,
+    alloc_count: usize,
+    dealloc_count: usize,
+    failure_count: usize,
+}
+
+unsafe impl Send for BuddyAllocator {}
+
+/// The header of a range added by init, which is followed by its bitmaps. The blocks cannot record whether they are
+/// free themselves, since the data of an allocated block may look like anything.
+struct Region {
+    /// The blocks of the region are in [start, end).
+    start: usize,
+    end: usize,
+    /// The bit (offsets[class] + ((addr - start) >> class)) of the bitmaps is set iff the block of size (1 << class)
+    /// at addr is free.
+    offsets: [usize; CLASS_COUNT],
+}
+
+impl Region {
+    fn bit(&self, addr: usize, class: usize) -> (*mut usize, usize) {
+        let bit = self.offsets[class] + ((addr - self.start) >> class);
+        let bitmap = (self as *const Self as usize + size_of::<Self>()) as *mut usize;
+        let bits = usize::BITS as usize;
+        (bitmap.wrapping_add(bit / bits), 1 << (bit % bits))
+    }
+
+    fn is_free(&self, addr: usize, class: usize) -> bool {
+        let (word, mask) = self.bit(addr, class);
+        unsafe { *word & mask != 0 }
+    }
+
+    fn set_free(&self, addr: usize, class: usize, free: bool) {
+        let (word, mask) = self.bit(addr, class);
+        unsafe {
+            if free {
+                *word |= mask;
+            } else {
+                *word &= !mask;
+            }
+        }
+    }
+}
+
+/// A snapshot of the usage of a BuddyAllocator.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapStats {
+    /// The bytes of the blocks added to the heap, which leaves out the bitmaps.
+    pub total: usize,
+    /// The bytes asked for by the live allocations.
+    pub requested: usize,
+    /// The bytes of the blocks given to the live allocations, which are rounded up to powers of 2. The difference
+    /// from requested is lost to internal fragmentation.
+    pub allocated: usize,
+    /// The most bytes allocated at once.
+    pub peak_allocated: usize,
+    /// The bytes in free blocks, which is total - allocated.
+    pub free: usize,
+    /// free_by_class[i] is the bytes in free blocks of size (1 << i).
+    pub free_by_class: [usize; CLASS_COUNT],
+    /// The size of the largest free block, which is the largest allocation that can succeed. The smaller it is
+    /// compared to free, the more fragmented the heap is.
+    pub largest_free_block: usize,
+    pub alloc_count: usize,
+    pub dealloc_count: usize,
+    /// The allocations that found no free block large enough. LockedBuddyAllocator retries them after its rescue,
+    /// so they may still succeed.
+    pub failure_count: usize,
 }
 
 fn prev_power_of_two(num: usize) -> usize {
@@ -25,85 +109,207 @@ fn prev_power_of_two(num: usize) -> usize {
     }
 }
 
+/// The size of the block allocated for layout.
+fn block_size(layout: &Layout) -> usize {
+    max(
+        layout.size().next_power_of_two(),
+        max(layout.align(), MIN_BLOCK_SIZE),
+    )
+}
+
 impl BuddyAllocator {
     pub const fn empty() -> Self {
         Self {
-            free_list: [LinkedList::new(); 32],
+            free_list: [LinkedList::new(); CLASS_COUNT],
+            regions: NonNull::dangling().as_ptr(),
+            region_count: 0,
+            total: 0,
+            requested: 0,
+            allocated: 0,
+            peak_allocated: 0,
+            alloc_count: 0,
+            dealloc_count: 0,
+            failure_count: 0,
         }
     }
 
-    /// Add a range of memory [start, start + size) to the heap.
-    pub unsafe fn init(&mut self, mut start: usize, size: usize) {
-        // Avoid unaligned access.
-        start = (start + size_of::<usize>() - 1) & (!size_of::<usize>() + 1);
-        let end = (start + size) & (!size_of::<usize>() + 1);
-        assert!(start <= end);
+    /// Add a range of memory [start, start + size) to the heap. The range begins with the bitmaps of its blocks and the
+    /// array of the ranges, and is ignored if it is too small to hold a block after them. The blocks of different ranges are never merged,
+    /// even if the ranges are adjacent.
+    ///
+    /// # Safety
+    ///
+    /// The range should be valid memory that is not used by anything else.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        let header = (start + align_of::<Region>() - 1) & !(align_of::<Region>() - 1);
+        let end = (start + size) & !(MIN_BLOCK_SIZE - 1);
+        if end < header + size_of::<Region>() {
+            return;
+        }
+        // The bitmaps are sized for [header, end), which is a bit more than the blocks after them.
+        let mut offsets = [0; CLASS_COUNT];
+        let mut bits = 0;
+        for (class, offset) in offsets.iter_mut().enumerate().skip(MIN_CLASS) {
+            *offset = bits;
+            bits += (end - header) >> class;
+        }
+        let bitmap = header + size_of::<Region>();
+        let words = bits.div_ceil(usize::BITS as usize);
+        let regions = (bitmap + words * size_of::<usize>()) as *mut *mut Region;
+        let region_count = self.region_count + 1;
+        let mut start =
+            (regions as usize + region_count * size_of::<*mut Region>() + MIN_BLOCK_SIZE - 1)
+                & !(MIN_BLOCK_SIZE - 1);
+        if start >= end {
+            return;
+        }
+        let region = header as *mut Region;
+        region.write(Region {
+            start,
+            end,
+            offsets,
+        });
+        core::ptr::write_bytes(bitmap as *mut usize, 0, words);
+        // The array of the ranges is copied with the new one inserted in order.
+        let old_regions = core::slice::from_raw_parts(self.regions, self.region_count);
+        let position = old_regions.partition_point(|old| (**old).start < start);
+        core::ptr::copy_nonoverlapping(self.regions, regions, position);
+        regions.add(position).write(region);
+        core::ptr::copy_nonoverlapping(
+            self.regions.add(position),
+            regions.add(position + 1),
+            self.region_count - position,
+        );
+        self.regions = regions;
+        self.region_count = region_count;
+        self.total += end - start;
         while start < end {
             let lowbit = start & (!start + 1);
             let size = min(lowbit, prev_power_of_two(end - start));
-            self.free_list[size.trailing_zeros() as usize].push(start as *mut usize);
+            self.push_free(&*region, start, size.trailing_zeros() as usize);
             start += size;
         }
     }
 
+    /// Return the region containing addr, which is the last one starting at or below it.
+    fn region_of(&self, addr: usize) -> *mut Region {
+        let regions = unsafe { core::slice::from_raw_parts(self.regions, self.region_count) };
+        let position = regions.partition_point(|region| unsafe { (**region).start } <= addr);
+        match position.checked_sub(1).map(|i| regions[i]) {
+            Some(region) if addr < unsafe { (*region).end } => region,
+            _ => panic!("{:#x} is not in the heap", addr),
+        }
+    }
+
+    fn push_free(&mut self, region: &Region, block: usize, class: usize) {
+        region.set_free(block, class, true);
+        self.free_list[class].push(block as *mut usize);
+    }
+
+    fn remove_free(&mut self, region: &Region, block: usize, class: usize) {
+        region.set_free(block, class, false);
+        self.free_list[class].remove(block as *mut usize);
+    }
+
+    #[allow(clippy::result_unit_err)]
     pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<usize>()),
-        );
+        let size = block_size(&layout);
         let class = size.trailing_zeros() as usize;
-        if let Some(i) = (class..self.free_list.len()).find(|i| !self.free_list[*i].is_empty()) {
-            for j in (class + 1..=i).rev() {
-                let block = self.free_list[j].pop().unwrap();
-                self.free_list[j - 1].push(block);
-                self.free_list[j - 1].push((block as usize + (1 << (j - 1))) as *mut usize);
+        if let Some(i) = (class..CLASS_COUNT).find(|i| !self.free_list[*i].is_empty()) {
+            let mut block = self.free_list[i].pop().unwrap() as usize;
+            let region = unsafe { &*self.region_of(block) };
+            region.set_free(block, i, false);
+            // Split the block, freeing the lower halves.
+            for j in (class..i).rev() {
+                self.push_free(region, block, j);
+                block += 1 << j;
             }
-            if let Some(result) = NonNull::new(self.free_list[class].pop().unwrap() as *mut u8) {
-                Ok(result)
-            } else {
-                Err(())
-            }
-        } else {
-            Err(())
+            self.requested += layout.size();
+            self.allocated += size;
+            self.peak_allocated = max(self.peak_allocated, self.allocated);
+            self.alloc_count += 1;
+            // A region never begins at 0, since it holds its header.
+            return Ok(NonNull::new(block as *mut u8).unwrap());
         }
+        self.failure_count += 1;
+        Err(())
     }
 
     pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<usize>()),
-        );
+        let size = block_size(&layout);
+        self.requested -= layout.size();
+        self.allocated -= size;
+        self.dealloc_count += 1;
         let mut class = size.trailing_zeros() as usize;
-        let mut ptr = ptr.as_ptr() as usize;
-        self.free_list[class].push(ptr as *mut usize);
-        while class < self.free_list.len() {
-            let buddy = ptr ^ (1 << class);
-            if let Some(block) = self.free_list[class]
-                .iter_mut()
-                .find(|block| block.value() as usize == buddy)
+        let mut block = ptr.as_ptr() as usize;
+        let region = unsafe { &*self.region_of(block) };
+        // Merge the block with its buddy as long as the buddy is free, which the bitmaps tell in constant time.
+        while class + 1 < CLASS_COUNT {
+            let buddy = block ^ (1 << class);
+            let merged = min(block, buddy);
+            if merged < region.start
+                || merged + (2 << class) > region.end
+                || !region.is_free(buddy, class)
             {
-                block.pop();
-                self.free_list[class].pop();
-                ptr = min(ptr, buddy);
-                class += 1;
-                self.free_list[class].push(ptr as *mut usize);
-            } else {
                 break;
             }
+            self.remove_free(region, buddy, class);
+            block = merged;
+            class += 1;
+        }
+        self.push_free(region, block, class);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            total: self.total,
+            requested: self.requested,
+            allocated: self.allocated,
+            peak_allocated: self.peak_allocated,
+            free: 0,
+            free_by_class: [0; CLASS_COUNT],
+            largest_free_block: 0,
+            alloc_count: self.alloc_count,
+            dealloc_count: self.dealloc_count,
+            failure_count: self.failure_count,
+        };
+        for (class, list) in self.free_list.iter().enumerate() {
+            let free = list.len() << class;
+            stats.free_by_class[class] = free;
+            stats.free += free;
+            if free > 0 {
+                stats.largest_free_block = 1 << class;
+            }
         }
+        stats
     }
 }
 
 pub struct LockedBuddyAllocator {
     heap: Mutex<BuddyAllocator>,
+    /// Called with the heap and the layout when an allocation fails, so that it can add memory to the heap before
+    /// the allocation is retried.
+    rescue: Option<fn(&mut BuddyAllocator, &Layout)>,
 }
 
 impl LockedBuddyAllocator {
     pub const fn empty() -> Self {
         Self {
             heap: Mutex::new(BuddyAllocator::empty()),
+            rescue: None,
+        }
+    }
+
+    pub const fn with_rescue(rescue: fn(&mut BuddyAllocator, &Layout)) -> Self {
+        Self {
+            heap: Mutex::new(BuddyAllocator::empty()),
+            rescue: Some(rescue),
         }
     }
+
+    pub fn stats(&self) -> HeapStats {
+        self.lock().stats()
+    }
 }
 
 impl Deref for LockedBuddyAllocator {
@@ -116,10 +322,17 @@ impl Deref for LockedBuddyAllocator {
 
 unsafe impl GlobalAlloc for LockedBuddyAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.lock()
-            .alloc(layout)
-            .ok()
-            .map_or(0 as *mut u8, |ptr| ptr.as_ptr())
+        let mut heap = self.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        match self.rescue {
+            Some(rescue) => {
+                rescue(&mut heap, &layout);
+                heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+            }
+            None => null_mut(),
+        }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
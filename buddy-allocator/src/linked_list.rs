@@ -1,81 +1,77 @@
+use core::ptr::null_mut;
+
+/// The header of an item, which is stored in the item itself.
+struct Node {
+    prev: *mut Node,
+    next: *mut Node,
+}
+
+/// A doubly linked list of free memory, so that any item can be removed in constant time.
 #[derive(Clone, Copy)]
 pub struct LinkedList {
-    head: *mut usize,
+    head: *mut Node,
 }
 
 impl LinkedList {
+    /// The size of the header written to each item.
+    pub const NODE_SIZE: usize = size_of::<Node>();
+
     pub const fn new() -> Self {
-        Self {
-            head: core::ptr::null_mut(),
-        }
+        Self { head: null_mut() }
     }
 
     pub fn is_empty(&self) -> bool {
         self.head.is_null()
     }
 
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        let mut cur = self.head;
+        while !cur.is_null() {
+            len += 1;
+            cur = unsafe { (*cur).next };
+        }
+        len
+    }
+
+    /// item should point to at least NODE_SIZE bytes of memory that is not used by anything else.
     pub fn push(&mut self, item: *mut usize) {
-        unsafe { *item = self.head as usize };
-        self.head = item;
+        let node = item as *mut Node;
+        unsafe {
+            (*node).prev = null_mut();
+            (*node).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).prev = node;
+            }
+        }
+        self.head = node;
     }
 
     pub fn pop(&mut self) -> Option<*mut usize> {
         if self.is_empty() {
             None
         } else {
-            let item = self.head;
-            self.head = unsafe { *item as *mut usize };
+            let item = self.head as *mut usize;
+            self.remove(item);
             Some(item)
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut {
-        IterMut {
-            cur: self.head,
-            prev: &mut self.head as *mut _ as *mut usize,
-        }
-    }
-}
-
-unsafe impl Send for LinkedList {}
-
-pub struct ListNode {
-    cur: *mut usize,
-    prev: *mut usize,
-}
-
-impl ListNode {
-    pub fn pop(self) -> *mut usize {
+    /// Remove item, which should be in self.
+    pub fn remove(&mut self, item: *mut usize) {
+        let node = item as *mut Node;
         unsafe {
-            *(self.prev) = *(self.cur);
+            let (prev, next) = ((*node).prev, (*node).next);
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
         }
-        self.cur
     }
-
-    pub fn value(&self) -> *mut usize {
-        self.cur
-    }
-}
-
-pub struct IterMut {
-    cur: *mut usize,
-    prev: *mut usize,
 }
 
-impl Iterator for IterMut {
-    type Item = ListNode;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.cur.is_null() {
-            None
-        } else {
-            let res = ListNode {
-                cur: self.cur,
-                prev: self.prev,
-            };
-            self.prev = self.cur;
-            self.cur = unsafe { *self.cur as *mut usize };
-            Some(res)
-        }
-    }
-}
+unsafe impl Send for LinkedList {}
@@ -0,0 +1,64 @@
+//! Helpers shared by the tests, each of which uses some of them.
+#![allow(dead_code)]
+
+use buddy_allocator::BuddyAllocator;
+use core::alloc::Layout;
+
+/// The smallest block of BuddyAllocator, which holds two pointers.
+pub const MIN_BLOCK_SIZE: usize = 16;
+
+/// A xorshift generator, so that each seed gives the same sequence.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Memory aligned to its size, so that the blocks carved from it do not depend on where it is.
+pub struct Memory {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, size).unwrap();
+        Self {
+            ptr: unsafe { std::alloc::alloc_zeroed(layout) },
+            layout,
+        }
+    }
+
+    pub fn start(&self) -> usize {
+        self.ptr as usize
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// The number of free blocks of each size.
+pub fn free_blocks(heap: &BuddyAllocator) -> [usize; 32] {
+    let stats = heap.stats();
+    core::array::from_fn(|class| stats.free_by_class[class] >> class)
+}
+
+/// A layout of up to 8KiB, with smaller sizes more likely, and an alignment of up to 256.
+pub fn random_layout(rng: &mut Rng) -> Layout {
+    let max_size = 1 << rng.below(14);
+    let size = 1 + rng.below(max_size);
+    let align = 1 << rng.below(9);
+    Layout::from_size_align(size, align).unwrap()
+}
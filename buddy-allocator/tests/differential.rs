@@ -0,0 +1,77 @@
+//! Check that the allocator makes the same choices as the reference allocator, which merges blocks by scanning the
+//! free lists.
+
+mod common;
+mod reference;
+
+use buddy_allocator::BuddyAllocator;
+use common::{free_blocks, random_layout, Memory, Rng, MIN_BLOCK_SIZE};
+use core::{alloc::Layout, ptr::NonNull};
+use reference::ReferenceAllocator;
+
+const HEAP_SIZE: usize = 1 << 20;
+
+fn run(seed: u64, ops: usize) {
+    let mut rng = Rng(seed);
+    let (memory, reference_memory) = (Memory::new(HEAP_SIZE), Memory::new(HEAP_SIZE));
+    let mut heap = BuddyAllocator::empty();
+    unsafe { heap.init(memory.start(), HEAP_SIZE) };
+    // The reference allocator gets the blocks that follow the bitmaps.
+    let offset = HEAP_SIZE - heap.stats().total;
+    let mut reference = ReferenceAllocator::empty();
+    unsafe { reference.init(reference_memory.start() + offset, HEAP_SIZE - offset) };
+    let initial_blocks = reference.free_blocks();
+    assert_eq!(free_blocks(&heap), initial_blocks);
+
+    let mut live: Vec<(usize, Layout)> = Vec::new();
+    for op in 0..ops {
+        if live.is_empty() || rng.below(100) < 55 {
+            let layout = random_layout(&mut rng);
+            match (heap.alloc(layout), reference.alloc(layout, MIN_BLOCK_SIZE)) {
+                (Ok(ptr), Some(reference_ptr)) => {
+                    let offset = ptr.as_ptr() as usize - memory.start();
+                    assert_eq!(offset, reference_ptr - reference_memory.start());
+                    live.push((offset, layout));
+                }
+                (Err(()), None) => {}
+                (result, reference_result) => panic!(
+                    "seed {} op {}: {:?} got {:?}, but the reference got {:?}",
+                    seed, op, layout, result, reference_result
+                ),
+            }
+        } else {
+            let (offset, layout) = live.swap_remove(rng.below(live.len()));
+            let ptr = NonNull::new((memory.start() + offset) as *mut u8).unwrap();
+            heap.dealloc(ptr, layout);
+            reference.dealloc(reference_memory.start() + offset, layout, MIN_BLOCK_SIZE);
+        }
+        if op % 64 == 0 {
+            assert_eq!(
+                free_blocks(&heap),
+                reference.free_blocks(),
+                "seed {} op {}",
+                seed,
+                op
+            );
+        }
+    }
+    for (offset, layout) in live {
+        let ptr = NonNull::new((memory.start() + offset) as *mut u8).unwrap();
+        heap.dealloc(ptr, layout);
+        reference.dealloc(reference_memory.start() + offset, layout, MIN_BLOCK_SIZE);
+    }
+    assert_eq!(free_blocks(&heap), initial_blocks);
+    assert_eq!(reference.free_blocks(), initial_blocks);
+}
+
+#[test]
+fn same_as_reference() {
+    for seed in 1..=32 {
+        run(seed, 4096);
+    }
+}
+
+#[test]
+fn same_as_reference_long() {
+    run(0x5eed, 100_000);
+}
@@ -0,0 +1,208 @@
+//! Randomized tests of the guarantees of the allocator: the blocks are aligned, inside the heap and disjoint, and
+//! freeing all of them merges the heap back into the blocks it started with.
+
+mod common;
+
+use buddy_allocator::BuddyAllocator;
+use common::{free_blocks, random_layout, Memory, Rng, MIN_BLOCK_SIZE};
+use core::{alloc::Layout, ptr::NonNull};
+use std::collections::BTreeMap;
+
+/// The live blocks by their addresses, with their layouts and the byte they are filled with.
+#[derive(Default)]
+struct Blocks(BTreeMap<usize, (Layout, u8)>);
+
+impl Blocks {
+    /// Check the new block at ptr against the heap [start, end) and the other blocks, then fill it.
+    fn insert(&mut self, ptr: NonNull<u8>, layout: Layout, start: usize, end: usize, fill: u8) {
+        let ptr = ptr.as_ptr() as usize;
+        assert_eq!(
+            ptr % layout.align(),
+            0,
+            "{:#x} is not aligned for {:?}",
+            ptr,
+            layout
+        );
+        assert!(
+            start <= ptr && ptr + layout.size() <= end,
+            "{:#x} is out of the heap",
+            ptr
+        );
+        if let Some((prev, (prev_layout, _))) = self.0.range(..ptr).next_back() {
+            assert!(
+                prev + prev_layout.size() <= ptr,
+                "{:#x} overlaps {:#x}",
+                ptr,
+                prev
+            );
+        }
+        if let Some((next, _)) = self.0.range(ptr..).next() {
+            assert!(
+                ptr + layout.size() <= *next,
+                "{:#x} overlaps {:#x}",
+                ptr,
+                next
+            );
+        }
+        unsafe { core::ptr::write_bytes(ptr as *mut u8, fill, layout.size()) };
+        self.0.insert(ptr, (layout, fill));
+    }
+
+    /// Remove the block at ptr, checking that nothing else wrote to it.
+    fn remove(&mut self, ptr: usize) -> Layout {
+        let (layout, fill) = self.0.remove(&ptr).unwrap();
+        let bytes = unsafe { core::slice::from_raw_parts(ptr as *const u8, layout.size()) };
+        assert!(
+            bytes.iter().all(|byte| *byte == fill),
+            "{:#x} was overwritten",
+            ptr
+        );
+        layout
+    }
+
+    fn nth(&self, n: usize) -> usize {
+        *self.0.keys().nth(n).unwrap()
+    }
+}
+
+/// Run ops random allocations and frees on heap, whose blocks are in [start, end), then free everything.
+fn run(heap: &mut BuddyAllocator, start: usize, end: usize, rng: &mut Rng, ops: usize) {
+    let initial_blocks = free_blocks(heap);
+    let mut blocks = Blocks::default();
+    for op in 0..ops {
+        if blocks.0.is_empty() || rng.below(100) < 60 {
+            let layout = random_layout(rng);
+            if let Ok(ptr) = heap.alloc(layout) {
+                blocks.insert(ptr, layout, start, end, op as u8);
+            }
+        } else {
+            let ptr = blocks.nth(rng.below(blocks.0.len()));
+            let layout = blocks.remove(ptr);
+            heap.dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout);
+        }
+        let stats = heap.stats();
+        assert_eq!(stats.free + stats.allocated, stats.total);
+        assert!(stats.requested <= stats.allocated);
+    }
+    // Free the rest in a random order.
+    while !blocks.0.is_empty() {
+        let ptr = blocks.nth(rng.below(blocks.0.len()));
+        let layout = blocks.remove(ptr);
+        heap.dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout);
+    }
+    assert_eq!(free_blocks(heap), initial_blocks);
+    let stats = heap.stats();
+    assert_eq!((stats.allocated, stats.requested), (0, 0));
+    assert_eq!(stats.alloc_count, stats.dealloc_count);
+}
+
+#[test]
+fn blocks_are_aligned_and_disjoint() {
+    const HEAP_SIZE: usize = 1 << 20;
+    for seed in 1..=16 {
+        let memory = Memory::new(HEAP_SIZE);
+        let mut heap = BuddyAllocator::empty();
+        unsafe { heap.init(memory.start(), HEAP_SIZE) };
+        let end = memory.start() + HEAP_SIZE;
+        run(&mut heap, memory.start(), end, &mut Rng(seed), 4096);
+    }
+}
+
+#[test]
+fn freeing_everything_merges_the_heap() {
+    const HEAP_SIZE: usize = 1 << 16;
+    let memory = Memory::new(HEAP_SIZE);
+    let mut heap = BuddyAllocator::empty();
+    unsafe { heap.init(memory.start(), HEAP_SIZE) };
+    let initial_blocks = free_blocks(&heap);
+    // Use up the heap with the smallest blocks, then free them in a random order.
+    let layout = Layout::from_size_align(1, 1).unwrap();
+    let mut ptrs = Vec::new();
+    while let Ok(ptr) = heap.alloc(layout) {
+        ptrs.push(ptr);
+    }
+    assert_eq!(ptrs.len() * MIN_BLOCK_SIZE, heap.stats().total);
+    assert_eq!(heap.stats().free, 0);
+    let mut rng = Rng(7);
+    while !ptrs.is_empty() {
+        let ptr = ptrs.swap_remove(rng.below(ptrs.len()));
+        heap.dealloc(ptr, layout);
+    }
+    assert_eq!(free_blocks(&heap), initial_blocks);
+}
+
+#[test]
+fn unaligned_ranges() {
+    // The heap is in the middle of the memory, and the rest should be left alone.
+    const MEMORY_SIZE: usize = 1 << 16;
+    const GUARD: u8 = 0xa5;
+    let mut rng = Rng(3);
+    for offset in 0..40 {
+        for size in [0, 1, 15, 200, 300, 333, 4097, 12345, 30000] {
+            let memory = Memory::new(MEMORY_SIZE);
+            let bytes =
+                unsafe { core::slice::from_raw_parts_mut(memory.start() as *mut u8, MEMORY_SIZE) };
+            bytes.fill(GUARD);
+            let start = memory.start() + MEMORY_SIZE / 4 + offset;
+            let mut heap = BuddyAllocator::empty();
+            unsafe { heap.init(start, size) };
+            let stats = heap.stats();
+            assert!(stats.total <= size);
+            assert_eq!(stats.free, stats.total);
+            if stats.total > 0 {
+                assert!(stats.largest_free_block >= MIN_BLOCK_SIZE);
+                run(&mut heap, start, start + size, &mut rng, 256);
+            } else {
+                // The range is ignored.
+                assert!(heap.alloc(Layout::from_size_align(1, 1).unwrap()).is_err());
+            }
+            let heap_range = start - memory.start()..start - memory.start() + size;
+            for (i, byte) in memory.bytes().iter().enumerate() {
+                assert!(
+                    heap_range.contains(&i) || *byte == GUARD,
+                    "offset {} size {}: byte {:#x} outside the heap was written",
+                    offset,
+                    size,
+                    i
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn several_ranges() {
+    const RANGE_SIZE: usize = 1 << 16;
+    let memories = [
+        Memory::new(RANGE_SIZE),
+        Memory::new(RANGE_SIZE),
+        Memory::new(RANGE_SIZE),
+    ];
+    let mut heap = BuddyAllocator::empty();
+    // Each range has its own bitmaps, so they can be added in any order.
+    for (i, memory) in memories.iter().enumerate() {
+        unsafe { heap.init(memory.start() + i * 24, RANGE_SIZE - i * 24) };
+    }
+    let initial_blocks = free_blocks(&heap);
+    let mut rng = Rng(11);
+    let mut blocks = Vec::new();
+    for _ in 0..4096 {
+        if blocks.is_empty() || rng.below(100) < 60 {
+            let layout = random_layout(&mut rng);
+            if let Ok(ptr) = heap.alloc(layout) {
+                let ptr = ptr.as_ptr() as usize;
+                assert!(memories.iter().any(|memory| {
+                    memory.start() <= ptr && ptr + layout.size() <= memory.start() + RANGE_SIZE
+                }));
+                blocks.push((ptr, layout));
+            }
+        } else {
+            let (ptr, layout) = blocks.swap_remove(rng.below(blocks.len()));
+            heap.dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout);
+        }
+    }
+    for (ptr, layout) in blocks {
+        heap.dealloc(NonNull::new(ptr as *mut u8).unwrap(), layout);
+    }
+    assert_eq!(free_blocks(&heap), initial_blocks);
+}
@@ -0,0 +1,118 @@
+//! The buddy allocator as it was before the bitmaps, which finds the buddy of a block by scanning its free list. Only
+//! init differs, which no longer rounds the end of an unaligned range past it. The tests check the allocator against
+//! it, and the benchmark compares them.
+
+use core::{
+    alloc::Layout,
+    cmp::{max, min},
+    ptr::null_mut,
+};
+
+pub struct ReferenceAllocator {
+    /// free_list[i] is a singly linked list of the blocks of size (1 << i).
+    free_list: [*mut usize; 32],
+}
+
+fn prev_power_of_two(num: usize) -> usize {
+    let next_power = num.next_power_of_two();
+    if num == next_power {
+        next_power
+    } else {
+        next_power >> 1
+    }
+}
+
+impl ReferenceAllocator {
+    pub fn empty() -> Self {
+        Self {
+            free_list: [null_mut(); 32],
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The range should be valid memory that is not used by anything else.
+    pub unsafe fn init(&mut self, mut start: usize, size: usize) {
+        let end = (start + size) & !(size_of::<usize>() - 1);
+        start = (start + size_of::<usize>() - 1) & !(size_of::<usize>() - 1);
+        while start < end {
+            let lowbit = start & (!start + 1);
+            let size = min(lowbit, prev_power_of_two(end - start));
+            self.push(size.trailing_zeros() as usize, start);
+            start += size;
+        }
+    }
+
+    fn push(&mut self, class: usize, block: usize) {
+        unsafe { *(block as *mut usize) = self.free_list[class] as usize };
+        self.free_list[class] = block as *mut usize;
+    }
+
+    fn pop(&mut self, class: usize) -> Option<usize> {
+        let block = self.free_list[class];
+        if block.is_null() {
+            None
+        } else {
+            self.free_list[class] = unsafe { *block as *mut usize };
+            Some(block as usize)
+        }
+    }
+
+    /// Remove block from free_list[class] if it is there.
+    fn remove(&mut self, class: usize, block: usize) -> bool {
+        let mut prev = &mut self.free_list[class] as *mut *mut usize;
+        unsafe {
+            while !(*prev).is_null() {
+                if *prev as usize == block {
+                    *prev = **prev as *mut usize;
+                    return true;
+                }
+                prev = *prev as *mut *mut usize;
+            }
+        }
+        false
+    }
+
+    /// min_block_size is the smallest block allocated, which should be a power of 2.
+    pub fn alloc(&mut self, layout: Layout, min_block_size: usize) -> Option<usize> {
+        let size = max(
+            layout.size().next_power_of_two(),
+            max(layout.align(), min_block_size),
+        );
+        let class = size.trailing_zeros() as usize;
+        let i = (class..self.free_list.len()).find(|i| !self.free_list[*i].is_null())?;
+        for j in (class + 1..=i).rev() {
+            let block = self.pop(j).unwrap();
+            self.push(j - 1, block);
+            self.push(j - 1, block + (1 << (j - 1)));
+        }
+        self.pop(class)
+    }
+
+    pub fn dealloc(&mut self, ptr: usize, layout: Layout, min_block_size: usize) {
+        let size = max(
+            layout.size().next_power_of_two(),
+            max(layout.align(), min_block_size),
+        );
+        let mut class = size.trailing_zeros() as usize;
+        let mut ptr = ptr;
+        while class + 1 < self.free_list.len() && self.remove(class, ptr ^ (1 << class)) {
+            ptr = min(ptr, ptr ^ (1 << class));
+            class += 1;
+        }
+        self.push(class, ptr);
+    }
+
+    /// The number of free blocks of each size.
+    pub fn free_blocks(&self) -> [usize; 32] {
+        let mut counts = [0; 32];
+        for (class, count) in counts.iter_mut().enumerate() {
+            let mut block = self.free_list[class];
+            while !block.is_null() {
+                *count += 1;
+                block = unsafe { *block as *mut usize };
+            }
+        }
+        counts
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     block_dev::BlockDevice,
     efs::EasyFileSystem,
     layout::{DirEntry, DiskInode, DiskInodeType, DIRENTRY_SZ},
+    BLOCK_SZ,
 };
 use alloc::{string::String, sync::Arc, vec::Vec};
 use spin::{Mutex, MutexGuard};
@@ -29,6 +30,17 @@ impl Inode {
         }
     }
 
+    /// Return a number identifying the file, which is the same for all the Inodes of it.
+    pub fn id(&self) -> usize {
+        self.disk_id * BLOCK_SZ + self.block_offset
+    }
+
+    /// Return the size of the file in bytes.
+    pub fn size(&self) -> usize {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| disk_inode.size as usize)
+    }
+
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
         get_block_cache(self.disk_id, &self.block_device)
             .lock()
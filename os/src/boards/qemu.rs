@@ -11,6 +11,8 @@ pub const VIRT_UART0: usize = 0x10000000;
 pub const VIRT_UART0_SIZE: usize = 0x100;
 pub const VIRT_VIRTIO: usize = 0x10001000;
 pub const VIRT_VIRTIO_SIZE: usize = 0x1000;
+pub const VIRT_FW_CFG: usize = 0x10100000;
+pub const VIRT_FW_CFG_SIZE: usize = 0x1000;
 
 pub const MMIO: &[(usize, usize)] = &[
     (VIRT_TEST, VIRT_TEST_SIZE),
@@ -18,9 +20,11 @@ pub const MMIO: &[(usize, usize)] = &[
     (VIRT_CLINT, VIRT_CLINT_SIZE),
     (VIRT_UART0, VIRT_UART0_SIZE),
     (VIRT_VIRTIO, VIRT_VIRTIO_SIZE),
+    (VIRT_FW_CFG, VIRT_FW_CFG_SIZE),
 ];
 
 pub const MTIME: usize = VIRT_CLINT + 0xbff8;
 pub const MTIMECMP: usize = VIRT_CLINT + 0x4000;
+pub const MSIP: usize = VIRT_CLINT;
 
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
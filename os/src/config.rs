@@ -1,11 +1,54 @@
 pub use crate::board::*;
 
+/// The initial size of a user stack, which grows on demand up to its RLIMIT_STACK.
 pub const USER_STACK_SIZE: usize = 0x2000; // 8KB
+/// The hard limit of RLIMIT_STACK. Each thread reserves this much address space for its user stack.
+pub const USER_STACK_LIMIT: usize = 0x800000; // 8MB
 pub const KERNEL_STACK_SIZE: usize = 0x2000; // 8KB
+/// The harts with ids from MAX_HARTS on are parked at boot. entry.asm reserves the stacks of MAX_HARTS harts.
+pub const MAX_HARTS: usize = 8;
+/// The size of the boot stack of each hart.
+pub const BOOT_STACK_SIZE: usize = 0x10000; // 64KB
 pub const KERNEL_HEAP_SIZE: usize = 0x200000; // 2MB
+/// When the kernel heap runs out, it grows by at least KERNEL_HEAP_GROWTH bytes of frames.
+pub const KERNEL_HEAP_GROWTH: usize = 0x40000; // 256KB
+/// The frames that user pages cannot use, which are left for the page tables, kernel stacks and trap contexts.
+pub const KERNEL_RESERVED_FRAMES: usize = 0x200; // 2MB
 
 pub const PAGE_SIZE: usize = 0x1000; // 4KB
 pub const PAGE_SIZE_BITS: usize = 12;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
-pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
\ No newline at end of file
+pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+
+/// The user stacks of the threads are placed from USER_STACK_BASE upwards. The user heap grows up to it.
+pub const USER_STACK_BASE: usize = 0x8_0000_0000;
+/// A position-independent executable is loaded at PIE_BASE.
+pub const PIE_BASE: usize = 0x4000_0000;
+/// The mappings created by mmap are placed in [MMAP_BASE, MMAP_END).
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+/// The end of the user address space, 1GB below the end of the lower half of the Sv39 address space, so that it fits
+/// in the user window of the kernel. User mappings should be below it.
+pub const MMAP_END: usize = 0x3f_c000_0000;
+/// The kernel accesses the user memory at va at USER_WINDOW_BASE + va, the start of the upper half of the Sv39
+/// address space.
+pub const USER_WINDOW_BASE: usize = 0xffff_ffc0_0000_0000;
+/// With ASLR, the user stacks, the user heap, the mmap area and PIEs are moved up by random numbers of pages below
+/// these.
+pub const ASLR_STACK_RANGE: usize = 0x4_0000_0000; // 16GB
+pub const ASLR_HEAP_RANGE: usize = 0x4000_0000; // 1GB
+pub const ASLR_MMAP_RANGE: usize = 0x10_0000_0000; // 64GB
+pub const ASLR_PIE_RANGE: usize = 0x1_0000_0000; // 4GB
+/// With ASLR, the user stacks of the first ASLR_STACK_SLOTS threads are placed in that many slots in random order.
+pub const ASLR_STACK_SLOTS: usize = 1024;
+/// A process has at most MAX_THREADS threads, so that their user stacks stay below MMAP_BASE wherever ASLR puts them.
+pub const MAX_THREADS: usize = ASLR_STACK_SLOTS;
+/// The swap area follows the 16MB file system on the block device.
+pub const SWAP_START_BLOCK: usize = 0x8000;
+pub const SWAP_SIZE: usize = 0x4000000; // 64MB
+/// The most pages the shared memories may have in total.
+pub const SHM_MAX_PAGES: usize = 0x800; // 8MB
+/// The scheduling policy, which is fifo, stride or mlfq, is read at boot from the fw_cfg file SCHED_POLICY_FILE, or is
+/// DEFAULT_SCHED_POLICY if there is no such file.
+pub const SCHED_POLICY_FILE: &str = "opt/sched";
+pub const DEFAULT_SCHED_POLICY: &str = "stride";
\ No newline at end of file
@@ -1,10 +1,7 @@
 use crate::{
     config::VIRT_VIRTIO,
-    mm::{
-        frame_alloc, frame_dealloc, kernel_satp, FrameTracker, PageTableView, PhysAddr,
-        PhysPageNum, StepByOne,
-    },
-    sync::UPSafeCell,
+    mm::{frame_alloc_contiguous, kernel_satp, FrameRun, PageTableView, PhysAddr, PhysPageNum},
+    sync::SpinLock,
 };
 use alloc::vec::Vec;
 use easy_fs::BlockDevice;
@@ -12,32 +9,32 @@ use lazy_static::lazy_static;
 use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = UPSafeCell::new(Vec::new());
+    static ref QUEUE_FRAMES: SpinLock<Vec<FrameRun>> = SpinLock::new(Vec::new());
 }
 
 struct VirtioHal;
 
 impl Hal for VirtioHal {
     fn dma_alloc(pages: usize) -> virtio_drivers::PhysAddr {
-        let mut ppn_base = PhysPageNum(0);
-        for i in 0..pages {
-            let frame = frame_alloc().unwrap();
-            if i == 0 {
-                ppn_base = frame.ppn;
-            }
-            assert_eq!(frame.ppn.0, ppn_base.0 + i);
-            QUEUE_FRAMES.exclusive_access().push(frame);
-        }
+        let frame_run = frame_alloc_contiguous(pages).unwrap();
+        let ppn_base = frame_run.ppn;
+        QUEUE_FRAMES.exclusive_access().push(frame_run);
         PhysAddr::from(ppn_base).into()
     }
 
-    fn dma_dealloc(paddr: virtio_drivers::PhysAddr, pages: usize) -> i32 {
-        let mut ppn_base: PhysPageNum = PhysAddr::from(paddr).into();
-        for _ in 0..pages {
-            frame_dealloc(ppn_base);
-            ppn_base.step();
+    fn dma_dealloc(paddr: virtio_drivers::PhysAddr, _pages: usize) -> i32 {
+        let ppn_base: PhysPageNum = PhysAddr::from(paddr).into();
+        let mut queue_frames = QUEUE_FRAMES.exclusive_access();
+        match queue_frames
+            .iter()
+            .position(|frame_run| frame_run.ppn == ppn_base)
+        {
+            Some(id) => {
+                queue_frames.remove(id);
+                0
+            }
+            None => -1,
         }
-        0
     }
 
     fn phys_to_virt(paddr: virtio_drivers::PhysAddr) -> virtio_drivers::VirtAddr {
@@ -52,11 +49,11 @@ impl Hal for VirtioHal {
     }
 }
 
-pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static, VirtioHal>>);
+pub struct VirtIOBlock(SpinLock<VirtIOBlk<'static, VirtioHal>>);
 
 impl VirtIOBlock {
     pub fn new() -> Self {
-        Self(UPSafeCell::new(
+        Self(SpinLock::new(
             VirtIOBlk::new(unsafe { &mut *(VIRT_VIRTIO as *mut VirtIOHeader) }).unwrap(),
         ))
     }
@@ -0,0 +1,57 @@
+//! The fw_cfg device of QEMU, through which files are passed to the kernel at boot, e.g. the scheduling policy by
+//! `-fw_cfg name=opt/sched,string=mlfq`.
+
+use crate::board::VIRT_FW_CFG;
+use core::cmp::min;
+
+/// The data register, which reads the selected item a byte at a time.
+const DATA: usize = VIRT_FW_CFG;
+/// The selector register, which is big-endian.
+const SELECTOR: usize = VIRT_FW_CFG + 8;
+const SIGNATURE_KEY: u16 = 0x00;
+const FILE_DIR_KEY: u16 = 0x19;
+/// An entry of the file directory has the size and key of the file, 2 reserved bytes and the name.
+const FILE_ENTRY_SIZE: usize = 64;
+const FILE_NAME_OFFSET: usize = 8;
+
+fn select(key: u16) {
+    unsafe {
+        (SELECTOR as *mut u16).write_volatile(key.to_be());
+    }
+}
+
+fn read_data(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        *byte = unsafe { (DATA as *const u8).read_volatile() };
+    }
+}
+
+/// Read the file with name to buf. Return the size of the file, or None if there is no such file.
+pub fn read_file(name: &str, buf: &mut [u8]) -> Option<usize> {
+    let mut signature = [0u8; 4];
+    select(SIGNATURE_KEY);
+    read_data(&mut signature);
+    if &signature != b"QEMU" {
+        return None;
+    }
+    let mut count = [0u8; 4];
+    select(FILE_DIR_KEY);
+    read_data(&mut count);
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut entry = [0u8; FILE_ENTRY_SIZE];
+        read_data(&mut entry);
+        let entry_name = &entry[FILE_NAME_OFFSET..];
+        let len = entry_name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(entry_name.len());
+        if &entry_name[..len] == name.as_bytes() {
+            let size = u32::from_be_bytes(entry[0..4].try_into().unwrap()) as usize;
+            select(u16::from_be_bytes(entry[4..6].try_into().unwrap()));
+            let len = min(size, buf.len());
+            read_data(&mut buf[..len]);
+            return Some(size);
+        }
+    }
+    None
+}
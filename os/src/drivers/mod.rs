@@ -1,3 +1,4 @@
 pub mod block;
+pub mod fw_cfg;
 
-pub use block::BLOCK_DEVICE;
\ No newline at end of file
+pub use block::BLOCK_DEVICE;
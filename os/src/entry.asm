@@ -1,12 +1,32 @@
-    .section .text.entry
-    .globl _start
-_start:
-    la sp, boot_stack_top
-    call rust_boot
-
-    .section .bss.stack
-    .globl boot_stack_bottom
-    .globl boot_stack_top
-boot_stack_bottom:
-    .space 4096 * 16
-boot_stack_top:
\ No newline at end of file
+    .section .text.entry
+    .globl _start
+_start:
+    # Every hart starts here in M-mode, and keeps its id in tp from now on.
+    csrr tp, mhartid
+    # The harts beyond MAX_HARTS (8) are parked.
+    li t0, 8
+    bgeu tp, t0, 2f
+    # Each hart runs on its own boot stack, below those of the harts with smaller ids.
+    la sp, boot_stack_top
+    slli t0, tp, 16
+    sub sp, sp, t0
+    call rust_boot
+2:
+    wfi
+    j 2b
+
+    .section .bss.stack
+    .globl boot_stack_bottom
+    .globl boot_stack_top
+    # The boot stacks of MAX_HARTS harts, each of 64KB. The lowest page of each boot stack is its guard page, which is
+    # not mapped in the kernel address space.
+    .align 12
+boot_stack_bottom:
+    .space 4096 * 16 * 8
+boot_stack_top:
+
+    # The stacks of trap_from_kernel, which may be entered because a kernel stack overflows. Each hart has 16KB,
+    # below those of the harts with smaller ids.
+    .globl kernel_trap_stack_top
+    .space 4096 * 4 * 8
+kernel_trap_stack_top:
\ No newline at end of file
@@ -1,13 +1,20 @@
-use crate::{drivers::BLOCK_DEVICE, fs::File, mm::UserBuffer, println, sync::UPSafeCell};
-use alloc::{sync::Arc, vec::Vec};
+use crate::{
+    drivers::BLOCK_DEVICE,
+    fs::File,
+    mm::{find_page_cache, page_cache, PageCache, UserBuffer},
+    println,
+    sync::SpinLock,
+};
+use alloc::{sync::Arc, vec};
 use bitflags::bitflags;
+use core::cmp::min;
 use easy_fs::{EasyFileSystem, Inode};
 use lazy_static::lazy_static;
 
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<OSInodeInner>,
+    inner: SpinLock<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
@@ -20,26 +27,39 @@ impl OSInode {
         Self {
             readable: readable,
             writable: writable,
-            inner: UPSafeCell::new(OSInodeInner {
+            inner: SpinLock::new(OSInodeInner {
                 offset: 0,
                 inode: inode,
             }),
         }
     }
+}
 
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
-        let mut buffer = [0u8; 512];
-        let mut v: Vec<u8> = Vec::new();
-        loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
-            if len == 0 {
-                break;
-            }
-            inner.offset += len;
-            v.extend_from_slice(&buffer[..len]);
-        }
-        v
+/// The file is copied from and to the user memory through a buffer of at most this many bytes, since the user
+/// memory is only accessed by the copies that recover from faults, e.g. when it is unmapped by another thread.
+const BOUNCE_BUFFER_SIZE: usize = 0x4000;
+
+/// Like Inode::read_at, but the page cache of the file is read if it exists, since its pages may be dirty.
+fn read_at(inode: &Inode, offset: usize, buf: &mut [u8]) -> usize {
+    match find_page_cache(inode) {
+        Some(page_cache) => page_cache.read_at(offset, buf),
+        None => inode.read_at(offset, buf),
+    }
+}
+
+/// Like Inode::write_at, but the page cache of the file is updated if it exists.
+fn write_at(inode: &Inode, offset: usize, buf: &[u8]) -> usize {
+    match find_page_cache(inode) {
+        Some(page_cache) => page_cache.write_at(offset, buf),
+        None => inode.write_at(offset, buf),
+    }
+}
+
+/// Truncate the file to 0 bytes.
+fn clear(inode: &Inode) {
+    inode.clear();
+    if let Some(page_cache) = find_page_cache(inode) {
+        page_cache.clear();
     }
 }
 
@@ -54,28 +74,42 @@ impl File for OSInode {
 
     fn read(&self, mut buf: UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
-        let mut total_read_size: usize = 0;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
+        let mut bytes = vec![0u8; min(buf.len(), BOUNCE_BUFFER_SIZE)];
+        let mut read_size = 0;
+        while read_size < buf.len() {
+            let len = min(bytes.len(), buf.len() - read_size);
+            let read = read_at(&inner.inode, inner.offset, &mut bytes[..len]);
+            // The bytes not copied to the user memory are left for the next read.
+            let written = buf.write(read_size, &bytes[..read]);
+            inner.offset += written;
+            read_size += written;
+            if read < len || written < read {
                 break;
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
         }
-        total_read_size
+        read_size
     }
 
     fn write(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
-        let mut total_write_size: usize = 0;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
+        let mut bytes = vec![0u8; min(buf.len(), BOUNCE_BUFFER_SIZE)];
+        let mut write_size = 0;
+        while write_size < buf.len() {
+            let len = min(bytes.len(), buf.len() - write_size);
+            let read = buf.read(write_size, &mut bytes[..len]);
+            let written = write_at(&inner.inode, inner.offset, &bytes[..read]);
+            assert_eq!(written, read);
+            inner.offset += written;
+            write_size += written;
+            if read < len {
+                break;
+            }
         }
-        total_write_size
+        write_size
+    }
+
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        Some(page_cache(&self.inner.exclusive_access().inode))
     }
 }
 
@@ -121,7 +155,7 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
     if flags.contains(OpenFlags::CREATE) {
         if let Some(inode) = ROOT_INODE.find(name) {
-            inode.clear();
+            clear(&inode);
             Some(Arc::new(OSInode::new(readable, writable, inode)))
         } else {
             ROOT_INODE
@@ -131,7 +165,7 @@ pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     } else {
         ROOT_INODE.find(name).map(|inode| {
             if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
+                clear(&inode);
             }
             Arc::new(OSInode::new(readable, writable, inode))
         })
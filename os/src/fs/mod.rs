@@ -2,7 +2,8 @@ mod inode;
 mod pipe;
 mod stdio;
 
-use crate::mm::UserBuffer;
+use crate::mm::{PageCache, UserBuffer};
+use alloc::sync::Arc;
 
 pub use inode::{list_apps, open_file, OSInode, OpenFlags};
 pub use pipe::make_pipe;
@@ -13,4 +14,8 @@ pub trait File: Send + Sync {
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Return the page cache of the file if it can be mapped by mmap.
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        None
+    }
 }
@@ -1,5 +1,14 @@
-use crate::{fs::File, mm::UserBuffer, sync::UPSafeCell, task::suspend_current_and_run_next};
-use alloc::sync::{Arc, Weak};
+use crate::{
+    fs::File,
+    mm::{CachedObject, ObjectCache, UserBuffer},
+    sync::SpinLock,
+    task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock},
+};
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+};
+use core::{cmp::min, ops::Deref};
 
 const RING_BUFFER_SIZE: usize = 32;
 
@@ -16,6 +25,10 @@ pub struct PipeRingBuffer {
     tail: usize,
     status: RingBufferStatus,
     write_end: Option<Weak<Pipe>>,
+    /// The tasks blocked until there are bytes to read, or the write end is closed.
+    read_waiters: VecDeque<Arc<TaskControlBlock>>,
+    /// The tasks blocked until there is room to write.
+    write_waiters: VecDeque<Arc<TaskControlBlock>>,
 }
 
 impl PipeRingBuffer {
@@ -26,6 +39,30 @@ impl PipeRingBuffer {
             tail: 0,
             status: RingBufferStatus::Empty,
             write_end: None,
+            read_waiters: VecDeque::new(),
+            write_waiters: VecDeque::new(),
+        }
+    }
+
+    /// Restore the state made by new, so that the ring buffer can be returned to RING_BUFFER_CACHE.
+    fn clear(&mut self) {
+        self.head = 0;
+        self.tail = 0;
+        self.status = RingBufferStatus::Empty;
+        self.write_end = None;
+        self.read_waiters.clear();
+        self.write_waiters.clear();
+    }
+
+    fn wake_readers(&mut self) {
+        while let Some(task) = self.read_waiters.pop_front() {
+            wakeup_task(task);
+        }
+    }
+
+    fn wake_writers(&mut self) {
+        while let Some(task) = self.write_waiters.pop_front() {
+            wakeup_task(task);
         }
     }
 
@@ -33,16 +70,25 @@ impl PipeRingBuffer {
         self.write_end = Some(Arc::downgrade(write_end));
     }
 
-    /// The ring buffer must not be empty before read_byte is called.
-    pub fn read_byte(&mut self) -> u8 {
-        let c = self.arr[self.head];
-        self.head = (self.head + 1) % RING_BUFFER_SIZE;
+    /// Copy the first dst.len() bytes of the ring buffer to dst, without removing them. The ring buffer must have
+    /// that many bytes.
+    pub fn peek(&self, dst: &mut [u8]) {
+        for (i, byte) in dst.iter_mut().enumerate() {
+            *byte = self.arr[(self.head + i) % RING_BUFFER_SIZE];
+        }
+    }
+
+    /// Remove the first len bytes of the ring buffer, which must have that many bytes.
+    pub fn consume(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+        self.head = (self.head + len) % RING_BUFFER_SIZE;
         self.status = if self.head == self.tail {
             RingBufferStatus::Empty
         } else {
             RingBufferStatus::Normal
         };
-        c
     }
 
     pub fn available_read(&self) -> usize {
@@ -77,14 +123,41 @@ impl PipeRingBuffer {
     }
 }
 
+/// The ring buffers of the pipes, which are constructed empty when their slabs are created.
+static RING_BUFFER_CACHE: ObjectCache<SpinLock<PipeRingBuffer>> =
+    ObjectCache::new("pipe_ring_buffer", construct_ring_buffer);
+
+fn construct_ring_buffer(object: *mut u8) {
+    unsafe {
+        (object as *mut SpinLock<PipeRingBuffer>).write(SpinLock::new(PipeRingBuffer::new()));
+    }
+}
+
+/// A ring buffer of RING_BUFFER_CACHE shared by the ends of a pipe, which is emptied before it is returned.
+pub struct SharedRingBuffer(CachedObject<SpinLock<PipeRingBuffer>>);
+
+impl Deref for SharedRingBuffer {
+    type Target = SpinLock<PipeRingBuffer>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for SharedRingBuffer {
+    fn drop(&mut self) {
+        self.0.exclusive_access().clear();
+    }
+}
+
 pub struct Pipe {
     readable: bool,
     writable: bool,
-    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    buffer: Arc<SharedRingBuffer>,
 }
 
 impl Pipe {
-    pub fn read_end_of_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn read_end_of_buffer(buffer: Arc<SharedRingBuffer>) -> Self {
         Self {
             readable: true,
             writable: false,
@@ -92,7 +165,7 @@ impl Pipe {
         }
     }
 
-    pub fn write_end_of_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    pub fn write_end_of_buffer(buffer: Arc<SharedRingBuffer>) -> Self {
         Self {
             readable: false,
             writable: true,
@@ -101,6 +174,15 @@ impl Pipe {
     }
 }
 
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // The readers blocked on the pipe see its end once the write end is closed.
+        if self.writable {
+            self.buffer.exclusive_access().wake_readers();
+        }
+    }
+}
+
 impl File for Pipe {
     fn readable(&self) -> bool {
         self.readable
@@ -110,10 +192,9 @@ impl File for Pipe {
         self.writable
     }
 
-    fn read(&self, buf: UserBuffer) -> usize {
+    fn read(&self, mut buf: UserBuffer) -> usize {
         assert!(self.readable());
         let buf_len = buf.len();
-        let mut buf_iter = buf.into_iter();
         let mut already_read: usize = 0;
         loop {
             let mut ring_buffer = self.buffer.exclusive_access();
@@ -122,17 +203,21 @@ impl File for Pipe {
                 if ring_buffer.all_write_ends_closed() {
                     return already_read;
                 }
+                ring_buffer.read_waiters.push_back(current_task());
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                block_current_and_run_next();
                 continue;
             }
-            for _ in 0..available_read {
-                let byte_ref = buf_iter.next().unwrap();
-                unsafe { *byte_ref = ring_buffer.read_byte() }
-                already_read += 1;
-                if already_read == buf_len {
-                    return already_read;
-                }
+            let mut bytes = [0u8; RING_BUFFER_SIZE];
+            let len = min(available_read, buf_len - already_read);
+            ring_buffer.peek(&mut bytes[..len]);
+            // The bytes not copied to the user memory are left in the pipe.
+            let written = buf.write(already_read, &bytes[..len]);
+            ring_buffer.consume(written);
+            ring_buffer.wake_writers();
+            already_read += written;
+            if already_read == buf_len || written < len {
+                return already_read;
             }
         }
     }
@@ -140,23 +225,26 @@ impl File for Pipe {
     fn write(&self, buf: UserBuffer) -> usize {
         assert!(self.writable());
         let buf_len = buf.len();
-        let mut buf_iter = buf.into_iter();
         let mut already_write: usize = 0;
         loop {
             let mut ring_buffer = self.buffer.exclusive_access();
             let available_write = ring_buffer.available_write();
             if available_write == 0 {
+                ring_buffer.write_waiters.push_back(current_task());
                 drop(ring_buffer);
-                suspend_current_and_run_next();
+                block_current_and_run_next();
                 continue;
             }
-            for _ in 0..available_write {
-                let byte_ref = buf_iter.next().unwrap();
-                ring_buffer.write_byte(unsafe { *byte_ref });
-                already_write += 1;
-                if already_write == buf_len {
-                    return already_write;
-                }
+            let mut bytes = [0u8; RING_BUFFER_SIZE];
+            let len = min(available_write, buf_len - already_write);
+            let read = buf.read(already_write, &mut bytes[..len]);
+            for byte in bytes[..read].iter() {
+                ring_buffer.write_byte(*byte);
+            }
+            ring_buffer.wake_readers();
+            already_write += read;
+            if already_write == buf_len || read < len {
+                return already_write;
             }
         }
     }
@@ -164,7 +252,7 @@ impl File for Pipe {
 
 /// Return (read_end, write_end).
 pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(UPSafeCell::new(PipeRingBuffer::new()));
+    let buffer = Arc::new(SharedRingBuffer(RING_BUFFER_CACHE.alloc().unwrap()));
     let read_end = Arc::new(Pipe::read_end_of_buffer(buffer.clone()));
     let write_end = Arc::new(Pipe::write_end_of_buffer(buffer.clone()));
     buffer.exclusive_access().set_write_end(&write_end);
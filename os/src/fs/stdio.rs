@@ -1,6 +1,15 @@
 use crate::{
-    fs::File, mm::UserBuffer, print, sbi::console_getchar, task::suspend_current_and_run_next,
+    fs::File,
+    mm::UserBuffer,
+    print,
+    sbi::console_getchar,
+    task::{block_current_and_run_next, current_task},
+    timer::{add_timer, get_time_ms},
 };
+use alloc::vec;
+
+/// How often a task reading stdin polls the console, which raises no interrupts.
+const STDIN_POLL_MS: usize = 10;
 
 pub struct Stdin;
 
@@ -19,14 +28,15 @@ impl File for Stdin {
         loop {
             c = console_getchar();
             if c == 0 {
-                suspend_current_and_run_next();
+                // The task sleeps until the next poll, and is woken like any blocked task.
+                add_timer(get_time_ms() + STDIN_POLL_MS, current_task());
+                block_current_and_run_next();
                 continue;
             } else {
                 break;
             }
         }
-        unsafe { buf.buffers[0].as_mut_ptr().write_volatile(c) };
-        1
+        buf.write(0, &[c])
     }
 
     fn write(&self, _buf: UserBuffer) -> usize {
@@ -50,9 +60,9 @@ impl File for Stdout {
     }
 
     fn write(&self, buf: UserBuffer) -> usize {
-        for buffer in buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
-        }
-        buf.len()
+        let mut bytes = vec![0u8; buf.len()];
+        let len = buf.read(0, &mut bytes);
+        print!("{}", core::str::from_utf8(&bytes[..len]).unwrap());
+        len
     }
 }
@@ -0,0 +1,29 @@
+//! The harts, i.e. the hardware threads. Each of them boots on its own stack and runs the scheduler.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The number of harts that have started running tasks.
+static STARTED_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The id of the current hart. Each hart keeps it in tp while it runs the kernel, and the traps from user mode load
+/// it from the trap context.
+pub fn hart_id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+/// Count the current hart as started, right before it runs tasks.
+pub fn start_hart() {
+    STARTED_HARTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of harts running tasks.
+pub fn started_harts() -> usize {
+    STARTED_HARTS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,188 @@
+//! Inter-processor interrupts. A hart interrupts another by writing its msip in the CLINT, which raises a machine
+//! software interrupt. The M-mode trap passes it on as a supervisor software interrupt, like a timer tick, and the
+//! interrupted hart then takes the messages in its mailbox.
+//!
+//! The kernel runs with interrupts disabled, so a hart takes its messages when it runs in user mode. A TLB flush
+//! must not wait that long, since the asking hart may hold a lock the other one is spinning on, so the harts also
+//! serve the flushes asked of them while spinning. The calls may take locks, so they are only waited for by a hart
+//! holding none.
+
+use crate::{
+    config::{MAX_HARTS, MSIP},
+    hart::hart_id,
+    sync::SpinLock,
+};
+use alloc::collections::VecDeque;
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+};
+
+/// The messages sent to a hart.
+struct Mailbox {
+    /// Whether the hart should switch to another task.
+    reschedule: AtomicBool,
+    /// The functions to call on the hart.
+    calls: SpinLock<VecDeque<fn()>>,
+    /// The number of calls sent to the hart, which is counted while calls is locked, and the number it has made.
+    calls_sent: AtomicUsize,
+    calls_served: AtomicUsize,
+    /// The number of TLB flushes asked of the hart, and the number it has served. They are counters instead of
+    /// queued messages, so that they can be served without taking a lock.
+    flushes_asked: AtomicUsize,
+    flushes_served: AtomicUsize,
+    /// The satp of the address space of the task on the hart, or 0 if the hart runs no task.
+    satp: AtomicUsize,
+}
+
+impl Mailbox {
+    const fn new() -> Self {
+        Self {
+            reschedule: AtomicBool::new(false),
+            calls: SpinLock::new(VecDeque::new()),
+            calls_sent: AtomicUsize::new(0),
+            calls_served: AtomicUsize::new(0),
+            flushes_asked: AtomicUsize::new(0),
+            flushes_served: AtomicUsize::new(0),
+            satp: AtomicUsize::new(0),
+        }
+    }
+}
+
+static MAILBOXES: [Mailbox; MAX_HARTS] = [const { Mailbox::new() }; MAX_HARTS];
+
+fn send_ipi(hart: usize) {
+    unsafe {
+        ((MSIP + hart * size_of::<u32>()) as *mut u32).write_volatile(1);
+    }
+}
+
+/// Make hart switch to another task at its next trap from user mode.
+pub fn send_reschedule(hart: usize) {
+    MAILBOXES[hart].reschedule.store(true, Ordering::Release);
+    send_ipi(hart);
+}
+
+/// Make every other hart running a task of the address space with satp switch to another task.
+pub fn send_reschedule_to_space(satp: usize) {
+    for hart in other_harts_on(satp) {
+        send_reschedule(hart);
+    }
+}
+
+/// Call function on hart at its next trap from user mode. Return the number of the call, which has been made once
+/// the hart has served that many.
+pub fn send_call(hart: usize, function: fn()) -> usize {
+    let mailbox = &MAILBOXES[hart];
+    let mut calls = mailbox.calls.exclusive_access();
+    calls.push_back(function);
+    let sent = mailbox.calls_sent.fetch_add(1, Ordering::Relaxed) + 1;
+    drop(calls);
+    send_ipi(hart);
+    sent
+}
+
+/// Call function on every other hart running a task of the address space with satp, and wait until they have made
+/// the call or left the address space. The current hart must hold no lock.
+pub fn call_on_space(satp: usize, function: fn()) {
+    fence(Ordering::SeqCst);
+    for hart in other_harts_on(satp) {
+        let mailbox = &MAILBOXES[hart];
+        let sent = send_call(hart, function);
+        while mailbox.calls_served.load(Ordering::Acquire) < sent
+            && mailbox.satp.load(Ordering::SeqCst) == satp
+        {
+            // The hart may be waiting for a call on this one at the same time.
+            serve_calls();
+            serve_flushes();
+            spin_loop();
+        }
+    }
+}
+
+/// Record that the current hart runs a task of the address space with satp, or no task if satp is 0. The TLB must be
+/// flushed after that, so that the changes made to the page table before it are seen.
+pub fn set_current_satp(satp: usize) {
+    MAILBOXES[hart_id()].satp.store(satp, Ordering::SeqCst);
+}
+
+fn other_harts_on(satp: usize) -> impl Iterator<Item = usize> {
+    let current = hart_id();
+    (0..MAX_HARTS)
+        .filter(move |&hart| hart != current && MAILBOXES[hart].satp.load(Ordering::SeqCst) == satp)
+}
+
+/// Flush the TLBs of the other harts running a task of the address space with satp, after its page table has been
+/// changed, and wait until they are done. The current hart flushes its own TLB when it returns to user mode, and
+/// the user windows are flushed when they are opened.
+pub fn shootdown(satp: usize) {
+    // The page table is changed before the harts are looked for, and a hart records its address space before it
+    // flushes its TLB, so either the hart is found, or it flushes after the change.
+    fence(Ordering::SeqCst);
+    for hart in other_harts_on(satp) {
+        let mailbox = &MAILBOXES[hart];
+        let asked = mailbox.flushes_asked.fetch_add(1, Ordering::SeqCst) + 1;
+        send_ipi(hart);
+        // The hart may leave the address space instead, after which it flushes its TLB before entering one again.
+        while mailbox.flushes_served.load(Ordering::Acquire) < asked
+            && mailbox.satp.load(Ordering::SeqCst) == satp
+        {
+            // The hart may be flushing the TLB of this one at the same time.
+            serve_flushes();
+            spin_loop();
+        }
+    }
+}
+
+/// Flush the TLB of the current hart if another hart has asked for it. This takes no lock, so it is called while
+/// spinning.
+pub fn serve_flushes() {
+    let mailbox = &MAILBOXES[hart_id()];
+    let asked = mailbox.flushes_asked.load(Ordering::Acquire);
+    if mailbox.flushes_served.load(Ordering::Relaxed) != asked {
+        unsafe {
+            asm!("sfence.vma");
+        }
+        mailbox.flushes_served.store(asked, Ordering::Release);
+    }
+}
+
+/// Make the calls sent to the current hart. The current hart must hold no lock.
+pub fn serve_calls() {
+    let mailbox = &MAILBOXES[hart_id()];
+    loop {
+        // The lock is not held by the call, which may send a call itself.
+        let function = mailbox.calls.exclusive_access().pop_front();
+        match function {
+            Some(function) => {
+                function();
+                mailbox.calls_served.fetch_add(1, Ordering::Release);
+            }
+            None => break,
+        }
+    }
+}
+
+/// Take the messages sent to the current hart. Return whether it should switch to another task.
+pub fn handle_ipi() -> bool {
+    serve_flushes();
+    serve_calls();
+    MAILBOXES[hart_id()]
+        .reschedule
+        .swap(false, Ordering::Acquire)
+}
+
+/// Make the instructions written to memory visible to the instruction fetches of the current hart.
+pub fn sync_instruction_cache() {
+    unsafe {
+        asm!("fence.i");
+    }
+}
+
+/// Drop the reschedule sent to the current hart, e.g. when it is about to switch to a task anyway.
+pub fn clear_reschedule() {
+    MAILBOXES[hart_id()]
+        .reschedule
+        .store(false, Ordering::Relaxed);
+}
@@ -4,12 +4,19 @@
 
 extern crate alloc;
 
-use core::arch::{asm, global_asm};
+use core::{
+    arch::{asm, global_asm},
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use riscv::register::{mepc, mstatus, pmpaddr0, pmpcfg0, satp};
 
 pub mod fs;
+pub mod hart;
+pub mod ipi;
 pub mod lang_items;
 pub mod mm;
+pub mod random;
 pub mod sbi;
 pub mod sync;
 pub mod syscall;
@@ -36,6 +43,15 @@ fn clear_bss() {
     }
 }
 
+/// The hart that initializes the kernel, while the others wait for it.
+const BOOT_HART: usize = 0;
+
+/// Set by the boot hart once the kernel is initialized. It is in .data, since .bss is cleared by the boot hart
+/// while the others may be waiting.
+#[link_section = ".data"]
+static KERNEL_READY: AtomicBool = AtomicBool::new(false);
+
+/// Entered by every hart in M-mode, on its own boot stack.
 #[unsafe(no_mangle)]
 unsafe fn rust_boot() -> ! {
     // Switch to S-mode after mret.
@@ -68,11 +84,31 @@ fn rust_init() {
     trap::init();
 }
 
+/// Initialize the parts of the kernel that are per hart, on a hart other than the boot hart.
+fn rust_init_hart() {
+    mm::activate_kernel_space();
+    trap::init();
+}
+
 #[unsafe(no_mangle)]
 fn rust_main() -> ! {
+    if hart::hart_id() != BOOT_HART {
+        while !KERNEL_READY.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        rust_init_hart();
+        println!("[kernel] Hart {} started", hart::hart_id());
+        hart::start_hart();
+        task::run_tasks();
+        unreachable!();
+    }
     rust_init();
     println!("[kernel] Hello, world!");
+    println!("[kernel] Scheduling policy: {}", task::sched_policy_name());
     fs::list_apps();
+    task::add_initproc();
+    KERNEL_READY.store(true, Ordering::Release);
+    hart::start_hart();
     task::run_tasks();
     unreachable!();
 }
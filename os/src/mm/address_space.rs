@@ -1,16 +1,30 @@
 use super::{
     address::VPNRange,
-    frame_alloc,
-    page_table::{PageTable, PageTableView},
+    frame_alloc, frame_alloc_contiguous,
+    page::{Page, PageRef, PinnedPages},
+    page_cache::PageCache,
+    page_table::{PageSize, PageTable, PageTableView},
+    shm::{shm_attachment, shm_get, shm_page_count, ShmAttachment},
+    user_access::BadAddress,
+    user_frame_alloc, user_frame_alloc_contiguous,
+    user_window::KernelView,
     FrameTracker, PageTableEntry, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum,
 };
 use crate::{
-    config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE},
+    config::{
+        ASLR_HEAP_RANGE, ASLR_MMAP_RANGE, ASLR_PIE_RANGE, ASLR_STACK_RANGE, ASLR_STACK_SLOTS,
+        BOOT_STACK_SIZE, MAX_HARTS, MEMORY_END, MMAP_BASE, MMAP_END, MMIO, PAGE_SIZE, PIE_BASE,
+        TRAMPOLINE, USER_STACK_BASE, USER_STACK_LIMIT,
+    },
     println,
+    random::random_below,
 };
-use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec, vec::Vec};
 use bitflags::bitflags;
-use core::{arch::asm, cmp::min};
+use core::{
+    arch::asm,
+    cmp::{max, min},
+};
 use riscv::register::satp;
 
 unsafe extern "C" {
@@ -21,6 +35,7 @@ unsafe extern "C" {
     unsafe fn sdata();
     unsafe fn edata();
     unsafe fn sbss_with_stack();
+    unsafe fn boot_stack_bottom();
     unsafe fn ebss();
     unsafe fn ekernel();
     unsafe fn strampoline();
@@ -31,6 +46,13 @@ unsafe extern "C" {
 pub enum MapType {
     Identical,
     Framed,
+    /// Like MapType::Framed, but a page is only backed by a frame when it is first accessed.
+    Lazy,
+    /// The frames belong to a shared memory, and are never copied on write.
+    Shared,
+    /// Like MapType::Shared, but the frames belong to the page cache of a file, and are mapped on fault.
+    /// A page is mapped read-only until it is written, so that only the dirty pages are written back.
+    File,
 }
 
 bitflags! {
@@ -43,11 +65,64 @@ bitflags! {
     }
 }
 
+fn is_mapped(page_table: &PageTable, vpn: VirtPageNum) -> bool {
+    page_table
+        .view()
+        .translate(vpn)
+        .is_some_and(|pte| pte.is_valid())
+}
+
+/// The part of a file mapped by a segment. The bytes [offset, offset + len) of the file are placed at virtual
+/// address start_va.
+#[derive(Clone)]
+pub struct SegmentData {
+    file: Arc<PageCache>,
+    offset: usize,
+    len: usize,
+    start_va: usize,
+    /// Whether the file is opened for writing, without which a shared mapping of it must not be made writable.
+    writable: bool,
+}
+
+impl SegmentData {
+    /// Copy the part of self that falls into the page with VirtPageNum vpn to the frame ppn.
+    fn load(&self, vpn: VirtPageNum, ppn: PhysPageNum) {
+        let page_start = VirtAddr::from(vpn).0;
+        let start = max(page_start, self.start_va);
+        let end = min(page_start + PAGE_SIZE, self.start_va + self.len);
+        if start >= end {
+            return;
+        }
+        self.file.read_at(
+            self.offset + start - self.start_va,
+            &mut ppn.get_bytes_array()[start - page_start..end - page_start],
+        );
+    }
+
+    /// Return the index of the page of the file mapped at vpn. Used by MapType::File, whose offset and start_va are
+    /// aligned to PAGE_SIZE.
+    fn page_index(&self, vpn: VirtPageNum) -> usize {
+        (self.offset + VirtAddr::from(vpn).0 - self.start_va) / PAGE_SIZE
+    }
+}
+
 pub struct MemorySegment {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// A page shared by several address spaces after fork is copied on the first write to it.
+    data_pages: BTreeMap<VirtPageNum, PageRef>,
     map_type: MapType,
     permission: Permission,
+    /// Only used by MapType::Lazy, whose pages not covered by it are zero-filled, and MapType::File.
+    file_data: Option<SegmentData>,
+    /// Segments created by mmap can be split, merged and removed by mmap/munmap/mprotect.
+    mmap: bool,
+    /// A user stack grows down on faults below it, up to the stack limit of the address space.
+    stack: bool,
+    /// The size of the pages of MapType::Framed and MapType::Lazy, which are huge pages backed by contiguous frames
+    /// if it is not PageSize::Page. MapType::Identical uses the largest pages fitting in self instead.
+    page_size: PageSize,
+    /// Only used by MapType::Shared.
+    shm: Option<ShmAttachment>,
 }
 
 impl MemorySegment {
@@ -59,23 +134,80 @@ impl MemorySegment {
     ) -> Self {
         Self {
             vpn_range: VPNRange::new(start_va.floor(), end_va.ceil()),
-            data_frames: BTreeMap::new(),
+            data_pages: BTreeMap::new(),
             map_type: map_type,
             permission: permission,
+            file_data: None,
+            mmap: false,
+            stack: false,
+            page_size: PageSize::Page,
+            shm: None,
         }
     }
 
     pub fn from_other(other: &Self) -> Self {
         Self {
             vpn_range: VPNRange::new(other.vpn_range.get_start(), other.vpn_range.get_end()),
-            data_frames: BTreeMap::new(),
+            data_pages: BTreeMap::new(),
             map_type: other.map_type,
             permission: other.permission,
+            file_data: other.file_data.clone(),
+            mmap: other.mmap,
+            stack: other.stack,
+            page_size: other.page_size,
+            shm: other.shm.clone(),
         }
     }
 
-    /// Add the page with VirtPageNum vpn to page_table (and self.data_frames if self.map_type == Maptype::Framed).
-    /// The page should belong to self.
+    /// Return a page for the data at vpn in frame. Only the user pages of MapType::Lazy can be swapped out.
+    fn new_page(&self, frame: FrameTracker, vpn: VirtPageNum) -> Arc<Page> {
+        if self.map_type == MapType::Lazy && self.permission.contains(Permission::U) {
+            Page::new_swappable(frame, vpn)
+        } else {
+            Page::new(frame)
+        }
+    }
+
+    /// Return the size of the page of self starting at vpn.
+    fn leaf_size(&self, vpn: VirtPageNum) -> PageSize {
+        if self.map_type != MapType::Identical {
+            return self.page_size;
+        }
+        [PageSize::GigaPage, PageSize::MegaPage]
+            .into_iter()
+            .find(|page_size| {
+                vpn.0 % page_size.page_count() == 0
+                    && vpn.0 + page_size.page_count() <= self.vpn_range.get_end().0
+            })
+            .unwrap_or(PageSize::Page)
+    }
+
+    /// Return the first page of the page of self containing vpn, which is the key of self.data_pages.
+    fn page_vpn(&self, vpn: VirtPageNum) -> VirtPageNum {
+        VirtPageNum(vpn.0 - vpn.0 % self.page_size.page_count())
+    }
+
+    /// Whether self can be split at vpn without splitting a huge page.
+    fn splittable_at(&self, vpn: VirtPageNum) -> bool {
+        vpn.0 % self.page_size.page_count() == 0
+    }
+
+    /// Return a zero-filled page of self.page_size for the data at vpn, or None if there are no free (contiguous)
+    /// frames for it. User pages cannot use the frames reserved for the kernel.
+    fn alloc_page(&self, vpn: VirtPageNum) -> Option<Arc<Page>> {
+        let user = self.permission.contains(Permission::U);
+        match self.page_size {
+            PageSize::Page if user => Some(self.new_page(user_frame_alloc()?, vpn)),
+            PageSize::Page => Some(self.new_page(frame_alloc()?, vpn)),
+            page_size if user => {
+                user_frame_alloc_contiguous(page_size.page_count()).map(Page::new_huge)
+            }
+            page_size => frame_alloc_contiguous(page_size.page_count()).map(Page::new_huge),
+        }
+    }
+
+    /// Add the page with VirtPageNum vpn to page_table (and self.data_pages if self.map_type == Maptype::Framed).
+    /// The page should belong to self. Pages of MapType::Lazy are added in handle_lazy_fault instead.
     fn map_page(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         let ppn: PhysPageNum;
         match self.map_type {
@@ -83,37 +215,332 @@ impl MemorySegment {
                 ppn = PhysPageNum(vpn.0);
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let page = self.alloc_page(vpn).unwrap();
+                ppn = page.ppn().unwrap();
+                self.data_pages
+                    .insert(vpn, PageRef::new(page, page_table.root_ppn()));
+            }
+            MapType::Lazy | MapType::File => return,
+            MapType::Shared => {
+                let index = vpn.0 - self.vpn_range.get_start().0;
+                let page = self.shm.as_ref().unwrap().page(index);
+                ppn = page.ppn().unwrap();
+                self.data_pages
+                    .insert(vpn, PageRef::new(page, page_table.root_ppn()));
             }
         }
-        page_table.map(vpn, ppn, self.permission);
+        page_table.map_huge(vpn, ppn, self.leaf_size(vpn), self.permission);
     }
 
-    /// Delete the page with VirtPageNum vpn from page_table (and self.data_frames if self.map_type == Maptype::Framed).
+    /// Delete the page with VirtPageNum vpn from page_table (and self.data_pages if self.map_type == Maptype::Framed).
     /// The page should belong to self.
     fn unmap_page(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         match self.map_type {
             MapType::Identical => {}
-            MapType::Framed => {
-                self.data_frames.remove(&vpn);
+            MapType::Framed | MapType::Lazy | MapType::Shared | MapType::File => {
+                self.write_back_page(vpn);
+                // The untouched pages of MapType::Lazy have no frame, and the pages without R/W/X or swapped out
+                // have no PTE.
+                if self.data_pages.remove(&vpn).is_none() || !is_mapped(page_table, vpn) {
+                    return;
+                }
             }
         }
         page_table.unmap(vpn);
     }
 
-    /// Add self to page_table (and self.data_frames if self.map_type == Maptype::Framed).
+    /// Update the PTE of the page with VirtPageNum vpn according to self.permission.
+    fn update_pte(&self, page_table: &mut PageTable, vpn: VirtPageNum, page: &Page) {
+        let mapped = is_mapped(page_table, vpn);
+        // A valid PTE without R/W/X points to the next level of the page table.
+        // A swapped-out page is mapped again in handle_swap_fault.
+        let ppn = match page.ppn() {
+            Some(ppn)
+                if self
+                    .permission
+                    .intersects(Permission::R | Permission::W | Permission::X) =>
+            {
+                ppn
+            }
+            _ => {
+                if mapped {
+                    page_table.unmap(vpn);
+                }
+                return;
+            }
+        };
+        let mut permission = self.permission;
+        match self.map_type {
+            // A clean page of a file stays read-only until the next write to it.
+            MapType::File => {
+                if !self
+                    .data_pages
+                    .get(&vpn)
+                    .is_some_and(|page_ref| page_ref.dirty())
+                {
+                    permission.remove(Permission::W);
+                }
+            }
+            MapType::Shared => {}
+            // A page shared copy-on-write stays read-only until the next write to it.
+            _ => {
+                if page.owner_count() > 1 {
+                    permission.remove(Permission::W);
+                }
+            }
+        }
+        if mapped {
+            page_table.remap(vpn, ppn, permission);
+        } else {
+            page_table.map_huge(vpn, ppn, self.leaf_size(vpn), permission);
+        }
+    }
+
+    /// Move the end of self to end_vpn, mapping the pages added and unmapping the pages removed.
+    fn set_end(&mut self, page_table: &mut PageTable, end_vpn: VirtPageNum) {
+        let start_vpn = self.vpn_range.get_start();
+        let old_end_vpn = self.vpn_range.get_end();
+        if end_vpn < old_end_vpn {
+            for vpn in VPNRange::new(end_vpn, old_end_vpn) {
+                self.unmap_page(page_table, vpn);
+            }
+        }
+        self.vpn_range = VPNRange::new(start_vpn, end_vpn);
+        if end_vpn > old_end_vpn {
+            for vpn in VPNRange::new(old_end_vpn, end_vpn) {
+                self.map_page(page_table, vpn);
+            }
+        }
+    }
+
+    /// Whether self is a stack that can grow down to vpn without exceeding limit pages.
+    fn can_grow_to(&self, vpn: VirtPageNum, limit: usize) -> bool {
+        self.stack
+            && vpn < self.vpn_range.get_start()
+            && self.vpn_range.get_end().0 - vpn.0 <= limit
+    }
+
+    /// Move the start of self, a stack, down to start_vpn. The pages added are backed by frames on fault.
+    fn grow_to(&mut self, start_vpn: VirtPageNum) {
+        assert_eq!(self.map_type, MapType::Lazy);
+        self.vpn_range = VPNRange::new(start_vpn, self.vpn_range.get_end());
+    }
+
+    fn set_permission(&mut self, page_table: &mut PageTable, permission: Permission) {
+        self.permission = permission;
+        for (vpn, page_ref) in self.data_pages.iter() {
+            self.update_pte(page_table, *vpn, page_ref.page());
+        }
+    }
+
+    /// Split self at vpn. Return the part starting from vpn.
+    fn split_off(&mut self, vpn: VirtPageNum) -> Self {
+        let mut segment = Self::from_other(self);
+        segment.vpn_range = VPNRange::new(vpn, self.vpn_range.get_end());
+        segment.data_pages = self.data_pages.split_off(&vpn);
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+        segment
+    }
+
+    /// Whether other starts at the end of self and can be appended to self.
+    fn mergeable_with(&self, other: &Self) -> bool {
+        self.mmap
+            && other.mmap
+            && self.vpn_range.get_end() == other.vpn_range.get_start()
+            && self.map_type == other.map_type
+            && self.permission == other.permission
+            && self.page_size == other.page_size
+            && self.file_data.is_none()
+            && other.file_data.is_none()
+    }
+
+    fn append(&mut self, mut other: Self) {
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), other.vpn_range.get_end());
+        self.data_pages.append(&mut other.data_pages);
+    }
+
+    /// Add self to page_table (and self.data_pages if self.map_type == Maptype::Framed).
     pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < self.vpn_range.get_end() {
             self.map_page(page_table, vpn);
+            vpn = VirtPageNum(vpn.0 + self.leaf_size(vpn).page_count());
         }
     }
 
-    /// Delete self from page_table (and self.data_frames if self.map_type == Maptype::Framed).
+    /// Delete self from page_table (and self.data_pages if self.map_type == Maptype::Framed).
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < self.vpn_range.get_end() {
             self.unmap_page(page_table, vpn);
+            vpn = VirtPageNum(vpn.0 + self.leaf_size(vpn).page_count());
+        }
+    }
+
+    /// Return a segment in new_page_table that shares the pages of self copy-on-write.
+    /// Both self and the returned segment lose the write permission until handle_cow_fault is called,
+    /// except for MapType::Shared, whose pages are always shared writable.
+    fn share_cow(&self, page_table: &mut PageTable, new_page_table: &mut PageTable) -> Self {
+        assert_ne!(self.map_type, MapType::Identical);
+        let mut segment = Self::from_other(self);
+        segment.data_pages = self
+            .data_pages
+            .iter()
+            .map(|(vpn, page_ref)| (*vpn, page_ref.share(new_page_table.root_ppn())))
+            .collect();
+        for (vpn, page_ref) in self.data_pages.iter() {
+            self.update_pte(page_table, *vpn, page_ref.page());
+            segment.update_pte(new_page_table, *vpn, page_ref.page());
+        }
+        segment
+    }
+
+    /// Whether the access to the pages of self is allowed by self.permission.
+    fn allows(&self, is_write: bool) -> bool {
+        self.permission
+            .intersects(Permission::R | Permission::W | Permission::X)
+            && (!is_write || self.permission.contains(Permission::W))
+    }
+
+    /// Back the page with VirtPageNum vpn with a frame. Return false if the page is not a lazy one waiting for it.
+    fn handle_lazy_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        is_write: bool,
+    ) -> Result<bool, OutOfMemory> {
+        let vpn = self.page_vpn(vpn);
+        if self.map_type != MapType::Lazy
+            || self.data_pages.contains_key(&vpn)
+            || !self.allows(is_write)
+        {
+            return Ok(false);
+        }
+        let page = self.alloc_page(vpn).ok_or(OutOfMemory::System)?;
+        // Only the segments of 4KiB pages are loaded from ELF files.
+        if let Some(file_data) = &self.file_data {
+            file_data.load(vpn, page.ppn().unwrap());
+        }
+        let page_ref = PageRef::new(page, page_table.root_ppn());
+        self.update_pte(page_table, vpn, page_ref.page());
+        self.data_pages.insert(vpn, page_ref);
+        Ok(true)
+    }
+
+    /// Bring the page with VirtPageNum vpn back to memory and map it. Return false if the page is not a swapped-out
+    /// one, or one swapped in by another address space sharing it.
+    fn handle_swap_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        is_write: bool,
+    ) -> Result<bool, OutOfMemory> {
+        let vpn = self.page_vpn(vpn);
+        if !self.allows(is_write) || is_mapped(page_table, vpn) {
+            return Ok(false);
+        }
+        let page = match self.data_pages.get(&vpn) {
+            Some(page_ref) => page_ref.page().clone(),
+            None => return Ok(false),
+        };
+        page.load().ok_or(OutOfMemory::System)?;
+        self.update_pte(page_table, vpn, &page);
+        Ok(true)
+    }
+
+    /// Give the page with VirtPageNum vpn a private writable frame. Return false if the page is not copy-on-write.
+    fn handle_cow_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Result<bool, OutOfMemory> {
+        let vpn = self.page_vpn(vpn);
+        if !self.permission.contains(Permission::W) || !is_mapped(page_table, vpn) {
+            return Ok(false);
+        }
+        let mut page = match self.data_pages.get(&vpn) {
+            Some(page_ref) => page_ref.page().clone(),
+            None => return Ok(false),
+        };
+        if page_table.view().translate(vpn).unwrap().writable() {
+            return Ok(false);
+        }
+        // The last owner of a shared page can write to it directly.
+        // A shared page is never swapped out, so it is still in memory.
+        if page.owner_count() > 1 {
+            let new_page = self.alloc_page(vpn).ok_or(OutOfMemory::System)?;
+            let src_ppn = page.ppn().unwrap();
+            let dest_ppn = new_page.ppn().unwrap();
+            for i in 0..self.page_size.page_count() {
+                PhysPageNum(dest_ppn.0 + i)
+                    .get_bytes_array()
+                    .copy_from_slice(PhysPageNum(src_ppn.0 + i).get_bytes_array());
+            }
+            page = new_page;
+            self.data_pages
+                .insert(vpn, PageRef::new(page.clone(), page_table.root_ppn()));
+        }
+        self.update_pte(page_table, vpn, &page);
+        Ok(true)
+    }
+
+    /// Map the page of the file at vpn, which becomes dirty and writable on write. Return false if self does not
+    /// map a file, or the page is already mapped with the permission needed.
+    fn handle_file_fault(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        is_write: bool,
+    ) -> Result<bool, OutOfMemory> {
+        if self.map_type != MapType::File || !self.allows(is_write) {
+            return Ok(false);
+        }
+        if !self.data_pages.contains_key(&vpn) {
+            let file_data = self.file_data.as_ref().unwrap();
+            let page = file_data
+                .file
+                .page(file_data.page_index(vpn))
+                .ok_or(OutOfMemory::System)?;
+            self.data_pages
+                .insert(vpn, PageRef::new(page, page_table.root_ppn()));
+        } else if !is_write || page_table.view().translate(vpn).unwrap().writable() {
+            return Ok(false);
+        }
+        let page_ref = self.data_pages.get_mut(&vpn).unwrap();
+        if is_write {
+            page_ref.set_dirty(true);
+        }
+        let page = page_ref.page().clone();
+        self.update_pte(page_table, vpn, &page);
+        Ok(true)
+    }
+
+    /// Write the page at vpn back to the file if it is dirty. Return whether it was.
+    fn write_back_page(&mut self, vpn: VirtPageNum) -> bool {
+        if self.map_type != MapType::File {
+            return false;
+        }
+        match self.data_pages.get_mut(&vpn) {
+            Some(page_ref) if page_ref.dirty() => page_ref.set_dirty(false),
+            _ => return false,
+        }
+        let file_data = self.file_data.as_ref().unwrap();
+        file_data.file.write_back(file_data.page_index(vpn));
+        true
+    }
+
+    /// Write the dirty pages of self in [start_vpn, end_vpn) back to the file, and make them read-only again.
+    fn sync(&mut self, page_table: &mut PageTable, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let vpns: Vec<VirtPageNum> = self
+            .data_pages
+            .range(start_vpn..end_vpn)
+            .map(|(vpn, _)| *vpn)
+            .collect();
+        for vpn in vpns {
+            if self.write_back_page(vpn) {
+                let page = self.data_pages[&vpn].page().clone();
+                self.update_pte(page_table, vpn, &page);
+            }
         }
     }
 
@@ -137,27 +564,170 @@ impl MemorySegment {
     }
 }
 
+/// A resource limit like that of setrlimit. cur is enforced, and can be raised up to max.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+impl RLimit {
+    /// Whether self can be replaced by new, which is valid and does not raise the hard limit.
+    fn can_be_set_to(&self, new: &Self) -> bool {
+        new.cur <= new.max && new.max <= self.max
+    }
+}
+
+/// The memory used by an address space in pages. The pages shared with other address spaces are counted in each of
+/// them.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct MemoryUsage {
+    /// The data pages in memory.
+    pub rss: usize,
+    /// The data pages swapped out.
+    pub swap: usize,
+    /// The frames of the page table.
+    pub page_table: usize,
+}
+
+/// Why a page fault could not be resolved for lack of memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutOfMemory {
+    /// There are no frames left, even after swapping out pages.
+    System,
+    /// The memory used by the address space exceeds its memory limit.
+    Limit,
+}
+
+impl Drop for MemorySegment {
+    fn drop(&mut self) {
+        // The dirty pages of a file are written back when the address space is dropped, e.g. on exit or exec.
+        let vpns: Vec<VirtPageNum> = self.data_pages.keys().copied().collect();
+        for vpn in vpns {
+            self.write_back_page(vpn);
+        }
+    }
+}
+
+/// Map the tids of threads to the slots of their user stacks. The low bits of a tid are mapped by
+/// x -> (x * multiplier + offset) mod ASLR_STACK_SLOTS, which is a permutation since multiplier is odd.
+#[derive(Clone, Copy)]
+struct StackSlots {
+    multiplier: usize,
+    offset: usize,
+}
+
+impl StackSlots {
+    fn identity() -> Self {
+        Self {
+            multiplier: 1,
+            offset: 0,
+        }
+    }
+
+    fn random() -> Self {
+        Self {
+            multiplier: random_below(ASLR_STACK_SLOTS) | 1,
+            offset: random_below(ASLR_STACK_SLOTS),
+        }
+    }
+
+    fn slot(&self, tid: usize) -> usize {
+        let mask = ASLR_STACK_SLOTS - 1;
+        (tid & !mask) | (tid.wrapping_mul(self.multiplier).wrapping_add(self.offset) & mask)
+    }
+}
+
+/// Return a random multiple of PAGE_SIZE in [0, range).
+fn random_offset(range: usize) -> usize {
+    random_below(range / PAGE_SIZE) * PAGE_SIZE
+}
+
+/// What the loader tells a program about itself in the auxiliary vector.
+pub struct ElfInfo {
+    /// The entry point, with the load bias of a PIE added.
+    pub entry: usize,
+    /// The address of the program headers, if they are loaded with the program.
+    pub phdr: Option<usize>,
+    /// The program headers, which are copied to the user stack if they are not loaded with the program.
+    pub phdrs: Vec<u8>,
+    pub phent: usize,
+    pub phnum: usize,
+}
+
 pub struct AddressSpace {
+    /// The page table the kernel runs on while serving a user address space. It is dropped first, so that the kernel
+    /// leaves it before the user page table is freed.
+    kernel_view: Option<KernelView>,
     page_table: PageTable,
     segments: Vec<MemorySegment>,
+    /// The user heap is the segment [heap_base, brk), which starts right after the ELF image, or a random number of
+    /// pages after it with ASLR.
+    heap_base: usize,
+    brk: usize,
+    /// The limit of the size of each user stack in bytes.
+    stack_limit: RLimit,
+    /// The limit of the memory used by self in bytes, which counts the pages swapped out and the page table as well.
+    memory_limit: RLimit,
+    /// The areas for mmap are searched from mmap_base, which is MMAP_BASE, or a random address above it with ASLR.
+    mmap_base: usize,
+    stack_slots: StackSlots,
+    /// The shared memories created by self, which are kept while self exists even if they are never attached. They
+    /// are charged against the memory limit of self.
+    created_shm: Vec<ShmAttachment>,
+    /// Whether fault_in failed because no frame was left, which the syscall that called it takes to run the OOM
+    /// killer, since that cannot be done while the address space is locked.
+    out_of_memory: bool,
 }
 
 impl AddressSpace {
     pub fn new_bare() -> Self {
         Self {
+            kernel_view: None,
             page_table: PageTable::new(),
             segments: Vec::new(),
+            heap_base: 0,
+            brk: 0,
+            stack_limit: RLimit {
+                cur: USER_STACK_LIMIT,
+                max: USER_STACK_LIMIT,
+            },
+            memory_limit: RLimit {
+                cur: usize::MAX,
+                max: usize::MAX,
+            },
+            mmap_base: MMAP_BASE,
+            stack_slots: StackSlots::identity(),
+            created_shm: Vec::new(),
+            out_of_memory: false,
         }
     }
 
-    pub fn from_existed_user(user_space: &Self) -> Self {
+    /// The user segments are shared copy-on-write, so user_space loses the write permission to them as well.
+    pub fn from_existed_user(user_space: &mut Self) -> Self {
         let mut address_space = Self::new_bare();
         address_space.map_trampoline();
-        // Copy data sections/trap context/user stack
+        address_space.kernel_view = Some(KernelView::new());
+        address_space.heap_base = user_space.heap_base;
+        address_space.brk = user_space.brk;
+        address_space.stack_limit = user_space.stack_limit;
+        address_space.memory_limit = user_space.memory_limit;
+        address_space.mmap_base = user_space.mmap_base;
+        address_space.stack_slots = user_space.stack_slots;
         for segment in user_space.segments.iter() {
+            if segment.permission.contains(Permission::U) {
+                // data sections/user stack
+                let segment =
+                    segment.share_cow(&mut user_space.page_table, &mut address_space.page_table);
+                address_space.segments.push(segment);
+                continue;
+            }
+            // The kernel accesses the trap context through its physical address, so it is copied eagerly.
             address_space.add_segment(MemorySegment::from_other(segment), None);
             for vpn in segment.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                let src_ppn = user_space.page_table.view().translate(vpn).unwrap().ppn();
                 let dest_ppn = address_space.translate(vpn).unwrap().ppn();
                 dest_ppn
                     .get_bytes_array()
@@ -217,9 +787,31 @@ impl AddressSpace {
             None,
         );
         println!("mapping .bss section");
+        // The guard pages at the bottom of the boot stacks are left unmapped.
         address_space.add_segment(
             MemorySegment::new(
                 (sbss_with_stack as usize).into(),
+                (boot_stack_bottom as usize).into(),
+                MapType::Identical,
+                Permission::R | Permission::W,
+            ),
+            None,
+        );
+        for hart in 0..MAX_HARTS {
+            let guard = boot_stack_bottom as usize + hart * BOOT_STACK_SIZE;
+            address_space.add_segment(
+                MemorySegment::new(
+                    (guard + PAGE_SIZE).into(),
+                    (guard + BOOT_STACK_SIZE).into(),
+                    MapType::Identical,
+                    Permission::R | Permission::W,
+                ),
+                None,
+            );
+        }
+        address_space.add_segment(
+            MemorySegment::new(
+                (boot_stack_bottom as usize + MAX_HARTS * BOOT_STACK_SIZE).into(),
                 (ebss as usize).into(),
                 MapType::Identical,
                 Permission::R | Permission::W,
@@ -251,49 +843,195 @@ impl AddressSpace {
         address_space
     }
 
-    /// Return (address_space, user_stack_base, the entry point of the program).
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// Return (address_space, user_stack_base, what the program is told about itself), or None if the file is not
+    /// a supported executable.
+    /// The segments are loaded from the ELF file lazily through its page cache, which is kept alive by the address
+    /// space. Only the headers are read here, and the pages with dynamic relocations if it is a PIE, which is loaded
+    /// at PIE_BASE.
+    /// If aslr, the user stacks, the user heap, the mmap area and a PIE are placed at random addresses.
+    pub fn from_elf(elf_file: Arc<PageCache>, aslr: bool) -> Option<(Self, usize, ElfInfo)> {
         let mut address_space = AddressSpace::new_bare();
+        let mut user_stack_base = USER_STACK_BASE;
+        let mut heap_base_offset = 0;
+        let mut pie_base = PIE_BASE;
+        if aslr {
+            user_stack_base += random_offset(ASLR_STACK_RANGE);
+            heap_base_offset = random_offset(ASLR_HEAP_RANGE);
+            address_space.mmap_base += random_offset(ASLR_MMAP_RANGE);
+            address_space.stack_slots = StackSlots::random();
+            pie_base += random_offset(ASLR_PIE_RANGE);
+        }
         // User address space does not have the ownership of the physical frame where the trampoline code resides.
         // So the trampoline should only be added to the page table.
         address_space.map_trampoline();
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-        let magic = elf.header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        address_space.kernel_view = Some(KernelView::new());
+        let elf_headers = read_elf_headers(&elf_file)?;
+        let elf = xmas_elf::ElfFile::new(elf_headers.as_slice()).ok()?;
+        // The load bias is added to every address in a PIE.
+        let bias = match elf.header.pt2.type_().as_type() {
+            xmas_elf::header::Type::Executable => 0,
+            xmas_elf::header::Type::SharedObject => {
+                let start = elf
+                    .program_iter()
+                    .filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load))
+                    .map(|ph| ph.virtual_addr() as usize)
+                    .min()?;
+                pie_base.checked_sub(VirtAddr::from(start).floor().0 * PAGE_SIZE)?
+            }
+            _ => return None,
+        };
+        let ph_offset = elf.header.pt2.ph_offset() as usize;
+        let ph_entry_size = elf.header.pt2.ph_entry_size() as usize;
         let ph_count = elf.header.pt2.ph_count();
+        let mut phdr = None;
+        let mut dynamic = None;
         let mut segment_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-                let mut permission = Permission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    permission |= Permission::R;
+            let ph = elf.program_header(i).ok()?;
+            match ph.get_type() {
+                Ok(xmas_elf::program::Type::Load) => {
+                    let start = bias.checked_add(ph.virtual_addr() as usize)?;
+                    let start_va: VirtAddr = start.into();
+                    let end_va: VirtAddr = start.checked_add(ph.mem_size() as usize)?.into();
+                    let mut permission = Permission::U;
+                    let ph_flags = ph.flags();
+                    if ph_flags.is_read() {
+                        permission |= Permission::R;
+                    }
+                    if ph_flags.is_write() {
+                        permission |= Permission::W;
+                    }
+                    if ph_flags.is_execute() {
+                        permission |= Permission::X;
+                    }
+                    let mut segment =
+                        MemorySegment::new(start_va, end_va, MapType::Lazy, permission);
+                    segment.file_data = Some(SegmentData {
+                        file: elf_file.clone(),
+                        offset: ph.offset() as usize,
+                        len: ph.file_size() as usize,
+                        start_va: start_va.0,
+                        writable: false,
+                    });
+                    segment_end_vpn = max(segment_end_vpn, segment.vpn_range.get_end());
+                    address_space.add_segment(segment, None);
+                    // The program headers may be loaded with the first segment.
+                    let ph_in_file = ph.offset() as usize..(ph.offset() + ph.file_size()) as usize;
+                    if phdr.is_none()
+                        && ph_in_file.contains(&ph_offset)
+                        && ph_offset + ph_count as usize * ph_entry_size <= ph_in_file.end
+                    {
+                        phdr = Some(start_va.0 + ph_offset - ph.offset() as usize);
+                    }
                 }
-                if ph_flags.is_write() {
-                    permission |= Permission::W;
+                Ok(xmas_elf::program::Type::Phdr) => {
+                    phdr = Some(bias + ph.virtual_addr() as usize);
                 }
-                if ph_flags.is_execute() {
-                    permission |= Permission::X;
+                Ok(xmas_elf::program::Type::Dynamic) => {
+                    dynamic = Some((bias + ph.virtual_addr() as usize, ph.mem_size() as usize));
                 }
-                let segment = MemorySegment::new(start_va, end_va, MapType::Framed, permission);
-                segment_end_vpn = segment.vpn_range.get_end();
-                address_space.add_segment(
-                    segment,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                _ => {}
+            }
+        }
+        if let Some((dynamic, dynamic_size)) = dynamic {
+            if bias != 0 {
+                address_space.relocate(dynamic, dynamic_size, bias)?;
             }
         }
-        let segment_end_va: VirtAddr = segment_end_vpn.into();
-        // guard page between segment_end and user_stack
-        let user_stack_base = segment_end_va.0 + PAGE_SIZE;
-        (
-            address_space,
-            user_stack_base,
-            elf.header.pt2.entry_point() as usize,
-        )
+        let heap_base: VirtAddr = (VirtAddr::from(segment_end_vpn).0 + heap_base_offset).into();
+        address_space.heap_base = heap_base.0;
+        address_space.brk = heap_base.0;
+        address_space.add_segment(
+            MemorySegment::new(
+                heap_base,
+                heap_base,
+                MapType::Lazy,
+                Permission::R | Permission::W | Permission::U,
+            ),
+            None,
+        );
+        let elf_info = ElfInfo {
+            entry: bias + elf.header.pt2.entry_point() as usize,
+            phdr: phdr,
+            phdrs: elf_headers[ph_offset..ph_offset + ph_count as usize * ph_entry_size].to_vec(),
+            phent: ph_entry_size,
+            phnum: ph_count as usize,
+        };
+        Some((address_space, user_stack_base, elf_info))
+    }
+
+    /// Apply the dynamic relocations described by the dynamic section at [dynamic, dynamic + dynamic_size) of a PIE
+    /// loaded with bias. Return None if a relocation is not supported, e.g. one against an undefined symbol, which
+    /// needs a dynamic linker.
+    fn relocate(&mut self, dynamic: usize, dynamic_size: usize, bias: usize) -> Option<()> {
+        let mut rela = (0, 0);
+        let mut jmprel = (0, 0);
+        let mut rela_entry_size = RELA_SIZE;
+        let mut symtab = 0;
+        let mut sym_entry_size = SYM_SIZE;
+        for i in 0..dynamic_size / DYN_SIZE {
+            let [tag, value]: [usize; 2] = self
+                .read_user((dynamic + i * DYN_SIZE) as *const [usize; 2])
+                .ok()?;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela.0 = bias + value,
+                DT_RELASZ => rela.1 = value,
+                DT_RELAENT => rela_entry_size = value,
+                DT_JMPREL => jmprel.0 = bias + value,
+                DT_PLTRELSZ => jmprel.1 = value,
+                DT_SYMTAB => symtab = bias + value,
+                DT_SYMENT => sym_entry_size = value,
+                DT_REL => return None,
+                _ => {}
+            }
+        }
+        if rela_entry_size < RELA_SIZE || sym_entry_size < SYM_SIZE {
+            return None;
+        }
+        for (table, table_size) in [rela, jmprel] {
+            for i in 0..table_size / rela_entry_size {
+                let [offset, info, addend]: [usize; 3] = self
+                    .read_user((table + i * rela_entry_size) as *const [usize; 3])
+                    .ok()?;
+                let symbol = info >> 32;
+                let value = match info & 0xffff_ffff {
+                    R_RISCV_NONE => continue,
+                    R_RISCV_64 => self
+                        .symbol_value(symtab, sym_entry_size, symbol, bias)?
+                        .wrapping_add(addend),
+                    R_RISCV_RELATIVE => bias.wrapping_add(addend),
+                    R_RISCV_JUMP_SLOT => self.symbol_value(symtab, sym_entry_size, symbol, bias)?,
+                    _ => return None,
+                };
+                self.write_loaded((bias + offset) as *mut usize, &value)
+                    .ok()?;
+            }
+        }
+        Some(())
+    }
+
+    /// Return the address of the symbol at index in the dynamic symbol table of a PIE loaded with bias, or None if
+    /// it is undefined.
+    fn symbol_value(
+        &mut self,
+        symtab: usize,
+        entry_size: usize,
+        index: usize,
+        bias: usize,
+    ) -> Option<usize> {
+        if index == 0 {
+            return Some(0);
+        }
+        // st_name, st_info, st_other and st_shndx are packed in the first word, followed by st_value.
+        let [names, value]: [usize; 2] = self
+            .read_user((symtab + index * entry_size) as *const [usize; 2])
+            .ok()?;
+        match names >> 48 {
+            SHN_UNDEF => None,
+            SHN_ABS => Some(value),
+            _ => Some(bias + value),
+        }
     }
 
     fn add_segment(&mut self, mut segment: MemorySegment, data: Option<&[u8]>) {
@@ -316,6 +1054,32 @@ impl AddressSpace {
         );
     }
 
+    /// Add a user stack, which grows down on demand, at [start_va, end_va).
+    /// The caller should keep USER_STACK_LIMIT bytes and a guard page below end_va free for it.
+    pub fn add_segment_stack(&mut self, start_va: VirtAddr, end_va: VirtAddr) {
+        let mut segment = MemorySegment::new(
+            start_va,
+            end_va,
+            MapType::Lazy,
+            Permission::R | Permission::W | Permission::U,
+        );
+        segment.stack = true;
+        self.add_segment(segment, None);
+    }
+
+    /// Remove the segment ending at end_vpn, which is used for stacks since their start moves.
+    pub fn remove_segment_with_end_vpn(&mut self, end_vpn: VirtPageNum) {
+        if let Some((id, segment)) = self
+            .segments
+            .iter_mut()
+            .enumerate()
+            .find(|(_, segment)| segment.vpn_range.get_end() == end_vpn)
+        {
+            segment.unmap(&mut self.page_table);
+            self.segments.remove(id);
+        }
+    }
+
     pub fn remove_segment_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((id, segment)) = self
             .segments
@@ -336,6 +1100,19 @@ impl AddressSpace {
         self.page_table.satp()
     }
 
+    /// The satp of the kernel view of self, which is used as the kernel_satp of the traps from self.
+    pub fn kernel_view_satp(&self) -> usize {
+        self.kernel_view.as_ref().unwrap().satp()
+    }
+
+    /// Make the user window of the kernel view of self map [start, end) like self.
+    pub fn sync_user_window(&self, start: usize, end: usize) {
+        self.kernel_view
+            .as_ref()
+            .unwrap()
+            .sync(self.page_table.root_ppn(), start, end);
+    }
+
     pub fn activate(&self) {
         unsafe {
             satp::write(self.satp());
@@ -346,8 +1123,594 @@ impl AddressSpace {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.view().translate(vpn)
     }
+
+    /// Try to resolve a page fault at vpn. Return false if the access is illegal, or an error if there is no memory
+    /// for the page. A page resolving the fault is kept even if it makes self exceed its memory limit.
+    pub fn handle_page_fault(
+        &mut self,
+        vpn: VirtPageNum,
+        is_write: bool,
+    ) -> Result<bool, OutOfMemory> {
+        let stack_limit = self.stack_limit.cur / PAGE_SIZE;
+        // A stack only grows for the accesses it allows, so that an illegal access leaves it as it is.
+        if let Some(stack) = self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.can_grow_to(vpn, stack_limit) && segment.allows(is_write))
+        {
+            stack.grow_to(vpn);
+        }
+        let charged_pages = self.charged_pages();
+        let handled = match self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.vpn_range.contains(vpn))
+        {
+            Some(segment) => {
+                segment.handle_lazy_fault(&mut self.page_table, vpn, is_write)?
+                    || segment.handle_file_fault(&mut self.page_table, vpn, is_write)?
+                    || segment.handle_swap_fault(&mut self.page_table, vpn, is_write)?
+                    || (is_write && segment.handle_cow_fault(&mut self.page_table, vpn)?)
+            }
+            None => false,
+        };
+        if self.charged_pages() > max(charged_pages, self.memory_limit.cur / PAGE_SIZE) {
+            return Err(OutOfMemory::Limit);
+        }
+        Ok(handled)
+    }
+
+    /// Whether a fault at vpn, which handle_page_fault failed to resolve, is an overflow of a user stack.
+    /// It is the case if vpn is below a stack, within the soft limit of the stack size and a guard page below it.
+    pub fn is_stack_overflow(&self, vpn: VirtPageNum) -> bool {
+        let stack_limit = self.stack_limit.cur / PAGE_SIZE;
+        self.segments
+            .iter()
+            .any(|segment| segment.can_grow_to(vpn, stack_limit + 1))
+    }
+
+    /// Return the index of the slot of the user stack of the thread tid, which is tid itself without ASLR.
+    pub fn stack_slot(&self, tid: usize) -> usize {
+        self.stack_slots.slot(tid)
+    }
+
+    pub fn stack_limit(&self) -> RLimit {
+        self.stack_limit
+    }
+
+    /// Return false if stack_limit is invalid, or raises the hard limit.
+    pub fn set_stack_limit(&mut self, stack_limit: RLimit) -> bool {
+        if !self.stack_limit.can_be_set_to(&stack_limit) {
+            return false;
+        }
+        self.stack_limit = stack_limit;
+        true
+    }
+
+    pub fn memory_limit(&self) -> RLimit {
+        self.memory_limit
+    }
+
+    /// Like set_stack_limit. Memory already used beyond the new limit is kept, but the faults allocating more memory
+    /// fail.
+    pub fn set_memory_limit(&mut self, memory_limit: RLimit) -> bool {
+        if !self.memory_limit.can_be_set_to(&memory_limit) {
+            return false;
+        }
+        self.memory_limit = memory_limit;
+        true
+    }
+
+    /// Count the data pages and page table frames of self.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage {
+            page_table: self.page_table.frame_count(),
+            ..Default::default()
+        };
+        for segment in self.segments.iter() {
+            let page_count = segment.page_size.page_count();
+            for page_ref in segment.data_pages.values() {
+                if page_ref.page().ppn().is_some() {
+                    usage.rss += page_count;
+                } else {
+                    usage.swap += page_count;
+                }
+            }
+        }
+        usage
+    }
+
+    /// The pages charged against the memory limit, which are the pages counted by memory_usage and the pages of the
+    /// shared memories created by self. It is cheaper, since the pages are not visited.
+    pub fn charged_pages(&self) -> usize {
+        self.page_table.frame_count()
+            + self
+                .segments
+                .iter()
+                .map(|segment| segment.data_pages.len() * segment.page_size.page_count())
+                .sum::<usize>()
+            + self
+                .created_shm
+                .iter()
+                .map(|shm| shm.page_count())
+                .sum::<usize>()
+    }
+
+    /// Unmap the user pages of self to free their frames and swap slots, e.g. when the process is killed for lack of
+    /// memory. The segments are kept, so the lazy pages are backed again if accessed.
+    pub fn release_user_pages(&mut self) {
+        for segment in self.segments.iter_mut() {
+            if !segment.permission.contains(Permission::U) {
+                continue;
+            }
+            let vpns: Vec<VirtPageNum> = segment.data_pages.keys().copied().collect();
+            for vpn in vpns {
+                segment.unmap_page(&mut self.page_table, vpn);
+            }
+        }
+    }
+
+    /// Return the page mapped at vpn, if it is backed by one.
+    fn page(&self, vpn: VirtPageNum) -> Option<Arc<Page>> {
+        self.segments
+            .iter()
+            .find(|segment| segment.vpn_range.contains(vpn))
+            .and_then(|segment| segment.data_pages.get(&segment.page_vpn(vpn)))
+            .map(|page_ref| page_ref.page().clone())
+    }
+
+    /// Resolve in advance the page faults that a user mode access to [start, start + len) would raise.
+    /// The kernel accesses user memory in user windows, where page faults are not handled.
+    /// Return the pages in the range, which are not swapped out until the result is dropped, or BadAddress if the
+    /// access is illegal in user mode, or there is no memory for it. The latter is told by take_out_of_memory.
+    pub fn fault_in(
+        &mut self,
+        start: usize,
+        len: usize,
+        is_write: bool,
+    ) -> Result<PinnedPages, BadAddress> {
+        let mut pinned_pages = PinnedPages::new();
+        if len == 0 {
+            return Ok(pinned_pages);
+        }
+        let end = start.checked_add(len).ok_or(BadAddress)?;
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(end).ceil();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if !self.user_accessible(vpn, is_write) {
+                match self.handle_page_fault(vpn, is_write) {
+                    Ok(_) => {}
+                    Err(OutOfMemory::System) => {
+                        self.out_of_memory = true;
+                        return Err(BadAddress);
+                    }
+                    Err(OutOfMemory::Limit) => return Err(BadAddress),
+                }
+                // The handler may map the page without the permission, e.g. for a trap context.
+                if !self.user_accessible(vpn, is_write) {
+                    return Err(BadAddress);
+                }
+            }
+            if let Some(page) = self.page(vpn) {
+                pinned_pages.push(page);
+            }
+        }
+        Ok(pinned_pages)
+    }
+
+    /// Return whether fault_in has failed because no frame was left since the last call, and clear it.
+    pub fn take_out_of_memory(&mut self) -> bool {
+        core::mem::take(&mut self.out_of_memory)
+    }
+
+    /// Whether vpn is mapped with the U bit and the permission needed by the access.
+    fn user_accessible(&self, vpn: VirtPageNum, is_write: bool) -> bool {
+        match self.translate(vpn) {
+            Some(pte) => {
+                pte.is_valid()
+                    && pte.user()
+                    && if is_write {
+                        pte.writable()
+                    } else {
+                        pte.readable()
+                    }
+            }
+            None => false,
+        }
+    }
+
+    /// Whether [start_vpn, end_vpn) can be used by mmap. Overlapping with other mmap segments is allowed if replace.
+    fn mmap_area_usable(
+        &self,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+        replace: bool,
+    ) -> bool {
+        start_vpn >= VirtAddr::from(MMAP_BASE).floor()
+            && start_vpn < end_vpn
+            && end_vpn <= VirtAddr::from(MMAP_END).floor()
+            && self.segments.iter().all(|segment| {
+                segment.vpn_range.get_end() <= start_vpn
+                    || end_vpn <= segment.vpn_range.get_start()
+                    || (replace
+                        && segment.mmap
+                        && segment.splittable_at(start_vpn)
+                        && segment.splittable_at(end_vpn))
+            })
+    }
+
+    /// Return the lowest free area of page_count pages in [self.mmap_base, MMAP_END), which starts at a multiple of
+    /// align pages.
+    fn find_mmap_area(&self, page_count: usize, align: usize) -> Option<VirtPageNum> {
+        let mut start_vpn = VirtPageNum(
+            VirtAddr::from(self.mmap_base)
+                .floor()
+                .0
+                .next_multiple_of(align),
+        );
+        loop {
+            let end_vpn = VirtPageNum(start_vpn.0 + page_count);
+            if end_vpn > VirtAddr::from(MMAP_END).floor() {
+                return None;
+            }
+            match self.segments.iter().find(|segment| {
+                segment.vpn_range.get_start() < end_vpn && start_vpn < segment.vpn_range.get_end()
+            }) {
+                Some(segment) => {
+                    start_vpn = VirtPageNum(segment.vpn_range.get_end().0.next_multiple_of(align))
+                }
+                None => return Some(start_vpn),
+            }
+        }
+    }
+
+    /// Split the mmap segments so that none of them crosses start_vpn or end_vpn.
+    fn split_mmap_segments(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        for vpn in [start_vpn, end_vpn] {
+            if let Some(segment) = self.segments.iter_mut().find(|segment| {
+                segment.mmap
+                    && segment.vpn_range.get_start() < vpn
+                    && vpn < segment.vpn_range.get_end()
+            }) {
+                let segment = segment.split_off(vpn);
+                self.segments.push(segment);
+            }
+        }
+    }
+
+    fn merge_mmap_segments(&mut self) {
+        loop {
+            let count = self.segments.len();
+            let pair = (0..count)
+                .flat_map(|left| (0..count).map(move |right| (left, right)))
+                .find(|&(left, right)| self.segments[left].mergeable_with(&self.segments[right]));
+            let (left, right) = match pair {
+                Some(pair) => pair,
+                None => break,
+            };
+            let segment = self.segments.remove(right);
+            let left = if right < left { left - 1 } else { left };
+            self.segments[left].append(segment);
+        }
+    }
+
+    /// Remove the mmap segments in [start_vpn, end_vpn).
+    fn remove_mmap_area(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        self.split_mmap_segments(start_vpn, end_vpn);
+        let page_table = &mut self.page_table;
+        self.segments.retain_mut(|segment| {
+            let inside = segment.mmap
+                && start_vpn <= segment.vpn_range.get_start()
+                && segment.vpn_range.get_end() <= end_vpn;
+            if inside {
+                segment.unmap(page_table);
+            }
+            !inside
+        });
+    }
+
+    /// Return the start of an area of page_count pages for a new mmap segment, which is a multiple of align pages.
+    /// The area is placed at hint if possible. If fixed, it must be placed at hint, replacing the mmap segments there.
+    fn place_mmap_area(
+        &mut self,
+        hint: usize,
+        page_count: usize,
+        align: usize,
+        fixed: bool,
+    ) -> Option<VirtPageNum> {
+        if hint % (align * PAGE_SIZE) != 0 || (fixed && hint >= MMAP_END) {
+            return None;
+        }
+        let hint_vpn = VirtAddr::from(hint).floor();
+        let hint_end_vpn = VirtPageNum(hint_vpn.0 + page_count);
+        if fixed {
+            if !self.mmap_area_usable(hint_vpn, hint_end_vpn, true) {
+                return None;
+            }
+            self.remove_mmap_area(hint_vpn, hint_end_vpn);
+            Some(hint_vpn)
+        } else if hint != 0
+            && hint < MMAP_END
+            && self.mmap_area_usable(hint_vpn, hint_end_vpn, false)
+        {
+            Some(hint_vpn)
+        } else {
+            self.find_mmap_area(page_count, align)
+        }
+    }
+
+    /// Map len bytes of anonymous memory, which are allocated lazily. Return the start address of the mapping.
+    /// The mapping is placed like place_mmap_area. The memory is made of pages of page_size, so hint should be
+    /// aligned to it, and len is rounded up to it.
+    pub fn mmap(
+        &mut self,
+        hint: usize,
+        len: usize,
+        permission: Permission,
+        fixed: bool,
+        page_size: PageSize,
+    ) -> Option<usize> {
+        if len == 0 || len > MMAP_END {
+            return None;
+        }
+        let align = page_size.page_count();
+        let page_count = VirtAddr::from(len).ceil().0.next_multiple_of(align);
+        let start_vpn = self.place_mmap_area(hint, page_count, align, fixed)?;
+        let mut segment = MemorySegment::new(
+            start_vpn.into(),
+            VirtPageNum(start_vpn.0 + page_count).into(),
+            MapType::Lazy,
+            permission,
+        );
+        segment.mmap = true;
+        segment.page_size = page_size;
+        self.add_segment(segment, None);
+        self.merge_mmap_segments();
+        Some(VirtAddr::from(start_vpn).0)
+    }
+
+    /// Map the bytes [offset, offset + len) of file like mmap, where offset should be aligned to PAGE_SIZE.
+    /// If shared, the pages of the page cache of the file are mapped, and the writes to them are written back to the
+    /// file, which must be writable for the mapping to be made writable. Otherwise, the pages are private copies
+    /// loaded on fault.
+    #[allow(clippy::too_many_arguments)]
+    pub fn mmap_file(
+        &mut self,
+        hint: usize,
+        len: usize,
+        permission: Permission,
+        fixed: bool,
+        file: Arc<PageCache>,
+        offset: usize,
+        shared: bool,
+        writable: bool,
+    ) -> Option<usize> {
+        if len == 0 || len > MMAP_END || offset % PAGE_SIZE != 0 {
+            return None;
+        }
+        if shared && permission.contains(Permission::W) && !writable {
+            return None;
+        }
+        let page_count = VirtAddr::from(len).ceil().0;
+        let start_vpn = self.place_mmap_area(hint, page_count, 1, fixed)?;
+        let map_type = if shared { MapType::File } else { MapType::Lazy };
+        let mut segment = MemorySegment::new(
+            start_vpn.into(),
+            VirtPageNum(start_vpn.0 + page_count).into(),
+            map_type,
+            permission,
+        );
+        let start_va = VirtAddr::from(start_vpn).0;
+        let file_len = min(len, file.size().saturating_sub(offset));
+        segment.file_data = Some(SegmentData {
+            file: file,
+            offset: offset,
+            len: file_len,
+            start_va: start_va,
+            writable: writable,
+        });
+        segment.mmap = true;
+        self.add_segment(segment, None);
+        Some(start_va)
+    }
+
+    /// Write the dirty pages of the shared file mappings in [start, start + len) back to the files.
+    /// Return false if the range is invalid.
+    pub fn msync(&mut self, start: usize, len: usize) -> bool {
+        if start % PAGE_SIZE != 0 || start.checked_add(len).is_none() {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        for segment in self.segments.iter_mut() {
+            segment.sync(&mut self.page_table, start_vpn, end_vpn);
+        }
+        true
+    }
+
+    /// Unmap the mmap segments in [start, start + len). Return false if the range is invalid.
+    pub fn munmap(&mut self, start: usize, len: usize) -> bool {
+        if start >= MMAP_END || len > MMAP_END || start % PAGE_SIZE != 0 {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtPageNum(start_vpn.0 + VirtAddr::from(len).ceil().0);
+        if !self.mmap_area_usable(start_vpn, end_vpn, true) {
+            return false;
+        }
+        self.remove_mmap_area(start_vpn, end_vpn);
+        true
+    }
+
+    /// Change the permission of [start, start + len), which should be covered by mmap segments.
+    /// Return false if the range is invalid, or a shared mapping of a file not opened for writing would be writable.
+    pub fn mprotect(&mut self, start: usize, len: usize, permission: Permission) -> bool {
+        if start >= MMAP_END || len > MMAP_END || start % PAGE_SIZE != 0 {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtPageNum(start_vpn.0 + VirtAddr::from(len).ceil().0);
+        if !self.mmap_area_usable(start_vpn, end_vpn, true) {
+            return false;
+        }
+        let covered_page_count: usize = self
+            .segments
+            .iter()
+            .map(|segment| {
+                let start = max(start_vpn, segment.vpn_range.get_start());
+                let end = min(end_vpn, segment.vpn_range.get_end());
+                end.0.saturating_sub(start.0)
+            })
+            .sum();
+        if covered_page_count != end_vpn.0 - start_vpn.0 {
+            return false;
+        }
+        if permission.contains(Permission::W)
+            && self.segments.iter().any(|segment| {
+                segment.map_type == MapType::File
+                    && segment.vpn_range.get_start() < end_vpn
+                    && start_vpn < segment.vpn_range.get_end()
+                    && !segment.file_data.as_ref().unwrap().writable
+            })
+        {
+            return false;
+        }
+        self.split_mmap_segments(start_vpn, end_vpn);
+        for segment in self.segments.iter_mut() {
+            if start_vpn <= segment.vpn_range.get_start() && segment.vpn_range.get_end() <= end_vpn
+            {
+                segment.set_permission(&mut self.page_table, permission);
+            }
+        }
+        self.merge_mmap_segments();
+        true
+    }
+
+    /// Return the id of the shared memory with key, which is created with size bytes if it does not exist. It is only
+    /// created if it fits in the memory limit of self.
+    pub fn get_shared_memory(&mut self, key: usize, size: usize) -> Option<usize> {
+        let page_limit = (self.memory_limit.cur / PAGE_SIZE).saturating_sub(self.charged_pages());
+        let (id, created) = shm_get(key, size, page_limit)?;
+        self.created_shm.extend(created);
+        Some(id)
+    }
+
+    /// Attach the shared memory with id at addr, or at an address chosen like mmap if addr is 0.
+    /// Return the start address.
+    pub fn attach_shared_memory(&mut self, id: usize, addr: usize) -> Option<usize> {
+        let page_count = shm_page_count(id)?;
+        let start_vpn = if addr == 0 {
+            self.find_mmap_area(page_count, 1)?
+        } else {
+            if addr % PAGE_SIZE != 0 || addr >= MMAP_END {
+                return None;
+            }
+            let start_vpn = VirtAddr::from(addr).floor();
+            if !self.mmap_area_usable(start_vpn, VirtPageNum(start_vpn.0 + page_count), false) {
+                return None;
+            }
+            start_vpn
+        };
+        let mut segment = MemorySegment::new(
+            start_vpn.into(),
+            VirtPageNum(start_vpn.0 + page_count).into(),
+            MapType::Shared,
+            Permission::R | Permission::W | Permission::U,
+        );
+        segment.shm = Some(shm_attachment(id).unwrap());
+        self.add_segment(segment, None);
+        Some(VirtAddr::from(start_vpn).0)
+    }
+
+    /// Detach the shared memory attached at addr. Return false if there is no such shared memory.
+    pub fn detach_shared_memory(&mut self, addr: usize) -> bool {
+        if addr % PAGE_SIZE != 0 || addr >= MMAP_END {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(addr).floor();
+        match self.segments.iter().position(|segment| {
+            segment.map_type == MapType::Shared && segment.vpn_range.get_start() == start_vpn
+        }) {
+            Some(id) => {
+                let mut segment = self.segments.remove(id);
+                segment.unmap(&mut self.page_table);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the program break to new_brk and return the new program break.
+    /// If new_brk is invalid, the program break is unchanged and returned.
+    pub fn brk(&mut self, new_brk: usize) -> usize {
+        // Leave a guard page between the heap and the user stacks.
+        if new_brk < self.heap_base || new_brk > USER_STACK_BASE - PAGE_SIZE {
+            return self.brk;
+        }
+        let heap_start_vpn = VirtAddr::from(self.heap_base).floor();
+        let old_end_vpn = VirtAddr::from(self.brk).ceil();
+        let end_vpn = VirtAddr::from(new_brk).ceil();
+        if end_vpn > old_end_vpn
+            && self.segments.iter().any(|segment| {
+                segment.vpn_range.get_start() < end_vpn
+                    && old_end_vpn < segment.vpn_range.get_end()
+                    && segment.vpn_range.get_start() != heap_start_vpn
+            })
+        {
+            return self.brk;
+        }
+        let heap = self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.vpn_range.get_start() == heap_start_vpn && !segment.mmap)
+            .unwrap();
+        heap.set_end(&mut self.page_table, end_vpn);
+        self.brk = new_brk;
+        self.brk
+    }
 }
 
+/// Return the bytes of elf_file up to the end of its program headers.
+fn read_elf_headers(elf_file: &PageCache) -> Option<Vec<u8>> {
+    // The header of a 64-bit ELF file has 64 bytes.
+    let mut data = vec![0u8; 64];
+    elf_file.read_at(0, &mut data);
+    let elf = xmas_elf::ElfFile::new(data.as_slice()).ok()?;
+    if elf.header.pt2.ph_entry_size() as usize != PH_SIZE {
+        return None;
+    }
+    let ph_end = (elf.header.pt2.ph_count() as usize)
+        .checked_mul(PH_SIZE)?
+        .checked_add(elf.header.pt2.ph_offset() as usize)?;
+    if ph_end > elf_file.size() {
+        return None;
+    }
+    data.resize(ph_end, 0);
+    elf_file.read_at(0, &mut data);
+    Some(data)
+}
+
+// The program headers, dynamic section, relocations and symbols of a 64-bit ELF file.
+const PH_SIZE: usize = 56;
+const DYN_SIZE: usize = 16;
+const RELA_SIZE: usize = 24;
+const SYM_SIZE: usize = 24;
+const DT_NULL: usize = 0;
+const DT_PLTRELSZ: usize = 2;
+const DT_SYMTAB: usize = 6;
+const DT_RELA: usize = 7;
+const DT_RELASZ: usize = 8;
+const DT_RELAENT: usize = 9;
+const DT_SYMENT: usize = 11;
+const DT_REL: usize = 17;
+const DT_JMPREL: usize = 23;
+const R_RISCV_NONE: usize = 0;
+const R_RISCV_64: usize = 2;
+const R_RISCV_RELATIVE: usize = 3;
+const R_RISCV_JUMP_SLOT: usize = 5;
+const SHN_UNDEF: usize = 0;
+const SHN_ABS: usize = 0xfff1;
+
 #[allow(unused)]
 #[unsafe(no_mangle)]
 pub fn remap_test() {
@@ -1,55 +1,179 @@
-use super::{PhysAddr, PhysPageNum};
-use crate::{config::MEMORY_END, println, sync::UPSafeCell};
+use super::{swap::reclaim_frame, PhysAddr, PhysPageNum};
+use crate::{
+    config::{KERNEL_RESERVED_FRAMES, MEMORY_END, PAGE_SIZE},
+    println,
+    sync::SpinLock,
+};
 use alloc::vec::Vec;
-use core::fmt::Debug;
+use core::{cmp::min, fmt::Debug};
 use lazy_static::lazy_static;
 
 trait FrameAllocator {
     fn new() -> Self;
-    fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn dealloc(&mut self, ppn: PhysPageNum);
+    /// Allocate 2^order contiguous frames. Return the first of them.
+    fn alloc(&mut self, order: usize) -> Option<PhysPageNum>;
+    fn dealloc(&mut self, ppn: PhysPageNum, order: usize);
+    fn free_frames(&self) -> usize;
 }
 
-pub struct StackFrameAllocator {
-    current: PhysPageNum,
-    end: PhysPageNum,
-    recycled: Vec<PhysPageNum>,
+/// Blocks of up to 2^(MAX_ORDER - 1) frames are managed.
+const MAX_ORDER: usize = 16;
+/// The end of a free list.
+const NIL: u32 = u32::MAX;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FrameState {
+    /// The frame is not the first frame of a block.
+    Unused,
+    /// The frame is the first frame of a free block in free_list[order].
+    Free,
+    /// The frame is the first frame of an allocated block.
+    Allocated,
+}
+
+/// The metadata of a frame. prev and next link the free blocks of the same order.
+#[derive(Clone, Copy)]
+struct FrameInfo {
+    state: FrameState,
+    order: u8,
+    prev: u32,
+    next: u32,
+}
+
+/// A buddy allocator, where a block of 2^order frames starts at a ppn aligned to 2^order.
+/// The metadata is kept in the first frames of the managed range, so that the kernel heap is not used.
+pub struct BuddyFrameAllocator {
+    /// The first frame managed. frames[i] describes the frame base + i.
+    base: PhysPageNum,
+    frames: &'static mut [FrameInfo],
+    /// The first frames of the free blocks of each order, relative to base.
+    free_list: [u32; MAX_ORDER],
+    free_frames: usize,
 }
 
-impl FrameAllocator for StackFrameAllocator {
+impl BuddyFrameAllocator {
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        let frame_count = r.0 - l.0;
+        let info_frame_count = (frame_count * size_of::<FrameInfo>()).div_ceil(PAGE_SIZE);
+        self.frames = unsafe {
+            core::slice::from_raw_parts_mut(PhysAddr::from(l).0 as *mut FrameInfo, frame_count)
+        };
+        self.frames.fill(FrameInfo {
+            state: FrameState::Unused,
+            order: 0,
+            prev: NIL,
+            next: NIL,
+        });
+        self.base = l;
+        // The frames holding the metadata are never freed.
+        let mut start = l.0 + info_frame_count;
+        while start < r.0 {
+            let lowbit = 1 << start.trailing_zeros();
+            let max_size = 1 << (usize::BITS - 1 - (r.0 - start).leading_zeros());
+            let size: usize = min(min(lowbit, max_size), 1 << (MAX_ORDER - 1));
+            self.push(PhysPageNum(start), size.trailing_zeros() as usize);
+            self.free_frames += size;
+            start += size;
+        }
+    }
+
+    fn index(&self, ppn: PhysPageNum) -> usize {
+        ppn.0 - self.base.0
+    }
+
+    fn push(&mut self, ppn: PhysPageNum, order: usize) {
+        let index = self.index(ppn);
+        let head = self.free_list[order];
+        self.frames[index] = FrameInfo {
+            state: FrameState::Free,
+            order: order as u8,
+            prev: NIL,
+            next: head,
+        };
+        if head != NIL {
+            self.frames[head as usize].prev = index as u32;
+        }
+        self.free_list[order] = index as u32;
+    }
+
+    /// Remove the free block starting at index from its free list.
+    fn remove(&mut self, index: usize) {
+        let info = self.frames[index];
+        if info.prev == NIL {
+            self.free_list[info.order as usize] = info.next;
+        } else {
+            self.frames[info.prev as usize].next = info.next;
+        }
+        if info.next != NIL {
+            self.frames[info.next as usize].prev = info.prev;
+        }
+        self.frames[index].state = FrameState::Unused;
+    }
+
+    /// Whether the block of 2^order frames starting at ppn is a free block.
+    fn is_free_block(&self, ppn: PhysPageNum, order: usize) -> bool {
+        ppn >= self.base
+            && self.index(ppn) < self.frames.len()
+            && self.frames[self.index(ppn)].state == FrameState::Free
+            && self.frames[self.index(ppn)].order as usize == order
+    }
+}
+
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0.into(),
-            end: 0.into(),
-            recycled: Vec::new(),
+            base: PhysPageNum(0),
+            frames: &mut [],
+            free_list: [NIL; MAX_ORDER],
+            free_frames: 0,
         }
     }
 
-    fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn)
-        } else {
-            if self.current == self.end {
-                None
-            } else {
-                self.current.0 += 1;
-                Some((self.current.0 - 1).into())
-            }
+    fn alloc(&mut self, order: usize) -> Option<PhysPageNum> {
+        let mut current_order = (order..MAX_ORDER).find(|i| self.free_list[*i] != NIL)?;
+        let index = self.free_list[current_order] as usize;
+        self.remove(index);
+        let ppn = PhysPageNum(self.base.0 + index);
+        // Split the block, keeping its first half each time.
+        while current_order > order {
+            current_order -= 1;
+            self.push(PhysPageNum(ppn.0 + (1 << current_order)), current_order);
         }
+        self.frames[index].state = FrameState::Allocated;
+        self.frames[index].order = order as u8;
+        self.free_frames -= 1 << order;
+        Some(ppn)
     }
 
-    fn dealloc(&mut self, ppn: PhysPageNum) {
-        if ppn >= self.current || self.recycled.iter().find(|&v| *v == ppn).is_some() {
-            panic!("Frame ppn={:#x} has not been allocated!", ppn.0);
+    fn dealloc(&mut self, mut ppn: PhysPageNum, mut order: usize) {
+        let allocated = ppn >= self.base
+            && self.index(ppn) < self.frames.len()
+            && self.frames[self.index(ppn)].state == FrameState::Allocated
+            && self.frames[self.index(ppn)].order as usize == order;
+        if !allocated {
+            panic!(
+                "Frames ppn={:#x}, order={} have not been allocated!",
+                ppn.0, order
+            );
+        }
+        let index = self.index(ppn);
+        self.frames[index].state = FrameState::Unused;
+        self.free_frames += 1 << order;
+        // Merge the block with its buddy as long as the buddy is free.
+        while order + 1 < MAX_ORDER {
+            let buddy = PhysPageNum(ppn.0 ^ (1 << order));
+            if !self.is_free_block(buddy, order) {
+                break;
+            }
+            self.remove(self.index(buddy));
+            ppn = min(ppn, buddy);
+            order += 1;
         }
-        self.recycled.push(ppn);
+        self.push(ppn, order);
     }
-}
 
-impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l;
-        self.end = r;
+    fn free_frames(&self) -> usize {
+        self.free_frames
     }
 }
 
@@ -76,11 +200,50 @@ impl Drop for FrameTracker {
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+/// Like FrameTracker, but for 2^order contiguous frames starting at ppn.
+pub struct FrameRun {
+    pub ppn: PhysPageNum,
+    pub order: usize,
+}
+
+impl FrameRun {
+    pub fn new(ppn: PhysPageNum, order: usize) -> Self {
+        for i in 0..1 << order {
+            PhysPageNum(ppn.0 + i).get_bytes_array().fill(0);
+        }
+        Self {
+            ppn: ppn,
+            order: order,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        1 << self.order
+    }
+}
+
+impl Debug for FrameRun {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!(
+            "FrameRun: PPN={:#x}, order={}",
+            self.ppn.0, self.order
+        ))
+    }
+}
+
+impl Drop for FrameRun {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR
+            .exclusive_access()
+            .dealloc(self.ppn, self.order);
+    }
+}
+
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
-    static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
-        UPSafeCell::new(FrameAllocatorImpl::new());
+    static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> =
+        SpinLock::new(FrameAllocatorImpl::new());
 }
 
 pub fn init_frame_allocator() {
@@ -93,15 +256,75 @@ pub fn init_frame_allocator() {
     );
 }
 
+/// Allocate 2^order contiguous frames, leaving at least reserved frames free. When there are not enough free frames,
+/// user pages are swapped out to make room if swap.
+fn alloc_order(order: usize, swap: bool, reserved: usize) -> Option<PhysPageNum> {
+    loop {
+        let mut frame_allocator = FRAME_ALLOCATOR.exclusive_access();
+        if frame_allocator.free_frames() >= (1 << order) + reserved {
+            let ppn = frame_allocator.alloc(order);
+            if ppn.is_some() {
+                return ppn;
+            }
+        }
+        drop(frame_allocator);
+        if !swap {
+            return None;
+        }
+        // The frame allocator is not borrowed here, since swapping out a page frees its frame.
+        if !reclaim_frame() {
+            return None;
+        }
+    }
+}
+
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(|ppn| FrameTracker::new(ppn))
+    alloc_order(0, true, 0).map(|ppn| FrameTracker::new(ppn))
+}
+
+/// Like frame_alloc, but KERNEL_RESERVED_FRAMES frames are left free, so that a process using up the memory does not
+/// make the allocations of the kernel fail. Used for user pages.
+pub fn user_frame_alloc() -> Option<FrameTracker> {
+    alloc_order(0, true, KERNEL_RESERVED_FRAMES).map(|ppn| FrameTracker::new(ppn))
+}
+
+fn alloc_contiguous(count: usize, swap: bool, reserved: usize) -> Option<FrameRun> {
+    let order = count.next_power_of_two().trailing_zeros() as usize;
+    if order >= MAX_ORDER {
+        return None;
+    }
+    alloc_order(order, swap, reserved).map(|ppn| FrameRun::new(ppn, order))
+}
+
+/// Allocate at least count contiguous frames, starting at a ppn aligned to their number rounded up to a power of 2.
+pub fn frame_alloc_contiguous(count: usize) -> Option<FrameRun> {
+    alloc_contiguous(count, true, 0)
+}
+
+/// Like frame_alloc_contiguous, but for user pages, like user_frame_alloc.
+pub fn user_frame_alloc_contiguous(count: usize) -> Option<FrameRun> {
+    alloc_contiguous(count, true, KERNEL_RESERVED_FRAMES)
+}
+
+/// Like frame_alloc_contiguous, but only free frames are used. Used when the kernel heap grows, since swapping
+/// allocates and frees heap memory.
+pub fn frame_alloc_contiguous_without_swap(count: usize) -> Option<FrameRun> {
+    alloc_contiguous(count, false, 0)
+}
+
+/// Whether count frames can be allocated without using the frames reserved for the kernel, swapping out user pages
+/// to make room if needed. Used before creating processes and threads, whose kernel resources could use them up.
+pub fn frames_available(count: usize) -> bool {
+    while FRAME_ALLOCATOR.exclusive_access().free_frames() < count + KERNEL_RESERVED_FRAMES {
+        if !reclaim_frame() {
+            return false;
+        }
+    }
+    true
 }
 
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn, 0);
 }
 
 #[allow(unused)]
@@ -119,5 +342,10 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v);
+    let run = frame_alloc_contiguous(5).unwrap();
+    println!("{:?}", run);
+    assert_eq!(run.frame_count(), 8);
+    assert_eq!(run.ppn.0 % run.frame_count(), 0);
+    drop(run);
     println!("frame_allocator_test passed!");
 }
@@ -1,19 +1,68 @@
-use crate::{config::KERNEL_HEAP_SIZE, println};
-use buddy_allocator::LockedBuddyAllocator;
+use super::{
+    frame_allocator::frame_alloc_contiguous_without_swap,
+    slab::{object_cache_stats, SlabAllocator, SlabStats},
+    PhysAddr,
+};
+use crate::{
+    config::{KERNEL_HEAP_GROWTH, KERNEL_HEAP_SIZE, PAGE_SIZE},
+    println,
+};
+use alloc::vec::Vec;
+use buddy_allocator::{BuddyAllocator, HeapStats, LockedBuddyAllocator};
+use core::{alloc::Layout, cmp::max};
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::empty();
+static HEAP_ALLOCATOR: SlabAllocator =
+    SlabAllocator::new(LockedBuddyAllocator::with_rescue(grow_heap));
 
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap()
             .lock()
             .init(&raw const HEAP_SPACE as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+/// The usage of the buddy heap, which backs the slabs and the large objects.
+pub fn heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.heap().stats()
+}
+
+/// The statistics of the kmalloc caches, followed by those of the object caches that have been used.
+pub fn slab_stats() -> Vec<SlabStats> {
+    let mut stats = HEAP_ALLOCATOR.stats().to_vec();
+    stats.extend(object_cache_stats());
+    stats
+}
+
+/// The buddy heap, from which the object caches take their slabs.
+pub(super) fn heap() -> &'static LockedBuddyAllocator {
+    HEAP_ALLOCATOR.heap()
+}
+
+/// Add at least KERNEL_HEAP_GROWTH bytes of frames to the heap, so that the allocation of layout can be retried.
+/// The frames are never returned to the frame allocator.
+fn grow_heap(heap: &mut BuddyAllocator, layout: &Layout) {
+    // The frames are aligned to their size, but they begin with the bitmaps of the heap, so twice the size is needed
+    // to hold a block for layout.
+    let size = max(
+        2 * max(layout.size(), layout.align()).next_power_of_two(),
+        KERNEL_HEAP_GROWTH,
+    );
+    if let Some(frame_run) = frame_alloc_contiguous_without_swap(size / PAGE_SIZE) {
+        // The kernel address space maps the physical memory identically, so the frames are already mapped.
+        let start = PhysAddr::from(frame_run.ppn).0;
+        let size = frame_run.frame_count() * PAGE_SIZE;
+        core::mem::forget(frame_run);
+        unsafe {
+            heap.init(start, size);
+        }
+    }
+}
+
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error, layout = {:?}", layout);
@@ -41,5 +90,30 @@ pub fn heap_test() {
     }
     assert!(bss_range.contains(&(v.as_ptr() as usize)));
     drop(v);
+    // A 40-byte object is allocated from the 48-byte cache instead of a 64-byte buddy block.
+    let in_use = HEAP_ALLOCATOR.stats()[3].objects_in_use;
+    let b = Box::new([0u8; 40]);
+    assert_eq!(HEAP_ALLOCATOR.stats()[3].objects_in_use, in_use + 1);
+    drop(b);
+    for stats in slab_stats() {
+        if stats.slab_count > 0 {
+            println!(
+                "{}: {} objects of {}B in use, {} slabs of {}B, {} allocs, {} frees",
+                stats.name(),
+                stats.objects_in_use,
+                stats.object_size,
+                stats.slab_count,
+                stats.slab_size,
+                stats.alloc_count,
+                stats.free_count
+            );
+        }
+    }
+    let heap_stats = heap_stats();
+    assert_eq!(heap_stats.free + heap_stats.allocated, heap_stats.total);
+    println!(
+        "heap: {}B of {}B allocated for {}B requested, largest free block {}B",
+        heap_stats.allocated, heap_stats.total, heap_stats.requested, heap_stats.largest_free_block
+    );
     println!("heap_test passed!");
 }
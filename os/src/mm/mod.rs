@@ -1,4 +1,4 @@
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
 use alloc::sync::Arc;
 use lazy_static::lazy_static;
 
@@ -6,19 +6,32 @@ mod address;
 mod address_space;
 mod frame_allocator;
 mod heap_allocator;
+mod page;
+mod page_cache;
 mod page_table;
+mod shm;
+mod slab;
+mod swap;
+mod user_access;
+mod user_window;
 
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
-pub use address_space::{AddressSpace, Permission};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
-pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
-    PageTableEntry, PageTableView, UserBuffer, UserBufferIterator,
+pub use address_space::{AddressSpace, ElfInfo, MemoryUsage, OutOfMemory, Permission, RLimit};
+pub use buddy_allocator::HeapStats;
+pub use frame_allocator::{
+    frame_alloc, frame_alloc_contiguous, frame_dealloc, frames_available, user_frame_alloc,
+    user_frame_alloc_contiguous, FrameRun, FrameTracker,
 };
+pub use heap_allocator::{heap_stats, slab_stats};
+pub use page::PinnedPages;
+pub use page_cache::{find_page_cache, page_cache, PageCache};
+pub use page_table::{PageSize, PageTable, PageTableEntry, PageTableView};
+pub use slab::{CachedObject, ObjectCache, SlabStats};
+pub use user_access::{BadAddress, UserBuffer};
 
 lazy_static! {
-    static ref KERNEL_SPACE: Arc<UPSafeCell<AddressSpace>> =
-        Arc::new(UPSafeCell::new(AddressSpace::new_kernel()));
+    static ref KERNEL_SPACE: Arc<SpinLock<AddressSpace>> =
+        Arc::new(SpinLock::new(AddressSpace::new_kernel()));
 }
 
 pub fn kernel_satp() -> usize {
@@ -43,6 +56,11 @@ pub fn init() {
     KERNEL_SPACE.exclusive_access().activate();
 }
 
+/// Switch the current hart to the kernel address space, e.g. a hart other than the one running init when it boots.
+pub fn activate_kernel_space() {
+    KERNEL_SPACE.exclusive_access().activate();
+}
+
 #[allow(unused)]
 pub fn test() {
     heap_allocator::heap_test();
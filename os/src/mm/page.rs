@@ -0,0 +1,210 @@
+use super::{
+    page_table::PageTableView,
+    swap::{clock_insert, swap_in, swap_out, swap_slot_alloc, swap_slot_dealloc},
+    user_frame_alloc, FrameRun, FrameTracker, PhysPageNum, VirtPageNum,
+};
+use crate::sync::SpinLock;
+use alloc::{sync::Arc, vec::Vec};
+
+/// The data of a page, which is either in a frame or, if swapped out, in a slot of the swap area.
+pub struct Page {
+    /// Only swappable pages have it, which is the vpn they are mapped at in every owner.
+    swap_vpn: Option<VirtPageNum>,
+    inner: SpinLock<PageInner>,
+}
+
+enum PageData {
+    Frame(FrameTracker),
+    /// The contiguous frames of a huge page, which is never swapped out.
+    Frames(FrameRun),
+    /// The slot in the swap area.
+    Swapped(usize),
+}
+
+struct PageInner {
+    data: PageData,
+    /// The root ppns of the page tables mapping self, which are added and removed by PageRef.
+    owners: Vec<PhysPageNum>,
+}
+
+impl Page {
+    /// Return an unswappable page backed by frame.
+    pub fn new(frame: FrameTracker) -> Arc<Self> {
+        Self::with_swap_vpn(frame, None)
+    }
+
+    /// Return a page backed by frame, which may be swapped out while it is mapped at vpn by a single page table.
+    pub fn new_swappable(frame: FrameTracker, vpn: VirtPageNum) -> Arc<Self> {
+        Self::with_swap_vpn(frame, Some(vpn))
+    }
+
+    /// Return an unswappable huge page backed by the frames of run.
+    pub fn new_huge(run: FrameRun) -> Arc<Self> {
+        Arc::new(Self {
+            swap_vpn: None,
+            inner: SpinLock::new(PageInner {
+                data: PageData::Frames(run),
+                owners: Vec::new(),
+            }),
+        })
+    }
+
+    fn with_swap_vpn(frame: FrameTracker, swap_vpn: Option<VirtPageNum>) -> Arc<Self> {
+        let page = Arc::new(Self {
+            swap_vpn: swap_vpn,
+            inner: SpinLock::new(PageInner {
+                data: PageData::Frame(frame),
+                owners: Vec::new(),
+            }),
+        });
+        if swap_vpn.is_some() {
+            clock_insert(&page);
+        }
+        page
+    }
+
+    /// Return the ppn of the (first) frame holding self, or None if self is swapped out.
+    pub fn ppn(&self) -> Option<PhysPageNum> {
+        match &self.inner.exclusive_access().data {
+            PageData::Frame(frame) => Some(frame.ppn),
+            PageData::Frames(run) => Some(run.ppn),
+            PageData::Swapped(_) => None,
+        }
+    }
+
+    pub fn owner_count(&self) -> usize {
+        self.inner.exclusive_access().owners.len()
+    }
+
+    /// Like Page::ppn, but swap self in if it is swapped out. Return None if there is no frame left.
+    pub fn load(self: &Arc<Self>) -> Option<PhysPageNum> {
+        if let Some(ppn) = self.ppn() {
+            return Some(ppn);
+        }
+        // user_frame_alloc may swap out other pages, so self.inner is not borrowed during it.
+        let frame = user_frame_alloc()?;
+        let ppn = frame.ppn;
+        let mut inner = self.inner.exclusive_access();
+        let slot = match &inner.data {
+            PageData::Swapped(slot) => *slot,
+            // Another hart has swapped self in meanwhile, and frame is not needed.
+            PageData::Frame(frame) => return Some(frame.ppn),
+            PageData::Frames(run) => return Some(run.ppn),
+        };
+        swap_in(slot, ppn);
+        swap_slot_dealloc(slot);
+        inner.data = PageData::Frame(frame);
+        drop(inner);
+        clock_insert(self);
+        Some(ppn)
+    }
+
+    /// Swap self out if it is in memory, mapped by a single page table, not pinned, and not accessed since the last
+    /// visit of the clock hand. Return whether self is swapped out. The caller should hold a reference to self.
+    pub fn try_swap_out(self: &Arc<Self>) -> bool {
+        let vpn = match self.swap_vpn {
+            Some(vpn) => vpn,
+            None => return false,
+        };
+        // The other reference is held by the only owner.
+        if Arc::strong_count(self) != 2 {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        if inner.owners.len() != 1 {
+            return false;
+        }
+        let ppn = match &inner.data {
+            PageData::Frame(frame) => frame.ppn,
+            PageData::Frames(_) | PageData::Swapped(_) => return false,
+        };
+        let page_table_view = PageTableView::from_root_ppn(inner.owners[0]);
+        if page_table_view.test_and_clear_accessed(vpn) {
+            return false;
+        }
+        let slot = match swap_slot_alloc() {
+            Some(slot) => slot,
+            None => return false,
+        };
+        // The page is unmapped from the TLBs of all harts before it is written out, so that it is not written while
+        // in the swap area. A fault on it waits for the lock of self.
+        page_table_view.invalidate(vpn);
+        swap_out(slot, ppn);
+        inner.data = PageData::Swapped(slot);
+        true
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        if let PageData::Swapped(slot) = self.inner.exclusive_access().data {
+            swap_slot_dealloc(slot);
+        }
+    }
+}
+
+/// A reference to a page held by the page table with root_ppn.
+pub struct PageRef {
+    page: Arc<Page>,
+    root_ppn: PhysPageNum,
+    /// Whether the page of a file has been written through the page table since it was last written back.
+    dirty: bool,
+}
+
+impl PageRef {
+    pub fn new(page: Arc<Page>, root_ppn: PhysPageNum) -> Self {
+        page.inner.exclusive_access().owners.push(root_ppn);
+        Self {
+            page: page,
+            root_ppn: root_ppn,
+            dirty: false,
+        }
+    }
+
+    pub fn page(&self) -> &Arc<Page> {
+        &self.page
+    }
+
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn set_dirty(&mut self, dirty: bool) {
+        self.dirty = dirty;
+    }
+
+    /// Return a reference to the same page held by the page table with root_ppn.
+    pub fn share(&self, root_ppn: PhysPageNum) -> Self {
+        Self::new(self.page.clone(), root_ppn)
+    }
+}
+
+impl Drop for PageRef {
+    fn drop(&mut self) {
+        let mut inner = self.page.inner.exclusive_access();
+        let index = inner
+            .owners
+            .iter()
+            .position(|root_ppn| *root_ppn == self.root_ppn)
+            .unwrap();
+        inner.owners.swap_remove(index);
+    }
+}
+
+/// The pages that must stay in memory while the kernel accesses them.
+/// They are unpinned when it is dropped.
+pub struct PinnedPages(Vec<Arc<Page>>);
+
+impl PinnedPages {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn push(&mut self, page: Arc<Page>) {
+        self.0.push(page);
+    }
+
+    pub fn append(&mut self, other: &mut Self) {
+        self.0.append(&mut other.0);
+    }
+}
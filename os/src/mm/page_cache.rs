@@ -0,0 +1,136 @@
+use super::{page::Page, user_frame_alloc};
+use crate::{config::PAGE_SIZE, sync::SpinLock};
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::cmp::{max, min};
+use easy_fs::Inode;
+use lazy_static::lazy_static;
+
+/// The pages of a file cached in memory, which are shared by the shared mappings of the file.
+/// A page is read from the file on its first access, and written back to it by the mappings that dirty it.
+pub struct PageCache {
+    inode: Arc<Inode>,
+    /// The pages indexed by their offsets in the file divided by PAGE_SIZE. They are never swapped out.
+    pages: SpinLock<BTreeMap<usize, Arc<Page>>>,
+}
+
+lazy_static! {
+    /// The page caches indexed by the ids of their inodes. A page cache is removed when it is no longer used.
+    static ref PAGE_CACHES: SpinLock<BTreeMap<usize, Weak<PageCache>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+impl PageCache {
+    pub fn size(&self) -> usize {
+        self.inode.size()
+    }
+
+    /// Return the page at index, which is read from the file if it is not cached. Return None if there is no frame
+    /// left. The part of the page beyond the end of the file is zero-filled.
+    pub fn page(&self, index: usize) -> Option<Arc<Page>> {
+        if let Some(page) = self.pages.exclusive_access().get(&index) {
+            return Some(page.clone());
+        }
+        // user_frame_alloc may swap out other pages, so self.pages is not borrowed during it.
+        let frame = user_frame_alloc()?;
+        self.inode
+            .read_at(index * PAGE_SIZE, frame.ppn.get_bytes_array());
+        let page = Page::new(frame);
+        self.pages.exclusive_access().insert(index, page.clone());
+        Some(page)
+    }
+
+    /// Like Inode::read_at, but the cached pages are read instead of the file, since they may be newer.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let end = min(offset + buf.len(), self.size());
+        let mut start = offset;
+        while start < end {
+            let index = start / PAGE_SIZE;
+            let page_end = min((index + 1) * PAGE_SIZE, end);
+            let dest = &mut buf[start - offset..page_end - offset];
+            match self.pages.exclusive_access().get(&index) {
+                Some(page) => {
+                    let page_offset = start % PAGE_SIZE;
+                    dest.copy_from_slice(
+                        &page.ppn().unwrap().get_bytes_array()
+                            [page_offset..page_offset + dest.len()],
+                    );
+                }
+                None => {
+                    self.inode.read_at(start, dest);
+                }
+            }
+            start = page_end;
+        }
+        end.saturating_sub(offset)
+    }
+
+    /// Like Inode::write_at, but the cached pages are updated as well.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let size = self.inode.write_at(offset, buf);
+        for (index, page) in self
+            .pages
+            .exclusive_access()
+            .range(offset / PAGE_SIZE..(offset + size).div_ceil(PAGE_SIZE))
+        {
+            let start = max(offset, index * PAGE_SIZE);
+            let end = min(offset + size, (index + 1) * PAGE_SIZE);
+            page.ppn().unwrap().get_bytes_array()[start % PAGE_SIZE..end - index * PAGE_SIZE]
+                .copy_from_slice(&buf[start - offset..end - offset]);
+        }
+        size
+    }
+
+    /// Write the page at index back to the file. The part of the page beyond the end of the file is dropped, since
+    /// a mapping never extends the file.
+    pub fn write_back(&self, index: usize) {
+        let page = match self.pages.exclusive_access().get(&index) {
+            Some(page) => page.clone(),
+            None => return,
+        };
+        let start = index * PAGE_SIZE;
+        let size = self.size();
+        if start < size {
+            let len = min(PAGE_SIZE, size - start);
+            self.inode
+                .write_at(start, &page.ppn().unwrap().get_bytes_array()[..len]);
+        }
+    }
+
+    /// Zero-fill the cached pages after the file is truncated to 0 bytes.
+    pub fn clear(&self) {
+        for page in self.pages.exclusive_access().values() {
+            page.ppn().unwrap().get_bytes_array().fill(0);
+        }
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        PAGE_CACHES.exclusive_access().remove(&self.inode.id());
+    }
+}
+
+/// Return the page cache of inode, which is created if the file has none.
+pub fn page_cache(inode: &Arc<Inode>) -> Arc<PageCache> {
+    let mut page_caches = PAGE_CACHES.exclusive_access();
+    if let Some(page_cache) = page_caches.get(&inode.id()).and_then(Weak::upgrade) {
+        return page_cache;
+    }
+    let page_cache = Arc::new(PageCache {
+        inode: inode.clone(),
+        pages: SpinLock::new(BTreeMap::new()),
+    });
+    page_caches.insert(inode.id(), Arc::downgrade(&page_cache));
+    page_cache
+}
+
+/// Return the page cache of inode if the file is mapped.
+pub fn find_page_cache(inode: &Inode) -> Option<Arc<PageCache>> {
+    PAGE_CACHES
+        .exclusive_access()
+        .get(&inode.id())
+        .and_then(Weak::upgrade)
+}
@@ -1,10 +1,12 @@
+use alloc::vec;
 use alloc::vec::Vec;
-use alloc::{string::String, vec};
 use bitflags::bitflags;
 
+use crate::ipi::shootdown;
+
 use super::PhysAddr;
 use super::{
-    address::{PhysPageNum, StepByOne, VirtPageNum},
+    address::{PhysPageNum, VirtPageNum},
     address_space::Permission,
     frame_allocator::{frame_alloc, FrameTracker},
     VirtAddr,
@@ -23,6 +25,44 @@ bitflags! {
     }
 }
 
+/// The size of the page mapped by a leaf PTE. Sv39 supports megapages (2MiB) and gigapages (1GiB), which are
+/// mapped by the leaf PTEs at the second and first level of the page table.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageSize {
+    Page,
+    MegaPage,
+    GigaPage,
+}
+
+impl PageSize {
+    /// The number of 4KiB pages in a page of self. A page of self is aligned to its size, both virtually and
+    /// physically.
+    pub fn page_count(&self) -> usize {
+        match self {
+            PageSize::Page => 1,
+            PageSize::MegaPage => 1 << 9,
+            PageSize::GigaPage => 1 << 18,
+        }
+    }
+
+    /// The level of the leaf PTEs mapping the pages of self, where the root page table is at level 0.
+    fn level(&self) -> usize {
+        match self {
+            PageSize::Page => 2,
+            PageSize::MegaPage => 1,
+            PageSize::GigaPage => 0,
+        }
+    }
+
+    fn from_level(level: usize) -> Self {
+        match level {
+            0 => PageSize::GigaPage,
+            1 => PageSize::MegaPage,
+            _ => PageSize::Page,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct PageTableEntry {
@@ -63,6 +103,22 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+
+    pub fn user(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
+
+    /// Whether self maps a page instead of pointing to the next level of the page table.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid()
+            && self
+                .flags()
+                .intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
 }
 
 pub struct PageTable {
@@ -79,12 +135,17 @@ impl PageTable {
         }
     }
 
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&'static mut PageTableEntry> {
+    /// Return the PTE at the level of page_size for vpn, creating the page tables above it.
+    fn find_pte_create(
+        &mut self,
+        vpn: VirtPageNum,
+        page_size: PageSize,
+    ) -> Option<&'static mut PageTableEntry> {
         let id = vpn.indexes();
         let mut ppn = self.root_ppn;
         for i in 0..3 {
             let pte = &mut ppn.get_pte_array()[id[i]];
-            if i == 2 {
+            if i == page_size.level() {
                 return Some(pte);
             }
             if !pte.is_valid() {
@@ -92,17 +153,44 @@ impl PageTable {
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is in a mapped huge page", vpn);
             ppn = pte.ppn();
         }
         None
     }
 
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&'static mut PageTableEntry> {
-        PageTableView::from_page_table(self).find_pte(vpn)
+    /// Return the leaf PTE mapping vpn, which should be the first page of the page mapped by it.
+    fn find_leaf_pte(&self, vpn: VirtPageNum) -> Option<&'static mut PageTableEntry> {
+        let (pte, page_size) = PageTableView::from_page_table(self).find_pte(vpn)?;
+        assert_eq!(
+            vpn.0 % page_size.page_count(),
+            0,
+            "vpn {:?} is in the middle of a huge page",
+            vpn
+        );
+        Some(pte)
     }
 
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, permission: Permission) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        self.map_huge(vpn, ppn, PageSize::Page, permission);
+    }
+
+    /// Map the page of page_size starting at vpn to the frames starting at ppn, which should both be aligned to it.
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        page_size: PageSize,
+        permission: Permission,
+    ) {
+        assert!(
+            vpn.0 % page_size.page_count() == 0 && ppn.0 % page_size.page_count() == 0,
+            "vpn {:?} or ppn {:?} is not aligned to {:?}",
+            vpn,
+            ppn,
+            page_size
+        );
+        let pte = self.find_pte_create(vpn, page_size).unwrap();
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(
             ppn,
@@ -110,10 +198,25 @@ impl PageTable {
         );
     }
 
+    /// Point the mapped page vpn to ppn with a new permission. A huge page is remapped as a whole. The old mapping is
+    /// flushed from the TLBs of the other harts.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, permission: Permission) {
+        let pte = self.find_leaf_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(
+            ppn,
+            PTEFlags::from_bits(permission.bits()).unwrap() | PTEFlags::V,
+        );
+        shootdown(self.satp());
+    }
+
+    /// Unmap the page vpn. A huge page is unmapped as a whole. The old mapping is flushed from the TLBs of the other
+    /// harts.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte(vpn).unwrap();
+        let pte = self.find_leaf_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        shootdown(self.satp());
     }
 
     pub fn view(&self) -> PageTableView {
@@ -125,6 +228,15 @@ impl PageTable {
     pub fn satp(&self) -> usize {
         0b1000usize << 60 | self.root_ppn.0
     }
+
+    pub fn root_ppn(&self) -> PhysPageNum {
+        self.root_ppn
+    }
+
+    /// The number of frames holding self, including the root.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
 }
 
 pub struct PageTableView {
@@ -144,13 +256,18 @@ impl PageTableView {
         }
     }
 
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&'static mut PageTableEntry> {
+    pub fn from_root_ppn(root_ppn: PhysPageNum) -> Self {
+        Self { root_ppn: root_ppn }
+    }
+
+    /// Return the PTE for vpn and the size of the page it maps. The walk stops at a leaf PTE, or at the last level.
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&'static mut PageTableEntry, PageSize)> {
         let id = vpn.indexes();
         let mut ppn = self.root_ppn;
         for i in 0..3 {
             let pte = &mut ppn.get_pte_array()[id[i]];
-            if i == 2 {
-                return Some(pte);
+            if i == 2 || pte.is_leaf() {
+                return Some((pte, PageSize::from_level(i)));
             }
             if !pte.is_valid() {
                 return None;
@@ -160,125 +277,47 @@ impl PageTableView {
         None
     }
 
+    /// Return the PTE for vpn. If vpn is in a huge page, the result is the PTE as if vpn were mapped by a 4KiB page.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).map(|pte| pte.clone())
+        self.find_pte(vpn).map(|(pte, page_size)| {
+            if page_size == PageSize::Page {
+                *pte
+            } else {
+                let offset = vpn.0 % page_size.page_count();
+                PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+            }
+        })
     }
 
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.floor())
+        self.translate(va.floor())
             .map(|pte| (usize::from(PhysAddr::from(pte.ppn())) + va.page_offset()).into())
     }
 
-    #[allow(unused)]
-    pub fn satp(&self) -> usize {
-        0b1000usize << 60 | self.root_ppn.0
-    }
-}
-
-pub fn translated_byte_buffer(satp: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table_view = PageTableView::from_satp(satp);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
-        let mut vpn = start_va.floor();
-        let ppn = page_table_view.translate(vpn).unwrap().ppn();
-        vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = core::cmp::min(end_va, end.into());
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
-        } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
-    }
-    v
-}
-
-pub fn translated_str(satp: usize, ptr: *const u8) -> String {
-    let page_table_view = PageTableView::from_satp(satp);
-    let mut string = String::new();
-    let mut va = ptr as usize;
-    loop {
-        let ch: u8 = *page_table_view.translate_va(va.into()).unwrap().get_mut();
-        if ch == b'\0' {
-            return string;
-        } else {
-            string.push(ch as char);
-            va += 1;
-        }
-    }
-}
-
-pub fn translated_ref<T>(satp: usize, ptr: *const T) -> &'static T {
-    PageTableView::from_satp(satp)
-        .translate_va((ptr as usize).into())
-        .unwrap()
-        .get_ref()
-}
-
-pub fn translated_refmut<T>(satp: usize, ptr: *mut T) -> &'static mut T {
-    PageTableView::from_satp(satp)
-        .translate_va((ptr as usize).into())
-        .unwrap()
-        .get_mut()
-}
-
-/// Abstract the result of translated_byte_buffer as &[u8].
-pub struct UserBuffer {
-    pub buffers: Vec<&'static mut [u8]>,
-}
-
-impl UserBuffer {
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers: buffers }
-    }
-
-    pub fn len(&self) -> usize {
-        let mut length: usize = 0;
-        for b in self.buffers.iter() {
-            length += b.len();
+    /// Clear the accessed bit of the PTE of vpn. Return whether it was set.
+    pub fn test_and_clear_accessed(&self, vpn: VirtPageNum) -> bool {
+        match self.find_pte(vpn) {
+            Some((pte, _)) if pte.is_valid() && pte.accessed() => {
+                *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+                true
+            }
+            _ => false,
         }
-        length
     }
-}
-
-impl IntoIterator for UserBuffer {
-    type Item = *mut u8;
-    type IntoIter = UserBufferIterator;
 
-    fn into_iter(self) -> Self::IntoIter {
-        Self::IntoIter {
-            buffers: self.buffers,
-            buffer_id: 0,
-            offset: 0,
+    /// Invalidate the PTE of vpn if it is valid. Unlike PageTable::unmap, the page table is not borrowed, so this
+    /// can be used on the page table of another address space. The old mapping is flushed from the TLBs of the other
+    /// harts.
+    pub fn invalidate(&self, vpn: VirtPageNum) {
+        if let Some((pte, _)) = self.find_pte(vpn) {
+            if pte.is_valid() {
+                *pte = PageTableEntry::empty();
+                shootdown(self.satp());
+            }
         }
     }
-}
 
-pub struct UserBufferIterator {
-    buffers: Vec<&'static mut [u8]>,
-    buffer_id: usize,
-    offset: usize,
-}
-
-impl Iterator for UserBufferIterator {
-    type Item = *mut u8;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.buffer_id >= self.buffers.len() {
-            None
-        } else {
-            let cur = &mut self.buffers[self.buffer_id][self.offset] as *mut _;
-            if self.offset + 1 == self.buffers[self.buffer_id].len() {
-                self.buffer_id += 1;
-                self.offset = 0;
-            } else {
-                self.offset += 1;
-            }
-            Some(cur)
-        }
+    pub fn satp(&self) -> usize {
+        0b1000usize << 60 | self.root_ppn.0
     }
 }
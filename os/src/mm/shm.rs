@@ -0,0 +1,107 @@
+use super::{page::Page, user_frame_alloc};
+use crate::{
+    config::{PAGE_SIZE, SHM_MAX_PAGES},
+    sync::SpinLock,
+};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+
+/// shm_get always creates a new shared memory for IPC_PRIVATE.
+pub const IPC_PRIVATE: usize = 0;
+
+pub struct SharedMemory {
+    id: usize,
+    key: usize,
+    /// The pages are never swapped out.
+    pages: Vec<Arc<Page>>,
+}
+
+lazy_static! {
+    /// The shared memories indexed by id.
+    static ref SHM_MANAGER: SpinLock<BTreeMap<usize, Arc<SharedMemory>>> =
+        SpinLock::new(BTreeMap::new());
+}
+
+/// Held by each segment the shared memory is attached to, and by the address space creating it. When the last
+/// attachment is dropped, the shared memory is removed, and its pages are freed. The attachments are only cloned and
+/// dropped while SHM_MANAGER is locked, so that the last one is known.
+pub struct ShmAttachment(Option<Arc<SharedMemory>>);
+
+impl ShmAttachment {
+    pub fn page(&self, index: usize) -> Arc<Page> {
+        self.0.as_ref().unwrap().pages[index].clone()
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.0.as_ref().unwrap().pages.len()
+    }
+}
+
+impl Clone for ShmAttachment {
+    fn clone(&self) -> Self {
+        let _manager = SHM_MANAGER.exclusive_access();
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for ShmAttachment {
+    fn drop(&mut self) {
+        let mut manager = SHM_MANAGER.exclusive_access();
+        let id = self.0.take().unwrap().id;
+        // The other reference is held by SHM_MANAGER.
+        if manager
+            .get(&id)
+            .is_some_and(|shm| Arc::strong_count(shm) == 1)
+        {
+            manager.remove(&id);
+        }
+    }
+}
+
+/// Return the id of the shared memory with key, which is created with size bytes if it does not exist. A shared
+/// memory is only created with at most page_limit pages, and while the shared memories have at most SHM_MAX_PAGES
+/// pages in total. The attachment of the creator is returned along with a created shared memory.
+pub fn shm_get(
+    key: usize,
+    size: usize,
+    page_limit: usize,
+) -> Option<(usize, Option<ShmAttachment>)> {
+    let mut manager = SHM_MANAGER.exclusive_access();
+    if key != IPC_PRIVATE {
+        if let Some(shm) = manager.values().find(|shm| shm.key == key) {
+            return (size <= shm.pages.len() * PAGE_SIZE).then_some((shm.id, None));
+        }
+    }
+    let page_count = size.div_ceil(PAGE_SIZE);
+    let total_pages: usize = manager.values().map(|shm| shm.pages.len()).sum();
+    if size == 0 || page_count > page_limit || total_pages + page_count > SHM_MAX_PAGES {
+        return None;
+    }
+    let mut pages = Vec::new();
+    for _ in 0..page_count {
+        pages.push(Page::new(user_frame_alloc()?));
+    }
+    let id = manager.keys().next_back().map_or(0, |id| id + 1);
+    let shm = Arc::new(SharedMemory {
+        id: id,
+        key: key,
+        pages: pages,
+    });
+    manager.insert(id, shm.clone());
+    Some((id, Some(ShmAttachment(Some(shm)))))
+}
+
+pub fn shm_page_count(id: usize) -> Option<usize> {
+    SHM_MANAGER
+        .exclusive_access()
+        .get(&id)
+        .map(|shm| shm.pages.len())
+}
+
+/// The attachment should be held by a segment, since dropping it may remove the shared memory.
+pub fn shm_attachment(id: usize) -> Option<ShmAttachment> {
+    SHM_MANAGER
+        .exclusive_access()
+        .get(&id)
+        .map(|shm| ShmAttachment(Some(shm.clone())))
+}
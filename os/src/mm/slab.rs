@@ -0,0 +1,377 @@
+use super::heap_allocator::heap;
+use crate::{config::PAGE_SIZE, sync::SpinLock};
+use alloc::vec::Vec;
+use buddy_allocator::LockedBuddyAllocator;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cmp::min,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Placed at the end of each slab, so that the objects start at the slab boundary and are aligned to the lowest
+/// set bit of the object size.
+struct SlabHeader {
+    prev: *mut SlabHeader,
+    next: *mut SlabHeader,
+    free_list: *mut FreeObject,
+    used: usize,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// The length of SlabStats::name.
+const SLAB_NAME_LEN: usize = 24;
+
+/// The statistics of a cache, which are also reported to user mode.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SlabStats {
+    /// The name of the cache, padded with zeros.
+    pub name: [u8; SLAB_NAME_LEN],
+    pub object_size: usize,
+    pub slab_size: usize,
+    pub slab_count: usize,
+    pub objects_in_use: usize,
+    pub alloc_count: usize,
+    pub free_count: usize,
+}
+
+impl SlabStats {
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(SLAB_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap()
+    }
+}
+
+/// A cache of objects of the same size, which are carved from slabs allocated from the buddy heap.
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    /// The distance between the objects in a slab.
+    stride: usize,
+    /// The offset of the free list link in a free object. It follows the object if there is a ctor, so that it does
+    /// not overwrite the constructed state.
+    free_offset: usize,
+    /// A power of 2. Each slab is aligned to its size.
+    slab_size: usize,
+    /// Called on every object of a new slab. The objects should be returned to the cache in the constructed state.
+    ctor: Option<fn(*mut u8)>,
+    /// The slabs with free objects. Full slabs are not tracked until an object of them is freed.
+    partial: *mut SlabHeader,
+    slab_count: usize,
+    objects_in_use: usize,
+    alloc_count: usize,
+    free_count: usize,
+}
+
+impl SlabCache {
+    pub const fn new(name: &'static str, object_size: usize, ctor: Option<fn(*mut u8)>) -> Self {
+        let (stride, free_offset) = if ctor.is_some() {
+            let free_offset = object_size.next_multiple_of(size_of::<FreeObject>());
+            (free_offset + size_of::<FreeObject>(), free_offset)
+        } else {
+            (object_size, 0)
+        };
+        assert!(stride >= size_of::<FreeObject>());
+        // Each slab holds at least 7 objects.
+        let mut slab_size = (stride * 8).next_power_of_two();
+        if slab_size < PAGE_SIZE {
+            slab_size = PAGE_SIZE;
+        }
+        Self {
+            name: name,
+            object_size: object_size,
+            stride: stride,
+            free_offset: free_offset,
+            slab_size: slab_size,
+            ctor: ctor,
+            partial: null_mut(),
+            slab_count: 0,
+            objects_in_use: 0,
+            alloc_count: 0,
+            free_count: 0,
+        }
+    }
+
+    /// The alignment of every object.
+    const fn align(&self) -> usize {
+        1 << self.stride.trailing_zeros()
+    }
+
+    fn fits(&self, layout: &Layout) -> bool {
+        layout.size() <= self.object_size && layout.align() <= self.align()
+    }
+
+    fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size, self.slab_size).unwrap()
+    }
+
+    fn object_count(&self) -> usize {
+        (self.slab_size - size_of::<SlabHeader>()) / self.stride
+    }
+
+    unsafe fn link(&self, object: *mut u8) -> *mut FreeObject {
+        object.add(self.free_offset) as *mut FreeObject
+    }
+
+    fn header(&self, object: *mut u8) -> *mut SlabHeader {
+        let slab = object as usize & !(self.slab_size - 1);
+        (slab + self.slab_size - size_of::<SlabHeader>()) as *mut SlabHeader
+    }
+
+    unsafe fn push_partial(&mut self, slab: *mut SlabHeader) {
+        (*slab).prev = null_mut();
+        (*slab).next = self.partial;
+        if !self.partial.is_null() {
+            (*self.partial).prev = slab;
+        }
+        self.partial = slab;
+    }
+
+    unsafe fn remove_partial(&mut self, slab: *mut SlabHeader) {
+        if (*slab).prev.is_null() {
+            self.partial = (*slab).next;
+        } else {
+            (*(*slab).prev).next = (*slab).next;
+        }
+        if !(*slab).next.is_null() {
+            (*(*slab).next).prev = (*slab).prev;
+        }
+    }
+
+    /// Add a new slab to self.partial. Return false if the heap is exhausted.
+    unsafe fn grow(&mut self, heap: &LockedBuddyAllocator) -> bool {
+        let slab = heap.alloc(self.slab_layout());
+        if slab.is_null() {
+            return false;
+        }
+        let header = self.header(slab);
+        (*header).free_list = null_mut();
+        (*header).used = 0;
+        for i in (0..self.object_count()).rev() {
+            let object = slab.add(i * self.stride);
+            if let Some(ctor) = self.ctor {
+                ctor(object);
+            }
+            let free_object = self.link(object);
+            (*free_object).next = (*header).free_list;
+            (*header).free_list = free_object;
+        }
+        self.push_partial(header);
+        self.slab_count += 1;
+        true
+    }
+
+    unsafe fn alloc(&mut self, heap: &LockedBuddyAllocator) -> *mut u8 {
+        if self.partial.is_null() && !self.grow(heap) {
+            return null_mut();
+        }
+        let slab = self.partial;
+        let free_object = (*slab).free_list;
+        (*slab).free_list = (*free_object).next;
+        (*slab).used += 1;
+        if (*slab).free_list.is_null() {
+            self.remove_partial(slab);
+        }
+        self.objects_in_use += 1;
+        self.alloc_count += 1;
+        (free_object as *mut u8).sub(self.free_offset)
+    }
+
+    unsafe fn dealloc(&mut self, heap: &LockedBuddyAllocator, object: *mut u8) {
+        let slab = self.header(object);
+        let was_full = (*slab).free_list.is_null();
+        let free_object = self.link(object);
+        (*free_object).next = (*slab).free_list;
+        (*slab).free_list = free_object;
+        (*slab).used -= 1;
+        if was_full {
+            self.push_partial(slab);
+        }
+        self.objects_in_use -= 1;
+        self.free_count += 1;
+        // An empty slab is kept if it is the only partial one, so that a single object allocated and freed
+        // repeatedly does not create a new slab each time.
+        if (*slab).used == 0 && !(self.partial == slab && (*slab).next.is_null()) {
+            self.remove_partial(slab);
+            heap.dealloc(
+                (slab as usize + size_of::<SlabHeader>() - self.slab_size) as *mut u8,
+                self.slab_layout(),
+            );
+            self.slab_count -= 1;
+        }
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let mut name = [0; SLAB_NAME_LEN];
+        let len = min(self.name.len(), SLAB_NAME_LEN);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        SlabStats {
+            name: name,
+            object_size: self.object_size,
+            slab_size: self.slab_size,
+            slab_count: self.slab_count,
+            objects_in_use: self.objects_in_use,
+            alloc_count: self.alloc_count,
+            free_count: self.free_count,
+        }
+    }
+}
+
+/// The object caches that have been used, which are reported along with the caches of SlabAllocator.
+static OBJECT_CACHES: SpinLock<Vec<&'static SpinLock<SlabCache>>> = SpinLock::new(Vec::new());
+
+/// A cache of objects of type T. Each object is constructed by ctor once, when its slab is created, and must be
+/// returned to the cache in the constructed state, so that it is not constructed again for the next user.
+pub struct ObjectCache<T> {
+    cache: SpinLock<SlabCache>,
+    registered: AtomicBool,
+    _marker: PhantomData<T>,
+}
+
+impl<T> ObjectCache<T> {
+    /// ctor is passed a pointer to the memory of a T, which it should initialize.
+    pub const fn new(name: &'static str, ctor: fn(*mut u8)) -> Self {
+        let cache = SlabCache::new(name, size_of::<T>(), Some(ctor));
+        assert!(align_of::<T>() <= cache.align());
+        Self {
+            cache: SpinLock::new(cache),
+            registered: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Return None if the heap is exhausted.
+    pub fn alloc(&'static self) -> Option<CachedObject<T>> {
+        if !self.registered.swap(true, Ordering::Relaxed) {
+            OBJECT_CACHES.exclusive_access().push(&self.cache);
+        }
+        let object = unsafe { self.cache.exclusive_access().alloc(heap()) };
+        NonNull::new(object as *mut T).map(|object| CachedObject {
+            cache: self,
+            object: object,
+        })
+    }
+}
+
+/// An object of an ObjectCache, which is returned to the cache when dropped. The object itself is not dropped, since
+/// it should be in the constructed state.
+pub struct CachedObject<T: 'static> {
+    cache: &'static ObjectCache<T>,
+    object: NonNull<T>,
+}
+
+unsafe impl<T: Send> Send for CachedObject<T> {}
+unsafe impl<T: Sync> Sync for CachedObject<T> {}
+
+impl<T> Deref for CachedObject<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for CachedObject<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for CachedObject<T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.cache
+                .cache
+                .exclusive_access()
+                .dealloc(heap(), self.object.as_ptr() as *mut u8);
+        }
+    }
+}
+
+/// The statistics of the object caches that have been used.
+pub fn object_cache_stats() -> Vec<SlabStats> {
+    OBJECT_CACHES
+        .exclusive_access()
+        .iter()
+        .map(|cache| cache.exclusive_access().stats())
+        .collect()
+}
+
+pub const SLAB_CACHE_COUNT: usize = 15;
+
+/// Small objects are allocated from the cache of the smallest size class fitting them, and the others are
+/// allocated from the buddy heap directly.
+pub struct SlabAllocator {
+    caches: SpinLock<[SlabCache; SLAB_CACHE_COUNT]>,
+    heap: LockedBuddyAllocator,
+}
+
+impl SlabAllocator {
+    /// heap backs the slabs and the large objects.
+    pub const fn new(heap: LockedBuddyAllocator) -> Self {
+        Self {
+            caches: SpinLock::new([
+                SlabCache::new("kmalloc-8", 8, None),
+                SlabCache::new("kmalloc-16", 16, None),
+                SlabCache::new("kmalloc-32", 32, None),
+                SlabCache::new("kmalloc-48", 48, None),
+                SlabCache::new("kmalloc-64", 64, None),
+                SlabCache::new("kmalloc-96", 96, None),
+                SlabCache::new("kmalloc-128", 128, None),
+                SlabCache::new("kmalloc-192", 192, None),
+                SlabCache::new("kmalloc-256", 256, None),
+                SlabCache::new("kmalloc-384", 384, None),
+                SlabCache::new("kmalloc-512", 512, None),
+                SlabCache::new("kmalloc-768", 768, None),
+                SlabCache::new("kmalloc-1024", 1024, None),
+                SlabCache::new("kmalloc-1536", 1536, None),
+                SlabCache::new("kmalloc-2048", 2048, None),
+            ]),
+            heap: heap,
+        }
+    }
+
+    /// The buddy heap backing the slabs.
+    pub fn heap(&self) -> &LockedBuddyAllocator {
+        &self.heap
+    }
+
+    fn cache_index(&self, caches: &[SlabCache], layout: &Layout) -> Option<usize> {
+        caches.iter().position(|cache| cache.fits(layout))
+    }
+
+    pub fn stats(&self) -> [SlabStats; SLAB_CACHE_COUNT] {
+        self.caches
+            .exclusive_access()
+            .each_ref()
+            .map(|cache| cache.stats())
+    }
+}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut caches = self.caches.exclusive_access();
+        match self.cache_index(caches.as_slice(), &layout) {
+            Some(index) => caches[index].alloc(&self.heap),
+            None => self.heap.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut caches = self.caches.exclusive_access();
+        match self.cache_index(caches.as_slice(), &layout) {
+            Some(index) => caches[index].dealloc(&self.heap, ptr),
+            None => self.heap.dealloc(ptr, layout),
+        }
+    }
+}
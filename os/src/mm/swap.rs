@@ -0,0 +1,119 @@
+use super::{page::Page, PhysPageNum};
+use crate::{
+    config::{PAGE_SIZE, SWAP_SIZE, SWAP_START_BLOCK},
+    drivers::BLOCK_DEVICE,
+    sync::SpinLock,
+};
+use alloc::{
+    collections::vec_deque::VecDeque,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use easy_fs::BLOCK_SZ;
+use lazy_static::lazy_static;
+
+const SLOT_COUNT: usize = SWAP_SIZE / PAGE_SIZE;
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SZ;
+
+/// Each bit records whether a slot of the swap area is in use.
+struct SwapSlotAllocator {
+    bitmap: Vec<u64>,
+}
+
+impl SwapSlotAllocator {
+    fn new() -> Self {
+        Self {
+            bitmap: vec![0; SLOT_COUNT.div_ceil(64)],
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        let (index, bits) = self
+            .bitmap
+            .iter_mut()
+            .enumerate()
+            .find(|(_, bits)| **bits != u64::MAX)?;
+        let offset = bits.trailing_ones() as usize;
+        let slot = index * 64 + offset;
+        if slot >= SLOT_COUNT {
+            return None;
+        }
+        *bits |= 1 << offset;
+        Some(slot)
+    }
+
+    fn dealloc(&mut self, slot: usize) {
+        let bits = &mut self.bitmap[slot / 64];
+        assert!(
+            *bits & (1 << (slot % 64)) != 0,
+            "swap slot {} is not in use!",
+            slot
+        );
+        *bits &= !(1 << (slot % 64));
+    }
+}
+
+lazy_static! {
+    static ref SWAP_SLOT_ALLOCATOR: SpinLock<SwapSlotAllocator> =
+        SpinLock::new(SwapSlotAllocator::new());
+    /// The swappable pages in memory, in the order the clock hand visits them.
+    /// Dropped pages are removed when the hand reaches them.
+    static ref CLOCK: SpinLock<VecDeque<Weak<Page>>> = SpinLock::new(VecDeque::new());
+}
+
+pub fn swap_slot_alloc() -> Option<usize> {
+    SWAP_SLOT_ALLOCATOR.exclusive_access().alloc()
+}
+
+pub fn swap_slot_dealloc(slot: usize) {
+    SWAP_SLOT_ALLOCATOR.exclusive_access().dealloc(slot);
+}
+
+pub fn swap_out(slot: usize, ppn: PhysPageNum) {
+    let bytes = ppn.get_bytes_array();
+    for i in 0..BLOCKS_PER_SLOT {
+        BLOCK_DEVICE.write_block(
+            SWAP_START_BLOCK + slot * BLOCKS_PER_SLOT + i,
+            &bytes[i * BLOCK_SZ..(i + 1) * BLOCK_SZ],
+        );
+    }
+}
+
+pub fn swap_in(slot: usize, ppn: PhysPageNum) {
+    let bytes = ppn.get_bytes_array();
+    for i in 0..BLOCKS_PER_SLOT {
+        BLOCK_DEVICE.read_block(
+            SWAP_START_BLOCK + slot * BLOCKS_PER_SLOT + i,
+            &mut bytes[i * BLOCK_SZ..(i + 1) * BLOCK_SZ],
+        );
+    }
+}
+
+/// Let the clock hand visit page, which has just been brought into memory.
+pub fn clock_insert(page: &Arc<Page>) {
+    CLOCK.exclusive_access().push_back(Arc::downgrade(page));
+}
+
+/// Swap out a page chosen by the clock (second-chance) algorithm. Return false if no page can be swapped out.
+/// A page whose accessed bit is set gets a second chance, with the bit cleared.
+pub fn reclaim_frame() -> bool {
+    let mut clock = CLOCK.exclusive_access();
+    // After the first round, all the accessed bits are cleared.
+    for _ in 0..2 * clock.len() {
+        let weak = match clock.pop_front() {
+            Some(weak) => weak,
+            None => return false,
+        };
+        let page = match weak.upgrade() {
+            Some(page) => page,
+            None => continue,
+        };
+        if page.try_swap_out() {
+            // The page is inserted again when it is swapped in.
+            return true;
+        }
+        clock.push_back(weak);
+    }
+    false
+}
@@ -0,0 +1,148 @@
+use super::{page::PinnedPages, user_window::UserWindow, AddressSpace, VirtAddr, VirtPageNum};
+use crate::config::{MMAP_END, PAGE_SIZE, USER_WINDOW_BASE};
+use alloc::string::String;
+use core::{cmp::min, mem::MaybeUninit};
+
+/// The error of an access to user memory that is not mapped in user mode with the needed permission, like EFAULT.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BadAddress;
+
+impl AddressSpace {
+    /// Return [ptr, ptr + len) of user memory as a UserBuffer, checking that it can be read, or written if
+    /// is_write, in user mode.
+    pub fn user_buffer(
+        &mut self,
+        ptr: *const u8,
+        len: usize,
+        is_write: bool,
+    ) -> Result<UserBuffer, BadAddress> {
+        let pinned_pages = self.fault_in(ptr as usize, len, is_write)?;
+        let start = ptr as usize;
+        // The user window of the kernel only reaches below MMAP_END.
+        if len > 0 && start + len > MMAP_END {
+            return Err(BadAddress);
+        }
+        self.sync_user_window(start, start + len);
+        Ok(UserBuffer {
+            start: start,
+            len: len,
+            satp: self.kernel_view_satp(),
+            _pinned_pages: pinned_pages,
+        })
+    }
+
+    /// Copy dst.len() bytes of user memory at src to dst.
+    pub fn copy_from_user(&mut self, src: usize, dst: &mut [u8]) -> Result<(), BadAddress> {
+        let user_buffer = self.user_buffer(src as *const u8, dst.len(), false)?;
+        if user_buffer.read(0, dst) < dst.len() {
+            return Err(BadAddress);
+        }
+        Ok(())
+    }
+
+    /// Copy src to the user memory at dst.
+    pub fn copy_to_user(&mut self, dst: usize, src: &[u8]) -> Result<(), BadAddress> {
+        let mut user_buffer = self.user_buffer(dst as *const u8, src.len(), true)?;
+        if user_buffer.write(0, src) < src.len() {
+            return Err(BadAddress);
+        }
+        Ok(())
+    }
+
+    /// Read a T from the user memory at ptr, which may be unaligned.
+    pub fn read_user<T: Copy>(&mut self, ptr: *const T) -> Result<T, BadAddress> {
+        let mut value = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        self.copy_from_user(ptr as usize, bytes)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Write value to the user memory at ptr, which may be unaligned.
+    pub fn write_user<T: Copy>(&mut self, ptr: *mut T, value: &T) -> Result<(), BadAddress> {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.copy_to_user(ptr as usize, bytes)
+    }
+
+    /// Like write_user, but the memory only needs to be readable in user mode, so that the loader can relocate
+    /// read-only segments. The pages of a program being loaded are private copies of the file, so they are written
+    /// through their physical addresses, since the user window has the permissions of user mode.
+    pub fn write_loaded<T: Copy>(&mut self, ptr: *mut T, value: &T) -> Result<(), BadAddress> {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        let _pinned_pages = self.fault_in(ptr as usize, bytes.len(), false)?;
+        let mut start = ptr as usize;
+        let end = start + bytes.len();
+        while start < end {
+            let start_va = VirtAddr::from(start);
+            let vpn = start_va.floor();
+            let page_end = min(VirtAddr::from(VirtPageNum(vpn.0 + 1)).0, end);
+            let offset = start - ptr as usize;
+            self.translate(vpn).unwrap().ppn().get_bytes_array()
+                [start_va.page_offset()..start_va.page_offset() + page_end - start]
+                .copy_from_slice(&bytes[offset..offset + page_end - start]);
+            start = page_end;
+        }
+        Ok(())
+    }
+
+    /// Read the null-terminated string at ptr in user memory.
+    pub fn read_user_str(&mut self, ptr: *const u8) -> Result<String, BadAddress> {
+        let mut string = String::new();
+        let mut start = ptr as usize;
+        let mut bytes = [0u8; PAGE_SIZE];
+        loop {
+            // The string is read a page at a time, since the page after its end may not be mapped.
+            let len = PAGE_SIZE - start % PAGE_SIZE;
+            self.copy_from_user(start, &mut bytes[..len])?;
+            for ch in bytes[..len].iter() {
+                if *ch == b'\0' {
+                    return Ok(string);
+                }
+                string.push(*ch as char);
+            }
+            start += len;
+        }
+    }
+}
+
+/// A range of user memory, whose pages stay in memory while it is alive. It is accessed in user windows, so it can
+/// be kept while the task is blocked.
+pub struct UserBuffer {
+    start: usize,
+    len: usize,
+    /// The satp of the kernel view of the address space.
+    satp: usize,
+    _pinned_pages: PinnedPages,
+}
+
+impl UserBuffer {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Copy the bytes of self from offset to dst. Return the number of bytes copied, which is less than dst.len()
+    /// if self ends, or the memory is no longer accessible, e.g. unmapped by another thread meanwhile.
+    pub fn read(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let len = min(dst.len(), self.len.saturating_sub(offset));
+        if len == 0 {
+            return 0;
+        }
+        let start = self.start + offset;
+        let window = UserWindow::open(self.satp, start, start + len);
+        len - window.copy(dst.as_mut_ptr() as usize, USER_WINDOW_BASE + start, len)
+    }
+
+    /// Copy src to self from offset. Return the number of bytes copied like UserBuffer::read.
+    pub fn write(&mut self, offset: usize, src: &[u8]) -> usize {
+        let len = min(src.len(), self.len.saturating_sub(offset));
+        if len == 0 {
+            return 0;
+        }
+        let start = self.start + offset;
+        let window = UserWindow::open(self.satp, start, start + len);
+        len - window.copy(USER_WINDOW_BASE + start, src.as_ptr() as usize, len)
+    }
+}
@@ -0,0 +1,146 @@
+use super::{frame_alloc, kernel_satp, FrameTracker, PhysPageNum};
+use crate::config::{MMAP_END, PAGE_SIZE, USER_WINDOW_BASE};
+use core::{
+    arch::{asm, global_asm},
+    cmp::min,
+};
+use riscv::register::{satp, sstatus};
+
+global_asm!(include_str!("copy_user.S"));
+
+unsafe extern "C" {
+    unsafe fn __copy_user(dst: usize, src: usize, len: usize) -> usize;
+}
+
+/// Each root entry of an Sv39 page table maps 1GB.
+const ROOT_ENTRY_SHIFT: usize = 30;
+/// The root entry of a kernel view mapping the user memory at 0.
+const WINDOW_ROOT_ENTRY: usize = (USER_WINDOW_BASE >> ROOT_ENTRY_SHIFT) & 0x1ff;
+/// A window opened on more pages than this is flushed from the TLB as a whole.
+const WINDOW_FLUSH_PAGES: usize = 16;
+
+/// The page table the kernel runs on while serving a process. It has the mappings of the kernel, and the user
+/// memory of the process again at USER_WINDOW_BASE, where it can only be accessed with sstatus.SUM set, i.e. in a
+/// UserWindow. The user window shares the lower levels of the user page table, so only its root entries are copied.
+pub struct KernelView {
+    root: FrameTracker,
+}
+
+impl KernelView {
+    pub fn new() -> Self {
+        let root = frame_alloc().unwrap();
+        // The root entries of the kernel are never changed after boot, so they are copied once.
+        let kernel_root = PhysPageNum(kernel_satp() & ((1usize << 44) - 1));
+        root.ppn
+            .get_pte_array()
+            .copy_from_slice(kernel_root.get_pte_array());
+        Self { root: root }
+    }
+
+    pub fn satp(&self) -> usize {
+        8usize << 60 | self.root.ppn.0
+    }
+
+    /// Make the user window map [start, end) like the user page table whose root is user_root.
+    pub fn sync(&self, user_root: PhysPageNum, start: usize, end: usize) {
+        let user_entries = user_root.get_pte_array();
+        let window_entries = &mut self.root.ppn.get_pte_array()[WINDOW_ROOT_ENTRY..];
+        let mut changed = false;
+        let end_entry = min(
+            end.div_ceil(1 << ROOT_ENTRY_SHIFT),
+            MMAP_END >> ROOT_ENTRY_SHIFT,
+        );
+        for i in start >> ROOT_ENTRY_SHIFT..end_entry {
+            if window_entries[i].bits != user_entries[i].bits {
+                window_entries[i] = user_entries[i];
+                changed = true;
+            }
+        }
+        // The TLB may cache root entries, which are only flushed by a global fence.
+        if changed && satp::read().bits() == self.satp() {
+            unsafe {
+                asm!("sfence.vma");
+            }
+        }
+    }
+}
+
+impl Drop for KernelView {
+    fn drop(&mut self) {
+        // The page table in use must not be freed, e.g. when a process is replaced by exec.
+        if satp::read().bits() == self.satp() {
+            activate(kernel_satp());
+        }
+    }
+}
+
+fn activate(satp: usize) {
+    unsafe {
+        satp::write(satp);
+        asm!("sfence.vma");
+    }
+}
+
+/// While a UserWindow is alive, the kernel can access the user memory in it at USER_WINDOW_BASE + va with
+/// sstatus.SUM set. SUM is clear outside user windows, so any other access of the kernel to user memory faults.
+/// A window must be closed before the task opening it is switched out.
+pub struct UserWindow {
+    /// The satp in use before the window is opened on another address space.
+    restore_satp: Option<usize>,
+    sum: bool,
+}
+
+impl UserWindow {
+    /// Open a window on [start, end) of the address space whose kernel view is satp. The window should be synced
+    /// with the user page table by KernelView::sync.
+    pub fn open(satp: usize, start: usize, end: usize) -> Self {
+        let current_satp = satp::read().bits();
+        let restore_satp = if current_satp == satp {
+            // The kernel may have changed the user page table since the pages were last accessed in a window.
+            let start_va = USER_WINDOW_BASE + start / PAGE_SIZE * PAGE_SIZE;
+            let end_va = USER_WINDOW_BASE + end;
+            if (end_va - start_va).div_ceil(PAGE_SIZE) > WINDOW_FLUSH_PAGES {
+                unsafe {
+                    asm!("sfence.vma");
+                }
+            } else {
+                for va in (start_va..end_va).step_by(PAGE_SIZE) {
+                    unsafe {
+                        asm!("sfence.vma {}", in(reg) va);
+                    }
+                }
+            }
+            None
+        } else {
+            activate(satp);
+            Some(current_satp)
+        };
+        let sum = sstatus::read().sum();
+        unsafe {
+            sstatus::set_sum();
+        }
+        Self {
+            restore_satp: restore_satp,
+            sum: sum,
+        }
+    }
+
+    /// Copy len bytes from src to dst, one of which is in the window. Return the number of bytes not copied, which
+    /// is not 0 only if a page fault happens, e.g. on a page unmapped after it was faulted in.
+    pub fn copy(&self, dst: usize, src: usize, len: usize) -> usize {
+        unsafe { __copy_user(dst, src, len) }
+    }
+}
+
+impl Drop for UserWindow {
+    fn drop(&mut self) {
+        if !self.sum {
+            unsafe {
+                sstatus::clear_sum();
+            }
+        }
+        if let Some(satp) = self.restore_satp {
+            activate(satp);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{sync::SpinLock, timer::get_time};
+use lazy_static::lazy_static;
+
+/// A splitmix64 generator. It is seeded with the jitter of mtime, and the time a number is drawn at is mixed into
+/// the state as well, so the numbers are hard to predict even if the boot time is known.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new() -> Self {
+        let mut seed: u64 = 0;
+        // The time taken by a busy loop varies with the host, which is the entropy collected.
+        for _ in 0..64 {
+            let start = get_time();
+            let mut spins = 0;
+            while get_time() == start && spins < 1000 {
+                spins += 1;
+                core::hint::spin_loop();
+            }
+            seed = seed.rotate_left(5) ^ (get_time() as u64) ^ spins;
+        }
+        Self { state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self
+            .state
+            .wrapping_add(0x9e37_79b9_7f4a_7c15)
+            .wrapping_add(get_time() as u64);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+lazy_static! {
+    static ref RANDOM: SpinLock<Random> = SpinLock::new(Random::new());
+}
+
+/// Return a random number in [0, bound). bound should not be 0.
+pub fn random_below(bound: usize) -> usize {
+    (RANDOM.exclusive_access().next() % bound as u64) as usize
+}
+
+/// Fill buf with random bytes.
+pub fn random_bytes(buf: &mut [u8]) {
+    let mut random = RANDOM.exclusive_access();
+    for chunk in buf.chunks_mut(8) {
+        let bytes = random.next().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
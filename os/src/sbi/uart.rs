@@ -1,4 +1,4 @@
-use crate::{config::VIRT_UART0, sync::UPSafeCell};
+use crate::{config::VIRT_UART0, sync::SpinLock};
 use bitflags::bitflags;
 use core::sync::atomic::{AtomicU8, Ordering};
 use lazy_static::lazy_static;
@@ -143,15 +143,19 @@ impl UartRaw {
         self.write_port().thr.store(byte, Ordering::Release);
     }
 
+    /// Return 0 if no byte is received, instead of waiting for one while the UART is locked, which would stop the
+    /// output of the other harts.
     fn recv(&self) -> u8 {
         let read_port = self.read_port();
-        wait_for!((read_port.lsr.load(Ordering::Acquire) & LineStatus::INPUT_AVAILABLE.bits) != 0);
+        if read_port.lsr.load(Ordering::Acquire) & LineStatus::INPUT_AVAILABLE.bits == 0 {
+            return 0;
+        }
         read_port.rbr.load(Ordering::Acquire)
     }
 }
 
 lazy_static! {
-    static ref UART: UPSafeCell<UartRaw> = UPSafeCell::new(UartRaw::new(VIRT_UART0));
+    static ref UART: SpinLock<UartRaw> = SpinLock::new(UartRaw::new(VIRT_UART0));
 }
 
 pub fn uart_init() {
@@ -1,17 +1,17 @@
 use crate::{
-    sync::{Mutex, UPSafeCell},
+    sync::{Mutex, SpinLock},
     task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock},
 };
 use alloc::{collections::vec_deque::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    waiter_queue: UPSafeCell<VecDeque<Arc<TaskControlBlock>>>,
+    waiter_queue: SpinLock<VecDeque<Arc<TaskControlBlock>>>,
 }
 
 impl Condvar {
     pub fn new() -> Self {
         Self {
-            waiter_queue: UPSafeCell::new(VecDeque::new()),
+            waiter_queue: SpinLock::new(VecDeque::new()),
         }
     }
 
@@ -23,10 +23,11 @@ impl Condvar {
     }
 
     pub fn wait(&self, mutex: Arc<dyn Mutex>) {
-        mutex.unlock();
+        // The task waits before the mutex is unlocked, so that a signal on another hart in between is not lost.
         self.waiter_queue
             .exclusive_access()
             .push_back(current_task());
+        mutex.unlock();
         block_current_and_run_next();
         mutex.lock();
     }
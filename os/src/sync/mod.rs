@@ -1,9 +1,11 @@
 mod condvar;
 mod mutex;
 mod semaphore;
+mod spin;
 mod up;
 
 pub use condvar::Condvar;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 pub use semaphore::Semaphore;
+pub use spin::{SpinLock, SpinLockGuard};
 pub use up::UPSafeCell;
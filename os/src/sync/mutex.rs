@@ -1,7 +1,7 @@
 use alloc::{collections::vec_deque::VecDeque, sync::Arc};
 
 use crate::{
-    sync::UPSafeCell,
+    sync::SpinLock,
     task::{
         block_current_and_run_next, current_task, suspend_current_and_run_next, wakeup_task,
         TaskControlBlock,
@@ -14,13 +14,13 @@ pub trait Mutex: Sync + Send {
 }
 
 pub struct MutexSpin {
-    locked: UPSafeCell<bool>,
+    locked: SpinLock<bool>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         Self {
-            locked: UPSafeCell::new(false),
+            locked: SpinLock::new(false),
         }
     }
 }
@@ -47,7 +47,7 @@ impl Mutex for MutexSpin {
 }
 
 pub struct MutexBlocking {
-    inner: UPSafeCell<MutexBlockingInner>,
+    inner: SpinLock<MutexBlockingInner>,
 }
 
 struct MutexBlockingInner {
@@ -58,7 +58,7 @@ struct MutexBlockingInner {
 impl MutexBlocking {
     pub fn new() -> Self {
         Self {
-            inner: UPSafeCell::new(MutexBlockingInner {
+            inner: SpinLock::new(MutexBlockingInner {
                 locked: false,
                 waiter_queue: VecDeque::new(),
             }),
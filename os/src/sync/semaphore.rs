@@ -1,12 +1,12 @@
 use alloc::{collections::vec_deque::VecDeque, sync::Arc};
 
 use crate::{
-    sync::UPSafeCell,
+    sync::SpinLock,
     task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock},
 };
 
 pub struct Semaphore {
-    inner: UPSafeCell<SemaphoreInner>,
+    inner: SpinLock<SemaphoreInner>,
 }
 
 struct SemaphoreInner {
@@ -17,7 +17,7 @@ struct SemaphoreInner {
 impl Semaphore {
     pub fn new(res_count: usize) -> Self {
         Self {
-            inner: UPSafeCell::new(SemaphoreInner {
+            inner: SpinLock::new(SemaphoreInner {
                 count: res_count as isize,
                 waiter_queue: VecDeque::new(),
             }),
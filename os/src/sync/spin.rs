@@ -0,0 +1,76 @@
+use crate::{hart::hart_id, ipi::serve_flushes};
+use core::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The owner of a SpinLock that is not held.
+const NO_OWNER: usize = usize::MAX;
+
+/// A lock shared by the harts, which spin until it is released. The kernel runs with interrupts disabled, so the
+/// holder is never interrupted. Taking the lock again on the hart holding it is a bug, which panics like a RefCell
+/// instead of deadlocking.
+pub struct SpinLock<T> {
+    /// The id of the hart holding the lock, or NO_OWNER.
+    owner: AtomicUsize,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            owner: AtomicUsize::new(NO_OWNER),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn exclusive_access(&self) -> SpinLockGuard<'_, T> {
+        let hart = hart_id();
+        while let Err(owner) =
+            self.owner
+                .compare_exchange_weak(NO_OWNER, hart, Ordering::Acquire, Ordering::Relaxed)
+        {
+            assert_ne!(owner, hart, "the lock is already held by hart {}", hart);
+            // The holder may be waiting for this hart to flush its TLB.
+            serve_flushes();
+            spin_loop();
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    /// Like exclusive_access, but return None if the lock is held, e.g. when reporting a fatal trap.
+    pub fn try_exclusive_access(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.owner
+            .compare_exchange(NO_OWNER, hart_id(), Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinLockGuard { lock: self })
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Release);
+    }
+}
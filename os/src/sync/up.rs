@@ -1,5 +1,6 @@
 use core::cell::{RefCell, RefMut};
 
+/// A cell used by one hart only, like its Processor. The state shared by the harts is in SpinLocks.
 pub struct UPSafeCell<T> {
     inner: RefCell<T>,
 }
@@ -7,8 +8,8 @@ pub struct UPSafeCell<T> {
 unsafe impl<T> Sync for UPSafeCell<T> {}
 
 impl<T> UPSafeCell<T> {
-    // User is responsible to guarantee that inner struct is only used in uniprocessor.
-    pub fn new(value: T) -> Self {
+    // User is responsible to guarantee that inner struct is only used by one hart.
+    pub const fn new(value: T) -> Self {
         Self {
             inner: RefCell::new(value),
         }
@@ -17,4 +18,9 @@ impl<T> UPSafeCell<T> {
     pub fn exclusive_access(&self) -> RefMut<T> {
         self.inner.borrow_mut()
     }
+
+    /// Like exclusive_access, but return None if inner is being accessed, e.g. when reporting a fatal trap.
+    pub fn try_exclusive_access(&self) -> Option<RefMut<T>> {
+        self.inner.try_borrow_mut().ok()
+    }
 }
@@ -1,7 +1,7 @@
+use super::EFAULT;
 use crate::{
     fs::{make_pipe, open_file, OpenFlags},
-    mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer},
-    task::{current_process, current_task_satp},
+    task::current_process,
 };
 
 pub fn sys_dup(fd: usize) -> isize {
@@ -17,8 +17,14 @@ pub fn sys_dup(fd: usize) -> isize {
 
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
-    let satp = current_task_satp();
-    let path = translated_str(satp, path);
+    let path = match process
+        .inner_exclusive_access()
+        .address_space
+        .read_user_str(path)
+    {
+        Ok(path) => path,
+        Err(_) => return EFAULT,
+    };
     if let Some(inode) = open_file(&path, OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
@@ -41,22 +47,27 @@ pub fn sys_close(fd: usize) -> isize {
 
 pub fn sys_pipe(pipe: *mut usize) -> isize {
     let process = current_process();
-    let satp = current_task_satp();
     let mut inner = process.inner_exclusive_access();
     let (pipe_read, pip_write) = make_pipe();
     let read_fd = inner.alloc_fd();
     inner.fd_table[read_fd] = Some(pipe_read);
     let write_fd = inner.alloc_fd();
     inner.fd_table[write_fd] = Some(pip_write);
-    *translated_refmut(satp, pipe) = read_fd;
-    *translated_refmut(satp, unsafe { pipe.add(1) }) = write_fd;
+    if inner
+        .address_space
+        .write_user(pipe as *mut [usize; 2], &[read_fd, write_fd])
+        .is_err()
+    {
+        inner.fd_table[read_fd].take();
+        inner.fd_table[write_fd].take();
+        return EFAULT;
+    }
     0
 }
 
 pub fn sys_write(fd: usize, buffer: *const u8, len: usize) -> isize {
-    let satp = current_task_satp();
     let process = current_process();
-    let inner = process.inner_exclusive_access();
+    let mut inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -65,17 +76,21 @@ pub fn sys_write(fd: usize, buffer: *const u8, len: usize) -> isize {
             return -1;
         }
         let file = file.clone();
+        // The pages stay in memory while the file is written, which may block.
+        let buffer = match inner.address_space.user_buffer(buffer, len, false) {
+            Ok(buffer) => buffer,
+            Err(_) => return EFAULT,
+        };
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(satp, buffer, len))) as isize
+        file.write(buffer) as isize
     } else {
         -1
     }
 }
 
 pub fn sys_read(fd: usize, buffer: *const u8, len: usize) -> isize {
-    let satp = current_task_satp();
     let process = current_process();
-    let inner = process.inner_exclusive_access();
+    let mut inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -84,8 +99,13 @@ pub fn sys_read(fd: usize, buffer: *const u8, len: usize) -> isize {
             return -1;
         }
         let file = file.clone();
+        // The pages stay in memory while the file is read, which may block.
+        let buffer = match inner.address_space.user_buffer(buffer, len, true) {
+            Ok(buffer) => buffer,
+            Err(_) => return EFAULT,
+        };
         drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(satp, buffer, len))) as isize
+        file.read(buffer) as isize
     } else {
         -1
     }
@@ -0,0 +1,272 @@
+use super::EFAULT;
+use crate::{
+    ipi::{call_on_space, sync_instruction_cache},
+    mm::{heap_stats, slab_stats, HeapStats, PageSize, Permission, RLimit, SlabStats},
+    task::current_process,
+};
+use bitflags::bitflags;
+
+/// The resources supported by getrlimit/setrlimit.
+const RLIMIT_STACK: usize = 3;
+/// The memory used by the process, including the pages swapped out and its page table. A process allocating pages
+/// beyond it is killed.
+const RLIMIT_RSS: usize = 5;
+
+bitflags! {
+    pub struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+        /// Use 2MiB huge pages, so addr should be aligned to 2MiB.
+        const HUGETLB = 1 << 18;
+    }
+}
+
+impl From<MmapProt> for Permission {
+    fn from(prot: MmapProt) -> Self {
+        let mut permission = Permission::U;
+        // A writable page must be readable in RISC-V.
+        if prot.intersects(MmapProt::READ | MmapProt::WRITE) {
+            permission |= Permission::R;
+        }
+        if prot.contains(MmapProt::WRITE) {
+            permission |= Permission::W;
+        }
+        if prot.contains(MmapProt::EXEC) {
+            permission |= Permission::X;
+        }
+        permission
+    }
+}
+
+/// Return the new program break, which is unchanged if addr is invalid.
+pub fn sys_brk(addr: usize) -> isize {
+    current_process()
+        .inner_exclusive_access()
+        .address_space
+        .brk(addr) as isize
+}
+
+/// Anonymous mappings should be private, and fd and offset are ignored for them. Otherwise, the file fd is mapped
+/// from offset, which should be aligned to the page size.
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    let (prot, flags) = match (MmapProt::from_bits(prot), MmapFlags::from_bits(flags)) {
+        (Some(prot), Some(flags)) => (prot, flags),
+        _ => return -1,
+    };
+    let shared = flags.contains(MmapFlags::SHARED);
+    if shared == flags.contains(MmapFlags::PRIVATE) {
+        return -1;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let fixed = flags.contains(MmapFlags::FIXED);
+    let start = if flags.contains(MmapFlags::ANONYMOUS) {
+        if shared {
+            return -1;
+        }
+        let page_size = if flags.contains(MmapFlags::HUGETLB) {
+            PageSize::MegaPage
+        } else {
+            PageSize::Page
+        };
+        inner
+            .address_space
+            .mmap(addr, len, prot.into(), fixed, page_size)
+    } else {
+        if fd >= inner.fd_table.len() || flags.contains(MmapFlags::HUGETLB) {
+            return -1;
+        }
+        let file = match &inner.fd_table[fd] {
+            Some(file) => file.clone(),
+            None => return -1,
+        };
+        if !file.readable() {
+            return -1;
+        }
+        let page_cache = match file.page_cache() {
+            Some(page_cache) => page_cache,
+            None => return -1,
+        };
+        inner.address_space.mmap_file(
+            addr,
+            len,
+            prot.into(),
+            fixed,
+            page_cache,
+            offset,
+            shared,
+            file.writable(),
+        )
+    };
+    match start {
+        Some(start) => start as isize,
+        None => -1,
+    }
+}
+
+/// Write the dirty pages of the shared file mappings in [addr, addr + len) back to the files.
+/// The flags are ignored, since the pages are always written back synchronously.
+pub fn sys_msync(addr: usize, len: usize, _flags: usize) -> isize {
+    if current_process()
+        .inner_exclusive_access()
+        .address_space
+        .msync(addr, len)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    if current_process()
+        .inner_exclusive_access()
+        .address_space
+        .munmap(addr, len)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let prot = match MmapProt::from_bits(prot) {
+        Some(prot) => prot,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if !inner.address_space.mprotect(addr, len, prot.into()) {
+        return -1;
+    }
+    let satp = inner.address_space.satp();
+    drop(inner);
+    // The code written to the pages may be run by the other threads of the process right away, so the harts running
+    // them must not fetch stale instructions. The current hart syncs its instruction cache when it returns to user
+    // mode.
+    if prot.contains(MmapProt::EXEC) {
+        call_on_space(satp, sync_instruction_cache);
+    }
+    0
+}
+
+/// Return the id of the shared memory with key, which is created with size bytes if it does not exist.
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    match current_process()
+        .inner_exclusive_access()
+        .address_space
+        .get_shared_memory(key, size)
+    {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+/// Return the address the shared memory is attached at.
+pub fn sys_shmat(id: usize, addr: usize) -> isize {
+    match current_process()
+        .inner_exclusive_access()
+        .address_space
+        .attach_shared_memory(id, addr)
+    {
+        Some(start) => start as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_shmdt(addr: usize) -> isize {
+    if current_process()
+        .inner_exclusive_access()
+        .address_space
+        .detach_shared_memory(addr)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_getrlimit(resource: usize, rlimit: *mut RLimit) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let limit = match resource {
+        RLIMIT_STACK => inner.address_space.stack_limit(),
+        RLIMIT_RSS => inner.address_space.memory_limit(),
+        _ => return -1,
+    };
+    match inner.address_space.write_user(rlimit, &limit) {
+        Ok(()) => 0,
+        Err(_) => EFAULT,
+    }
+}
+
+/// The hard limit can only be lowered. A stack larger than the new limit is kept, but cannot grow any more.
+/// Likewise, the memory already used beyond the new RLIMIT_RSS is kept.
+pub fn sys_setrlimit(resource: usize, rlimit: *const RLimit) -> isize {
+    if resource != RLIMIT_STACK && resource != RLIMIT_RSS {
+        return -1;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let limit = match inner.address_space.read_user(rlimit) {
+        Ok(limit) => limit,
+        Err(_) => return EFAULT,
+    };
+    let result = if resource == RLIMIT_STACK {
+        inner.address_space.set_stack_limit(limit)
+    } else {
+        inner.address_space.set_memory_limit(limit)
+    };
+    if result {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Write the usage of the kernel heap to stats.
+pub fn sys_heap_stats(stats: *mut HeapStats) -> isize {
+    // Take the snapshot first, since writing to user memory may allocate.
+    let heap_stats = heap_stats();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match inner.address_space.write_user(stats, &heap_stats) {
+        Ok(()) => 0,
+        Err(_) => EFAULT,
+    }
+}
+
+/// Write the statistics of at most count slab caches to stats, and return the number of caches.
+pub fn sys_slab_stats(stats: *mut SlabStats, count: usize) -> isize {
+    let slab_stats = slab_stats();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    for (i, cache_stats) in slab_stats.iter().take(count).enumerate() {
+        if inner
+            .address_space
+            .write_user(stats.wrapping_add(i), cache_stats)
+            .is_err()
+        {
+            return EFAULT;
+        }
+    }
+    slab_stats.len() as isize
+}
@@ -1,24 +1,95 @@
+use super::EFAULT;
 use crate::{
-    fs::{open_file, OpenFlags},
-    mm::{translated_ref, translated_refmut, translated_str},
+    config::{KERNEL_STACK_SIZE, PAGE_SIZE},
+    fs::{open_file, File, OpenFlags},
+    hart::started_harts,
+    mm::{frames_available, MemoryUsage},
+    println,
+    sbi::shutdown,
     task::{
-        current_process, current_task, current_task_satp, exit_current_and_run_next, pid2process,
-        suspend_current_and_run_next, SignalAction, SignalFlags, SIG_CNT,
+        current_process, current_task, exit_current_and_run_next, pid2process, sched_policy,
+        suspend_current_and_run_next, SchedInfo, SignalAction, SignalFlags, MAX_PRIORITY, SIG_CNT,
     },
     timer::get_time_ms,
 };
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{string::String, vec::Vec};
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct ExecFlags: usize {
+        /// Randomize the layout of the new address space.
+        const ASLR = 1 << 0;
+    }
+}
+
+/// The targets of sys_set_priority.
+const PRIO_PROCESS: usize = 0;
+const PRIO_THREAD: usize = 1;
 
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
     unreachable!();
 }
 
+/// Shut down the machine, reporting a failure if exit_code is not 0.
+pub fn sys_shutdown(exit_code: i32) -> ! {
+    println!(
+        "[kernel] process {} shut down the machine with code {}",
+        current_process().get_pid(),
+        exit_code
+    );
+    shutdown(exit_code != 0)
+}
+
 pub fn sys_yield() -> isize {
     suspend_current_and_run_next();
     0
 }
 
+/// Set the priority of all threads of the process who if which is PRIO_PROCESS, or of the thread who of the current
+/// process if which is PRIO_THREAD. A thread gets a share of the CPU proportional to its priority, which should be in
+/// [1, MAX_PRIORITY]. Return -1 if the priority is invalid or there is no such process or thread.
+pub fn sys_set_priority(which: usize, who: usize, priority: usize) -> isize {
+    if priority == 0 || priority > MAX_PRIORITY {
+        return -1;
+    }
+    let (process, tids) = match which {
+        PRIO_PROCESS => match pid2process(who) {
+            Some(process) => (process, None),
+            None => return -1,
+        },
+        PRIO_THREAD => (current_process(), Some(who..who + 1)),
+        _ => return -1,
+    };
+    let inner = process.inner_exclusive_access();
+    let tids = tids.unwrap_or(0..inner.tasks.len());
+    let mut found = false;
+    for task in inner.tasks.get(tids).unwrap_or(&[]).iter().flatten() {
+        task.inner_exclusive_access().priority = priority;
+        found = true;
+    }
+    if found {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Write the scheduling policy and the number of harts running tasks to info, so that a program can tell how the
+/// CPU is shared.
+pub fn sys_sched_info(info: *mut SchedInfo) -> isize {
+    let sched_info = SchedInfo {
+        policy: sched_policy(),
+        harts: started_harts(),
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match inner.address_space.write_user(info, &sched_info) {
+        Ok(()) => 0,
+        Err(_) => EFAULT,
+    }
+}
+
 pub fn sys_kill(pid: usize, signum: i32) -> isize {
     if signum as usize >= SIG_CNT {
         return -1;
@@ -48,7 +119,6 @@ pub fn sys_sigaction(
     action: *const SignalAction,
     old_action: *mut SignalAction,
 ) -> isize {
-    let satp = current_task_satp();
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
     if signum as usize >= SIG_CNT {
@@ -58,8 +128,20 @@ pub fn sys_sigaction(
         if check_sigaction_error(flag, action as usize, old_action as usize) {
             return -1;
         }
-        *translated_refmut(satp, old_action) = inner.signal_actions.table[signum as usize];
-        inner.signal_actions.table[signum as usize] = *translated_ref(satp, action);
+        // The new action is read first, so that nothing is changed if it cannot be read.
+        let new_action = match inner.address_space.read_user(action) {
+            Ok(new_action) => new_action,
+            Err(_) => return EFAULT,
+        };
+        let current_action = inner.signal_actions.table[signum as usize];
+        if inner
+            .address_space
+            .write_user(old_action, &current_action)
+            .is_err()
+        {
+            return EFAULT;
+        }
+        inner.signal_actions.table[signum as usize] = new_action;
         0
     } else {
         -1
@@ -97,8 +179,36 @@ pub fn sys_getpid() -> isize {
     current_process().get_pid() as isize
 }
 
+/// Write the memory used by the process pid to usage. Return -1 if there is no such process.
+pub fn sys_memory_usage(pid: usize, usage: *mut MemoryUsage) -> isize {
+    let process = current_process();
+    let memory_usage = if pid == process.get_pid() {
+        process.inner_exclusive_access().memory_usage()
+    } else {
+        match pid2process(pid) {
+            Some(other) => other.inner_exclusive_access().memory_usage(),
+            None => return -1,
+        }
+    };
+    let mut inner = process.inner_exclusive_access();
+    match inner.address_space.write_user(usage, &memory_usage) {
+        Ok(()) => 0,
+        Err(_) => EFAULT,
+    }
+}
+
 pub fn sys_fork() -> isize {
     let current_process = current_process();
+    // The page table, kernel view, kernel stack and trap context of the child may use the frames reserved for the
+    // kernel.
+    // Fail instead of using them up, e.g. in a fork bomb.
+    let page_table_frames = current_process
+        .inner_exclusive_access()
+        .memory_usage()
+        .page_table;
+    if !frames_available(page_table_frames + KERNEL_STACK_SIZE / PAGE_SIZE + 2) {
+        return -1;
+    }
     let new_process = current_process.fork();
     let new_pid = new_process.get_pid();
     let new_process_inner = new_process.inner_exclusive_access();
@@ -112,23 +222,42 @@ pub fn sys_fork() -> isize {
 }
 
 #[no_mangle]
-pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
-    let satp = current_task_satp();
-    let path = translated_str(satp, path);
+pub fn sys_exec(path: *const u8, mut args: *const usize, flags: usize) -> isize {
+    let flags = match ExecFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let path = match inner.address_space.read_user_str(path) {
+        Ok(path) => path,
+        Err(_) => return EFAULT,
+    };
     let mut args_vec: Vec<String> = Vec::new();
     loop {
-        let arg_str_ptr = *translated_ref(satp, args);
+        let arg_str_ptr = match inner.address_space.read_user(args) {
+            Ok(arg_str_ptr) => arg_str_ptr,
+            Err(_) => return EFAULT,
+        };
         if arg_str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(satp, arg_str_ptr as *const u8));
+        match inner.address_space.read_user_str(arg_str_ptr as *const u8) {
+            Ok(arg) => args_vec.push(arg),
+            Err(_) => return EFAULT,
+        }
         unsafe { args = args.add(1) }
     }
+    drop(inner);
     if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
-        let process = current_process();
         let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
+        if !process.exec(
+            app_inode.page_cache().unwrap(),
+            args_vec,
+            flags.contains(ExecFlags::ASLR),
+        ) {
+            return -1;
+        }
         // a0 will be covered by the return value of sys_exec, so the first argument (argc) should be returned.
         argc as isize
     } else {
@@ -146,14 +275,31 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     {
         return -1;
     }
+    // The exit code pointer is checked before a child is reaped, and its page is kept in memory until the exit code
+    // is written, so that no exit code is lost.
+    let _pinned_pages =
+        match inner
+            .address_space
+            .fault_in(exit_code_ptr as usize, size_of::<i32>(), true)
+        {
+            Ok(pinned_pages) => pinned_pages,
+            Err(_) => return EFAULT,
+        };
     if let Some((id, _)) = inner.children.iter().enumerate().find(|(_, pcb)| {
         pcb.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == pcb.get_pid())
     }) {
+        let child_exit_code = inner.children[id].inner_exclusive_access().exit_code;
+        if inner
+            .address_space
+            .write_user(exit_code_ptr, &child_exit_code)
+            .is_err()
+        {
+            return EFAULT;
+        }
+        // Other harts may still hold child for a moment, e.g. the one finishing its exit, and the last of them
+        // frees it.
         let child = inner.children.remove(id);
-        assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.get_pid();
-        let child_exit_code = child.inner_exclusive_access().exit_code;
-        *translated_refmut(inner.address_space.satp(), exit_code_ptr) = child_exit_code;
         found_pid as isize
     } else {
         -2
@@ -1,23 +1,32 @@
 use crate::{
-    mm::kernel_satp,
+    config::{KERNEL_STACK_SIZE, PAGE_SIZE},
+    mm::{frames_available, kernel_satp},
     task::{add_task, current_task, TaskControlBlock},
     trap::{trap_handler, TrapContext},
 };
 use alloc::sync::Arc;
 
+/// The frames needed by a new thread: its kernel stack, its trap context and the page tables mapping them.
+const THREAD_FRAMES: usize = KERNEL_STACK_SIZE / PAGE_SIZE + 4;
+
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    // Like sys_fork, fail instead of using up the frames reserved for the kernel.
+    if !frames_available(THREAD_FRAMES) {
+        return -1;
+    }
     let task = current_task();
     let process = task.process.upgrade().unwrap();
     // Create new thread.
-    let new_task = Arc::new(TaskControlBlock::new(
-        process.clone(),
-        task.inner_exclusive_access()
-            .user_resource
-            .as_ref()
-            .unwrap()
-            .user_stack_base,
-        true,
-    ));
+    let user_stack_base = task
+        .inner_exclusive_access()
+        .user_resource
+        .as_ref()
+        .unwrap()
+        .user_stack_base;
+    let new_task = match TaskControlBlock::new(process.clone(), user_stack_base, true) {
+        Some(new_task) => Arc::new(new_task),
+        None => return -1,
+    };
     let new_task_inner = new_task.inner_exclusive_access();
     let user_stack_top = new_task_inner
         .user_resource
@@ -35,6 +44,7 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         trap_handler as usize,
     );
     trap_cx.gprs[10] = arg;
+    new_task.inner_exclusive_access().priority = task.inner_exclusive_access().priority;
     // Add the new thread to process.
     let tasks = &mut process.inner_exclusive_access().tasks;
     let new_task_tid = new_task.get_tid();
@@ -1,6 +1,9 @@
 use crate::{
-    fs::{open_file, OpenFlags},
+    fs::{open_file, File, OpenFlags},
+    ipi::{send_reschedule_to_space, serve_calls, serve_flushes},
+    mm::activate_kernel_space,
     println,
+    sbi::shutdown,
     task::{
         process::ProcessControlBlock,
         thread::{TaskStatus, TaskUserResource},
@@ -8,6 +11,7 @@ use crate::{
     timer::remove_timer,
 };
 use alloc::{sync::Arc, vec, vec::Vec};
+use core::{hint::spin_loop, sync::atomic::Ordering};
 use lazy_static::lazy_static;
 
 mod process;
@@ -18,44 +22,54 @@ mod utils;
 
 pub use process::{pid_alloc, PidHandle};
 pub use scheduler::{
-    add_task, current_kernel_stack_top, current_process, current_task, current_task_satp,
-    current_task_trap_cx, current_task_trap_cx_user_va, pid2process, remove_from_pid2process,
-    remove_task, schedule, take_current_task, wakeup_task,
+    add_preempted_task, add_task, current_kernel_stack_top, current_process, current_task,
+    current_task_satp, current_task_trap_cx, current_task_trap_cx_user_va, pid2process, processes,
+    remove_from_pid2process, remove_task, sched_policy, sched_policy_name, schedule,
+    try_current_task, wakeup_task, SchedInfo, MAX_PRIORITY,
 };
 pub use signal::{SignalAction, SignalActionTable, SignalFlags, SIG_CNT};
-pub use thread::{KernelStack, TaskContext, TaskControlBlock};
+pub use thread::{kernel_stack_guarded_by, KernelStack, TaskContext, TaskControlBlock};
 pub use utils::RecycleAllocator;
 
 lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new(inode.page_cache().unwrap())
     };
 }
 
 /// Initialize INITPROC. INITPROC will not be initialized before it is accessed.
-fn add_initproc() {
+pub fn add_initproc() {
     let _ = INITPROC.clone();
 }
 
+/// Run the tasks on the current hart. Every hart runs its own scheduler loop on the shared ready queue.
 pub fn run_tasks() {
-    add_initproc();
     scheduler::run_tasks();
 }
 
 pub fn suspend_current_and_run_next() {
-    let task = take_current_task().unwrap();
+    requeue_current_and_run_next(add_task);
+}
+
+/// Like suspend_current_and_run_next, but the current task is preempted by a timer tick, which counts against its time
+/// slice.
+pub fn preempt_current_and_run_next() {
+    requeue_current_and_run_next(add_preempted_task);
+}
+
+fn requeue_current_and_run_next(add: fn(Arc<TaskControlBlock>)) {
+    let task = current_task();
     let mut task_inner = task.inner_exclusive_access();
     let current_task_cx_ptr = &mut task_inner.task_cx as *mut _;
     task_inner.status = TaskStatus::Ready;
     drop(task_inner);
-    add_task(task);
+    add(task);
     schedule(current_task_cx_ptr);
 }
 
 pub fn block_current_and_run_next() {
-    let task = take_current_task().unwrap();
+    let task = current_task();
     let mut task_inner = task.inner_exclusive_access();
     let current_task_cx_ptr = &mut task_inner.task_cx as *mut _;
     task_inner.status = TaskStatus::Blocked;
@@ -65,73 +79,109 @@ pub fn block_current_and_run_next() {
 }
 
 pub fn exit_current_and_run_next(exit_code: i32) {
-    let task = take_current_task().unwrap();
+    let task = current_task();
     let process = task.process.upgrade().unwrap();
     let tid = task.get_tid();
+    // Once the process is a zombie, it may be reaped on another hart, which frees its kernel view. The kernel address
+    // space maps the kernel stacks as well.
+    activate_kernel_space();
     let mut task_inner = task.inner_exclusive_access();
     // Record the exit code of the thread.
     task_inner.exit_code = Some(exit_code);
     // Release thread user resources.
     task_inner.user_resource = None;
-    // task_inner and task must be dropped manually, because schedule never returns.
+    // task_inner and task must be dropped manually, because schedule never returns. The processor keeps the task
+    // until it is switched out, since its kernel stack is still in use.
     drop(task_inner);
-    drop(task);
     // If the main thread exits or the process is killed, the process should be terminated.
     if tid == 0 || process.inner_exclusive_access().killed {
-        let pid = process.get_pid();
-        remove_from_pid2process(pid);
-        let mut process_inner = process.inner_exclusive_access();
-        // Mark this process as a zombie process.
-        process_inner.is_zombie = true;
-        // The exit code of a process is the exit code of its main thread.
-        process_inner.exit_code = exit_code;
-        for child in process_inner.children.iter() {
-            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-            INITPROC
-                .inner_exclusive_access()
-                .children
-                .push(child.clone());
-        }
-        let mut recycle_resources: Vec<TaskUserResource> = Vec::new();
-        for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
-            let task = task.as_ref().unwrap();
-            remove_inactive_task(task.clone());
-            let mut task_inner = task.inner_exclusive_access();
-            if let Some(resource) = task_inner.user_resource.take() {
-                recycle_resources.push(resource);
-            }
-        }
-        // dealloc_user_resource require access to PCB inner, so we need to collect those user res first,
-        // then release process_inner for now to avoid double borrow.
-        drop(process_inner);
-        // Deallocate the user resources first. Otherwise, these pages will be deallocated twice.
-        recycle_resources.clear();
-        let mut process_inner = process.inner_exclusive_access();
-        // Clear children vector.
-        process_inner.children.clear();
-        // Deallocate the program code/data sections in user address space.
-        process_inner.address_space.recycle_data_pages();
-        // Drop file descriptors.
-        process_inner.fd_table.clear();
-        // Drop mutexes.
-        process_inner.mutex_list.clear();
-        // Drop semaphores.
-        process_inner.semaphore_list.clear();
-        // Drop condvars.
-        process_inner.condvar_list.clear();
-        // Remove all threads, except for the current thread. Deallocate the kernel stacks of these threads.
-        // We are still using the kernel stack of the current thread, so the TCB of the current thread must not be deallocated.
-        // The TCB (including the kernel stack) of the current thread will be deallocated when the processs is reaped via waitpid.
-        // There is no need to deallocate the tids, because the process itself is dead.
-        let current_task_vec = vec![process_inner.tasks[tid].clone()];
-        process_inner.tasks = current_task_vec;
+        terminate_process(&process, tid, exit_code);
     }
+    drop(task);
     // process must be dropped manually, because schedule never returns.
     drop(process);
     let mut unused_task_cx = TaskContext::zero_init();
     schedule(&mut unused_task_cx as *mut _);
 }
 
+/// Terminate process, whose thread tid is exiting with exit_code. Nothing is done if another thread has terminated it
+/// already.
+fn terminate_process(process: &Arc<ProcessControlBlock>, tid: usize, exit_code: i32) {
+    // No process is left to run once INITPROC exits, so the machine is shut down.
+    if Arc::ptr_eq(process, &INITPROC) {
+        println!("[kernel] initproc exited with code {}", exit_code);
+        shutdown(exit_code != 0);
+    }
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner.is_zombie {
+        return;
+    }
+    // Mark this process as a zombie process, after which its threads are not run again.
+    process_inner.is_zombie = true;
+    // The exit code of a process is the exit code of its main thread.
+    process_inner.exit_code = exit_code;
+    // The children are moved to INITPROC without holding the lock of this process, since INITPROC may be waiting for
+    // this process while holding its own.
+    let children = core::mem::take(&mut process_inner.children);
+    let others: Vec<Arc<TaskControlBlock>> = process_inner
+        .tasks
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| *id != tid)
+        .filter_map(|(_, task)| task.clone())
+        .collect();
+    let satp = process_inner.address_space.satp();
+    drop(process_inner);
+    remove_from_pid2process(process.get_pid());
+    for child in children {
+        child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+        INITPROC.inner_exclusive_access().children.push(child);
+    }
+    // The other threads running on other harts are interrupted to be switched out. Their resources are only released
+    // after that.
+    send_reschedule_to_space(satp);
+    for task in others.iter() {
+        while task.on_cpu.load(Ordering::Acquire) {
+            // A thread may be waiting for this hart to flush its TLB or make a call before it can be switched out.
+            serve_flushes();
+            serve_calls();
+            spin_loop();
+        }
+    }
+    let process_inner = process.inner_exclusive_access();
+    let mut recycle_resources: Vec<TaskUserResource> = Vec::new();
+    for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
+        let task = task.as_ref().unwrap();
+        remove_inactive_task(task.clone());
+        let mut task_inner = task.inner_exclusive_access();
+        if let Some(resource) = task_inner.user_resource.take() {
+            recycle_resources.push(resource);
+        }
+    }
+    // dealloc_user_resource require access to PCB inner, so we need to collect those user res first,
+    // then release process_inner for now to avoid double borrow.
+    drop(process_inner);
+    // Deallocate the user resources first. Otherwise, these pages will be deallocated twice.
+    recycle_resources.clear();
+    let mut process_inner = process.inner_exclusive_access();
+    // Deallocate the program code/data sections in user address space.
+    process_inner.address_space.recycle_data_pages();
+    // Drop file descriptors.
+    process_inner.fd_table.clear();
+    // Drop mutexes.
+    process_inner.mutex_list.clear();
+    // Drop semaphores.
+    process_inner.semaphore_list.clear();
+    // Drop condvars.
+    process_inner.condvar_list.clear();
+    // Remove all threads, except for the current thread. Deallocate the kernel stacks of these threads.
+    // We are still using the kernel stack of the current thread, so the TCB of the current thread must not be deallocated.
+    // The TCB (including the kernel stack) of the current thread will be deallocated when the processs is reaped via waitpid.
+    // There is no need to deallocate the tids, because the process itself is dead.
+    let current_task_vec = vec![process_inner.tasks[tid].clone()];
+    process_inner.tasks = current_task_vec;
+}
+
 pub fn check_signals_of_current() -> Option<(i32, &'static str)> {
     current_process()
         .inner_exclusive_access()
@@ -143,6 +193,74 @@ pub fn current_add_signal(signal: SignalFlags) {
     current_process().inner_exclusive_access().signals |= signal;
 }
 
+/// Called when a page fault or syscall of the current process finds no frame left. Kill the process using the most
+/// memory, except for INITPROC. If it is not the current process, its user pages are released once none of its
+/// threads is on a hart, so that the faulting instruction or syscall can be retried with the frames freed.
+pub fn out_of_memory() {
+    let current = current_process();
+    // Another hart has chosen the current process, which is about to be killed, so no other victim is chosen.
+    if is_killed(&current) {
+        return;
+    }
+    let victim = processes()
+        .into_iter()
+        .filter(|process| !Arc::ptr_eq(process, &INITPROC))
+        // The processes killed before are skipped, since their memory is already released.
+        .filter(|process| !is_killed(process))
+        .max_by_key(|process| {
+            process
+                .inner_exclusive_access()
+                .address_space
+                .charged_pages()
+        })
+        .unwrap_or_else(|| current.clone());
+    let mut victim_inner = victim.inner_exclusive_access();
+    println!(
+        "[kernel] out of memory: killed process {} using {} pages",
+        victim.get_pid(),
+        victim_inner.address_space.charged_pages()
+    );
+    victim_inner.signals |= SignalFlags::SIGKILL;
+    if Arc::ptr_eq(&victim, &current) {
+        return;
+    }
+    // The threads of the victim on other harts are interrupted to be switched out. Otherwise, the retried fault would
+    // find no frame again and choose another victim.
+    send_reschedule_to_space(victim_inner.address_space.satp());
+    drop(victim_inner);
+    loop {
+        // A thread switched to after this check sees SIGKILL before it returns to user mode, since the lock of the
+        // process is taken when a thread is claimed by a hart.
+        let mut victim_inner = victim.inner_exclusive_access();
+        let running = victim_inner
+            .tasks
+            .iter()
+            .flatten()
+            .any(|task| task.on_cpu.load(Ordering::Acquire));
+        if !running {
+            victim_inner.address_space.release_user_pages();
+            return;
+        }
+        drop(victim_inner);
+        // The victim may have chosen the current process at the same time, in which case both are killed.
+        if is_killed(&current) {
+            return;
+        }
+        // A thread of the victim may be waiting for this hart to flush its TLB or make a call before it can be
+        // switched out.
+        serve_flushes();
+        serve_calls();
+        spin_loop();
+    }
+}
+
+fn is_killed(process: &Arc<ProcessControlBlock>) -> bool {
+    process
+        .inner_exclusive_access()
+        .signals
+        .contains(SignalFlags::SIGKILL)
+}
+
 /// Remove all Arc references pointing to *task, except those that belong to the corresponding PCB.
 pub fn remove_inactive_task(task: Arc<TaskControlBlock>) {
     remove_task(task.clone());
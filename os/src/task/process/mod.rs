@@ -1,10 +1,12 @@
 use crate::{
+    config::{PAGE_SIZE, USER_STACK_SIZE},
     fs::{File, Stdin, Stdout},
-    mm::{kernel_satp, translated_refmut, AddressSpace},
-    sync::{Condvar, Mutex, Semaphore, UPSafeCell},
+    mm::{kernel_satp, AddressSpace, BadAddress, ElfInfo, MemoryUsage, PageCache},
+    random::random_bytes,
+    sync::{Condvar, Mutex, Semaphore, SpinLock, SpinLockGuard},
     task::{
-        add_task, scheduler::insert_into_pid2process, RecycleAllocator, SignalActionTable,
-        SignalFlags, TaskControlBlock,
+        add_task, current_task, scheduler::insert_into_pid2process, RecycleAllocator,
+        SignalActionTable, SignalFlags, TaskControlBlock,
     },
     trap::{trap_handler, TrapContext},
 };
@@ -14,7 +16,7 @@ use alloc::{
     vec,
     vec::Vec,
 };
-use core::cell::RefMut;
+use core::{cmp::max, mem::size_of};
 
 mod pid;
 
@@ -42,7 +44,7 @@ pub struct ProcessControlBlockInner {
 
 pub struct ProcessControlBlock {
     pub pid: PidHandle,
-    inner: UPSafeCell<ProcessControlBlockInner>,
+    inner: SpinLock<ProcessControlBlockInner>,
 }
 
 impl ProcessControlBlockInner {
@@ -62,6 +64,11 @@ impl ProcessControlBlockInner {
         self.tasks[tid].as_ref().unwrap().clone()
     }
 
+    /// The memory used by the process. Only its page table is left once it exits, until it is reaped.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.address_space.memory_usage()
+    }
+
     pub fn alloc_fd(&mut self) -> usize {
         if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
             fd
@@ -73,7 +80,7 @@ impl ProcessControlBlockInner {
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, ProcessControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
@@ -81,14 +88,15 @@ impl ProcessControlBlock {
         self.pid.0
     }
 
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    pub fn new(elf_file: Arc<PageCache>) -> Arc<Self> {
         // Create address space.
-        let (address_space, user_stack_base, entry_point) = AddressSpace::from_elf(elf_data);
+        let (address_space, user_stack_base, elf_info) =
+            AddressSpace::from_elf(elf_file, false).expect("invalid elf!");
         // Create new process.
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
             pid: pid_handle,
-            inner: UPSafeCell::new(ProcessControlBlockInner {
+            inner: SpinLock::new(ProcessControlBlockInner {
                 is_zombie: false,
                 address_space: address_space,
                 parent: None,
@@ -113,23 +121,27 @@ impl ProcessControlBlock {
             }),
         });
         // Create main thread.
-        let task = Arc::new(TaskControlBlock::new(
-            process.clone(),
-            user_stack_base,
-            true,
-        ));
+        let task = Arc::new(TaskControlBlock::new(process.clone(), user_stack_base, true).unwrap());
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
         let user_stack_top = task_inner.user_resource.as_ref().unwrap().user_stack_top();
         let kernel_stack_top = task.kernel_stack.get_top();
         drop(task_inner);
-        *trap_cx = TrapContext::app_initial_context(
-            entry_point,
+        let (user_sp, argv_base) = push_initial_stack(
+            &mut process.inner_exclusive_access().address_space,
             user_stack_top,
+            &[],
+            &elf_info,
+        )
+        .unwrap();
+        *trap_cx = TrapContext::app_initial_context(
+            elf_info.entry,
+            user_sp,
             kernel_satp(),
             kernel_stack_top,
             trap_handler as usize,
         );
+        trap_cx.gprs[11] = argv_base;
         // Add main thread to the new process.
         process
             .inner_exclusive_access()
@@ -143,53 +155,62 @@ impl ProcessControlBlock {
         process
     }
 
-    /// Only support processes with a single thread.
-    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) {
+    /// Only support processes with a single thread. The layout of the new address space is randomized if aslr.
+    /// Return false if elf_file is not a supported executable or args do not fit in the stack limit, in which case
+    /// self is not changed. If the initial stack cannot be pushed after the address space is replaced, e.g. when no
+    /// frame is left, self is killed and false is returned as well.
+    pub fn exec(&self, elf_file: Arc<PageCache>, args: Vec<String>, aslr: bool) -> bool {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // Modify PCB.
-        let (address_space, user_stack_base, entry_point) = AddressSpace::from_elf(elf_data);
-        let satp = address_space.satp();
+        let (mut address_space, user_stack_base, elf_info) =
+            match AddressSpace::from_elf(elf_file, aslr) {
+                Some(loaded) => loaded,
+                None => return false,
+            };
+        // The stack and memory limits are kept across exec.
+        let stack_limit = self.inner_exclusive_access().address_space.stack_limit();
+        assert!(address_space.set_stack_limit(stack_limit));
+        // The stack starts with USER_STACK_SIZE bytes, and grows up to the soft limit.
+        let stack_size = max(USER_STACK_SIZE, stack_limit.cur / PAGE_SIZE * PAGE_SIZE);
+        if initial_stack_size(&args, &elf_info) > stack_size {
+            return false;
+        }
+        let memory_limit = self.inner_exclusive_access().address_space.memory_limit();
+        assert!(address_space.set_memory_limit(memory_limit));
         self.inner_exclusive_access().address_space = address_space;
         // Modify TCB.
         let task = self.inner_exclusive_access().get_task(0);
         let mut task_inner = task.inner_exclusive_access();
-        task_inner.user_resource.as_mut().unwrap().user_stack_base = user_stack_base;
+        let user_resource = task_inner.user_resource.as_mut().unwrap();
+        user_resource.user_stack_base = user_stack_base;
+        user_resource.stack_slot = self
+            .inner_exclusive_access()
+            .address_space
+            .stack_slot(user_resource.tid);
         task_inner
             .user_resource
             .as_mut()
             .unwrap()
             .alloc_user_resource();
         task_inner.trap_cx_ppn = task_inner.user_resource.as_mut().unwrap().trap_cx_ppn();
-        let mut user_sp = task_inner.user_resource.as_mut().unwrap().user_stack_top();
-        // Push arguments on user stack.
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<&'static mut usize> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    satp,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        // Write nullptr at the end of argv.
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(satp, p as *mut u8) = *c;
-                p += 1;
+        let user_stack_top = task_inner.user_resource.as_mut().unwrap().user_stack_top();
+        // Push arguments and the auxiliary vector on user stack.
+        let (user_sp, argv_base) = match push_initial_stack(
+            &mut self.inner_exclusive_access().address_space,
+            user_stack_top,
+            &args,
+            &elf_info,
+        ) {
+            Ok(initial_stack) => initial_stack,
+            Err(_) => {
+                // The old address space is gone, so there is nothing to return to.
+                self.inner_exclusive_access().signals |= SignalFlags::SIGKILL;
+                return false;
             }
-            // Write '\0' at the end of each arg.
-            *translated_refmut(satp, p as *mut u8) = 0;
-        }
-        // Make the user_sp aligned to 8B.
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        };
         // Modify trap_cx.
         let mut trap_cx = TrapContext::app_initial_context(
-            entry_point,
+            elf_info.entry,
             user_sp,
             kernel_satp(),
             task.kernel_stack.get_top(),
@@ -198,21 +219,22 @@ impl ProcessControlBlock {
         trap_cx.gprs[10] = args.len();
         trap_cx.gprs[11] = argv_base;
         *task_inner.get_trap_cx() = trap_cx;
+        true
     }
 
     /// Only support processes with a single thread.
     pub fn fork(self: &Arc<Self>) -> Arc<Self> {
         let mut parent_inner = self.inner_exclusive_access();
         assert_eq!(parent_inner.thread_count(), 1);
-        // Clone parent's address space completely (including user_stack and trap_cx).
-        let address_space = AddressSpace::from_existed_user(&parent_inner.address_space);
+        // Share parent's user segments copy-on-write and copy the trap_cx.
+        let address_space = AddressSpace::from_existed_user(&mut parent_inner.address_space);
         // Create child process.
         let pid = pid_alloc();
         let new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> =
             parent_inner.fd_table.iter().cloned().collect();
         let process = Arc::new(Self {
             pid: pid,
-            inner: UPSafeCell::new(ProcessControlBlockInner {
+            inner: SpinLock::new(ProcessControlBlockInner {
                 is_zombie: false,
                 address_space: address_space,
                 parent: Some(Arc::downgrade(self)),
@@ -233,20 +255,27 @@ impl ProcessControlBlock {
             }),
         });
         // Create main thread of child process.
-        let task = Arc::new(TaskControlBlock::new(
-            process.clone(),
-            parent_inner
-                .get_task(0)
-                .inner_exclusive_access()
-                .user_resource
-                .as_ref()
-                .unwrap()
-                .user_stack_base,
-            // There is no need to allocate the user_stack ant the trap_cx, since these two segments have
-            // been added to the child process's address space in AddressSpace::from_existed_user.
-            false,
-        ));
-        task.inner_exclusive_access().get_trap_cx().kernel_stack_top = task.kernel_stack.get_top();
+        let task = Arc::new(
+            TaskControlBlock::new(
+                process.clone(),
+                parent_inner
+                    .get_task(0)
+                    .inner_exclusive_access()
+                    .user_resource
+                    .as_ref()
+                    .unwrap()
+                    .user_stack_base,
+                // There is no need to allocate the user_stack ant the trap_cx, since these two segments have
+                // been added to the child process's address space in AddressSpace::from_existed_user.
+                false,
+            )
+            .unwrap(),
+        );
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.get_trap_cx().kernel_stack_top = task.kernel_stack.get_top();
+        // The child runs with the priority of the thread forking it.
+        task_inner.priority = current_task().inner_exclusive_access().priority;
+        drop(task_inner);
         // Add child's main thread to child process.
         process
             .inner_exclusive_access()
@@ -262,3 +291,84 @@ impl ProcessControlBlock {
         process
     }
 }
+
+// The types of the entries of the auxiliary vector.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+/// The number of words in the auxiliary vector pushed by push_initial_stack, including AT_NULL.
+const AUXV_LEN: usize = 2 * 7;
+
+/// Return the most bytes push_initial_stack may push for args and elf_info, including the padding for alignment.
+fn initial_stack_size(args: &[String], elf_info: &ElfInfo) -> usize {
+    let random = 16;
+    let phdrs = match elf_info.phdr {
+        Some(_) => 0,
+        None => elf_info.phdrs.len() + size_of::<usize>() - 1,
+    };
+    let strings: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    // argc, argv, the nullptrs at the end of argv and envp, and the auxiliary vector.
+    let words = (args.len() + 3 + AUXV_LEN) * size_of::<usize>();
+    random + phdrs + strings + words + 15
+}
+
+/// Push args and the auxiliary vector on the user stack below user_sp, and return (user_sp, argv_base).
+/// From the returned user_sp, which is aligned to 16B, there are argc, argv, an empty envp and the auxiliary vector,
+/// and above them are the data they point to. Return an error if the stack cannot grow to hold them.
+fn push_initial_stack(
+    address_space: &mut AddressSpace,
+    mut user_sp: usize,
+    args: &[String],
+    elf_info: &ElfInfo,
+) -> Result<(usize, usize), BadAddress> {
+    // AT_RANDOM points to 16 random bytes.
+    let mut random = [0u8; 16];
+    random_bytes(&mut random);
+    user_sp -= random.len();
+    let random_ptr = user_sp;
+    address_space.copy_to_user(user_sp, &random)?;
+    // The program headers are copied if they are not loaded with the program.
+    let phdr = match elf_info.phdr {
+        Some(phdr) => phdr,
+        None => {
+            user_sp -= elf_info.phdrs.len();
+            user_sp -= user_sp % size_of::<usize>();
+            address_space.copy_to_user(user_sp, &elf_info.phdrs)?;
+            user_sp
+        }
+    };
+    let mut vector: Vec<usize> = vec![args.len()];
+    for arg in args.iter() {
+        user_sp -= arg.len() + 1;
+        address_space.copy_to_user(user_sp, arg.as_bytes())?;
+        // Write '\0' at the end of each arg.
+        address_space.write_user((user_sp + arg.len()) as *mut u8, &0)?;
+        vector.push(user_sp);
+    }
+    // The nullptrs at the end of argv and envp.
+    vector.push(0);
+    vector.push(0);
+    for (key, value) in [
+        (AT_PHDR, phdr),
+        (AT_PHENT, elf_info.phent),
+        (AT_PHNUM, elf_info.phnum),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf_info.entry),
+        (AT_RANDOM, random_ptr),
+        (AT_NULL, 0),
+    ] {
+        vector.push(key);
+        vector.push(value);
+    }
+    user_sp -= vector.len() * size_of::<usize>();
+    user_sp -= user_sp % 16;
+    for (i, word) in vector.iter().enumerate() {
+        address_space.write_user((user_sp as *mut usize).wrapping_add(i), word)?;
+    }
+    Ok((user_sp, user_sp + size_of::<usize>()))
+}
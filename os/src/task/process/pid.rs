@@ -1,9 +1,8 @@
-use crate::{sync::UPSafeCell, task::RecycleAllocator};
+use crate::{sync::SpinLock, task::RecycleAllocator};
 use lazy_static::lazy_static;
 
 lazy_static! {
-    static ref PID_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        UPSafeCell::new(RecycleAllocator::new());
+    static ref PID_ALLOCATOR: SpinLock<RecycleAllocator> = SpinLock::new(RecycleAllocator::new());
 }
 
 pub struct PidHandle(pub usize);
@@ -1,11 +1,17 @@
+mod policy;
 mod process_manager;
 mod processor;
 mod switch;
 mod task_manager;
 
-pub use process_manager::{insert_into_pid2process, pid2process, remove_from_pid2process};
+pub use policy::{SchedInfo, DEFAULT_PRIORITY, MAX_PRIORITY};
+pub use process_manager::{
+    insert_into_pid2process, pid2process, processes, remove_from_pid2process,
+};
 pub use processor::{
     current_kernel_stack_top, current_process, current_task, current_task_satp,
-    current_task_trap_cx, current_task_trap_cx_user_va, run_tasks, schedule, take_current_task,
+    current_task_trap_cx, current_task_trap_cx_user_va, run_tasks, schedule, try_current_task,
+};
+pub use task_manager::{
+    add_preempted_task, add_task, remove_task, sched_policy, sched_policy_name, wakeup_task,
 };
-pub use task_manager::{add_task, remove_task, wakeup_task};
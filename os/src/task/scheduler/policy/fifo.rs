@@ -0,0 +1,37 @@
+use super::SchedPolicy;
+use crate::task::TaskControlBlock;
+use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+
+/// Round robin, which ignores the priorities.
+pub struct FifoPolicy {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoPolicy {
+    pub fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl SchedPolicy for FifoPolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        if let Some((id, _)) = self
+            .ready_queue
+            .iter()
+            .enumerate()
+            .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(task))
+        {
+            self.ready_queue.remove(id);
+        }
+    }
+}
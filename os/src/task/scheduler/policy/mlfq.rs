@@ -0,0 +1,99 @@
+use super::SchedPolicy;
+use crate::{task::TaskControlBlock, timer::get_time_ms};
+use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use core::cmp::min;
+
+/// The number of ready queues, of which level 0 runs first.
+const LEVELS: usize = 4;
+/// The time slice of each level in timer ticks. The lower levels run longer, since their tasks are CPU-bound.
+const SLICE_TICKS: [usize; LEVELS] = [1, 2, 4, 8];
+/// All ready tasks are moved to level 0 this often, so that the CPU-bound tasks do not starve and a task that becomes
+/// interactive is favored again.
+const BOOST_INTERVAL_MS: usize = 1000;
+
+/// A multi-level feedback queue, which favors the tasks that block often, like the interactive ones. New tasks start
+/// at level 0. A task moves down a level when it uses up the time slice of its level, and up a level when it is woken
+/// up after blocking. A task preempted before its time slice is used up stays at its level. The priorities are
+/// ignored.
+pub struct MlfqPolicy {
+    ready_queues: [VecDeque<Arc<TaskControlBlock>>; LEVELS],
+    next_boost_ms: usize,
+}
+
+impl MlfqPolicy {
+    pub fn new() -> Self {
+        Self {
+            ready_queues: Default::default(),
+            next_boost_ms: BOOST_INTERVAL_MS,
+        }
+    }
+
+    /// Add task at the level that new_level returns for its current one. The ticks of the task start again when it
+    /// changes level.
+    fn push(&mut self, task: Arc<TaskControlBlock>, new_level: impl FnOnce(usize) -> usize) {
+        let mut task_inner = task.inner_exclusive_access();
+        let level = new_level(task_inner.level);
+        if level != task_inner.level {
+            task_inner.level = level;
+            task_inner.ticks = 0;
+        }
+        drop(task_inner);
+        self.ready_queues[level].push_back(task);
+    }
+
+    fn boost(&mut self) {
+        for level in 1..LEVELS {
+            while let Some(task) = self.ready_queues[level].pop_front() {
+                let mut task_inner = task.inner_exclusive_access();
+                task_inner.level = 0;
+                task_inner.ticks = 0;
+                drop(task_inner);
+                self.ready_queues[0].push_back(task);
+            }
+        }
+    }
+}
+
+impl SchedPolicy for MlfqPolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.push(task, |level| level);
+    }
+
+    fn add_preempted(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.ticks += 1;
+        let used_up = task_inner.ticks >= SLICE_TICKS[task_inner.level];
+        if used_up {
+            task_inner.ticks = 0;
+        }
+        drop(task_inner);
+        self.push(task, |level| {
+            if used_up {
+                min(level + 1, LEVELS - 1)
+            } else {
+                level
+            }
+        });
+    }
+
+    fn add_woken(&mut self, task: Arc<TaskControlBlock>) {
+        self.push(task, |level| level.saturating_sub(1));
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let now = get_time_ms();
+        if now >= self.next_boost_ms {
+            self.boost();
+            self.next_boost_ms = now + BOOST_INTERVAL_MS;
+        }
+        self.ready_queues
+            .iter_mut()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        for queue in self.ready_queues.iter_mut() {
+            queue.retain(|t| !Arc::ptr_eq(t, task));
+        }
+    }
+}
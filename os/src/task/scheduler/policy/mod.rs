@@ -0,0 +1,49 @@
+//! The scheduling policies, which decide the order in which the ready tasks run.
+
+mod fifo;
+mod mlfq;
+mod stride;
+
+use crate::task::TaskControlBlock;
+use alloc::sync::Arc;
+
+pub use fifo::FifoPolicy;
+pub use mlfq::MlfqPolicy;
+pub use stride::StridePolicy;
+
+/// The priority of a task is its weight in the scheduler, in [1, MAX_PRIORITY]. New threads and the main threads of
+/// forked processes get the priority of the thread creating them, and initproc gets DEFAULT_PRIORITY.
+pub const DEFAULT_PRIORITY: usize = 16;
+pub const MAX_PRIORITY: usize = 1024;
+
+/// The scheduling policies as told to user mode.
+pub const SCHED_FIFO: usize = 0;
+pub const SCHED_STRIDE: usize = 1;
+pub const SCHED_MLFQ: usize = 2;
+
+/// What sys_sched_info tells user mode about the scheduler.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SchedInfo {
+    /// One of SCHED_FIFO, SCHED_STRIDE and SCHED_MLFQ.
+    pub policy: usize,
+    /// The number of harts running tasks.
+    pub harts: usize,
+}
+
+pub trait SchedPolicy: Send {
+    /// Add a task that is ready to run, which is new or yielding.
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    /// Add a task preempted by a timer tick, which counts against its time slice.
+    fn add_preempted(&mut self, task: Arc<TaskControlBlock>) {
+        self.add(task);
+    }
+    /// Add a task that is woken up after blocking.
+    fn add_woken(&mut self, task: Arc<TaskControlBlock>) {
+        self.add(task);
+    }
+    /// Take the task to run next.
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Remove task if it is ready to run.
+    fn remove(&mut self, task: &Arc<TaskControlBlock>);
+}
@@ -0,0 +1,100 @@
+use super::SchedPolicy;
+use crate::task::TaskControlBlock;
+use alloc::{collections::binary_heap::BinaryHeap, sync::Arc};
+use core::cmp::Ordering;
+
+/// The pass of a task grows by BIG_STRIDE / priority each time it runs.
+const BIG_STRIDE: u64 = 1 << 32;
+
+/// Stride scheduling, which runs the ready task with the least pass, so that each task gets a share of the time
+/// slices proportional to its priority. A task is charged a whole slice even if it blocks or yields early.
+pub struct StridePolicy {
+    ready_tasks: BinaryHeap<ReadyTask>,
+    /// The pass of the task that ran last. A new or woken up task starts from it, so that it cannot make up for the
+    /// time it was not ready.
+    current_pass: u64,
+    /// Added to the ready tasks in order, so that the tasks with the same pass run in turn.
+    count: u64,
+}
+
+struct ReadyTask {
+    pass: u64,
+    order: u64,
+    task: Arc<TaskControlBlock>,
+}
+
+/// Whether pass a is before b. The passes of the ready tasks are within BIG_STRIDE of each other, so they are
+/// compared correctly when they wrap around.
+fn pass_before(a: u64, b: u64) -> bool {
+    (a.wrapping_sub(b) as i64) < 0
+}
+
+impl Ord for ReadyTask {
+    /// BinaryHeap is a max-heap, so the task to run first is the greatest.
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.pass == other.pass {
+            other.order.cmp(&self.order)
+        } else if pass_before(self.pass, other.pass) {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }
+    }
+}
+
+impl PartialOrd for ReadyTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ReadyTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ReadyTask {}
+
+impl StridePolicy {
+    pub fn new() -> Self {
+        Self {
+            ready_tasks: BinaryHeap::new(),
+            current_pass: 0,
+            count: 0,
+        }
+    }
+}
+
+impl SchedPolicy for StridePolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        if pass_before(task_inner.pass, self.current_pass) {
+            task_inner.pass = self.current_pass;
+        }
+        let pass = task_inner.pass;
+        drop(task_inner);
+        self.ready_tasks.push(ReadyTask {
+            pass: pass,
+            order: self.count,
+            task: task,
+        });
+        self.count += 1;
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ready_task = self.ready_tasks.pop()?;
+        let mut task_inner = ready_task.task.inner_exclusive_access();
+        self.current_pass = task_inner.pass;
+        task_inner.pass = task_inner
+            .pass
+            .wrapping_add(BIG_STRIDE / task_inner.priority as u64);
+        drop(task_inner);
+        Some(ready_task.task)
+    }
+
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        self.ready_tasks
+            .retain(|ready_task| !Arc::ptr_eq(&ready_task.task, task));
+    }
+}
@@ -1,16 +1,21 @@
-use crate::{sync::UPSafeCell, task::process::ProcessControlBlock};
-use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use crate::{sync::SpinLock, task::process::ProcessControlBlock};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 use lazy_static::lazy_static;
 
 lazy_static! {
-    pub static ref PID2PCB: UPSafeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-        UPSafeCell::new(BTreeMap::new());
+    pub static ref PID2PCB: SpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        SpinLock::new(BTreeMap::new());
 }
 
 pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     PID2PCB.exclusive_access().get(&pid).map(Arc::clone)
 }
 
+/// Return the processes that have not exited.
+pub fn processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB.exclusive_access().values().cloned().collect()
+}
+
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
@@ -1,6 +1,9 @@
 //! the state of the processor
 use crate::{
-    sbi::shutdown,
+    config::MAX_HARTS,
+    hart::hart_id,
+    ipi::{clear_reschedule, send_reschedule, set_current_satp},
+    mm::kernel_satp,
     sync::UPSafeCell,
     task::{
         process::ProcessControlBlock,
@@ -8,12 +11,20 @@ use crate::{
         thread::TaskStatus,
         TaskContext, TaskControlBlock,
     },
-    trap::TrapContext,
+    trap::{take_software_interrupt, TrapContext},
 };
 use alloc::sync::Arc;
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use lazy_static::lazy_static;
+use riscv::register::{satp, sip};
 
 pub struct Processor {
+    /// The task running on the hart. It is kept until the task is switched out, so that its kernel stack is not
+    /// freed while in use, even if it exits.
     current: Option<Arc<TaskControlBlock>>,
     idle_task_cx: TaskContext,
 }
@@ -40,15 +51,38 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPSafeCell<Processor> = UPSafeCell::new(Processor::new());
+    /// The processor of each hart, which is only used by that hart.
+    static ref PROCESSORS: [UPSafeCell<Processor>; MAX_HARTS] =
+        core::array::from_fn(|_| UPSafeCell::new(Processor::new()));
+}
+
+/// A bit for each hart that is looking for a task to run, or waiting for one.
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Wake up an idle hart, if there is one, since a task has become ready. The current hart looks for a task itself if
+/// it is idle.
+pub fn notify_idle_hart() {
+    let idle_harts = IDLE_HARTS.load(Ordering::SeqCst) & !(1 << hart_id());
+    if idle_harts != 0 {
+        send_reschedule(idle_harts.trailing_zeros() as usize);
+    }
 }
 
-pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+fn processor() -> &'static UPSafeCell<Processor> {
+    &PROCESSORS[hart_id()]
+}
+
+fn take_current_task() -> Option<Arc<TaskControlBlock>> {
+    processor().exclusive_access().take_current()
 }
 
 pub fn current_task() -> Arc<TaskControlBlock> {
-    PROCESSOR.exclusive_access().current().unwrap()
+    processor().exclusive_access().current().unwrap()
+}
+
+/// Like current_task, but return None instead of panicking if the processor is being accessed.
+pub fn try_current_task() -> Option<Arc<TaskControlBlock>> {
+    processor().try_exclusive_access()?.current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -76,31 +110,90 @@ pub fn current_kernel_stack_top() -> usize {
     current_task().kernel_stack.get_top()
 }
 
+/// Claim task for the current hart, which returns false if it must not run because its process has exited.
+fn claim(task: &Arc<TaskControlBlock>) -> bool {
+    // The hart that switched away from the task may not have saved its context yet.
+    while task.on_cpu.swap(true, Ordering::Acquire) {
+        spin_loop();
+    }
+    // A process is marked as a zombie before its threads on other harts are waited for, so either its exit sees the
+    // task on a hart, or this sees it as a zombie.
+    let exited = task
+        .process
+        .upgrade()
+        .map_or(true, |process| process.inner_exclusive_access().is_zombie);
+    if exited {
+        task.on_cpu.store(false, Ordering::Release);
+    }
+    !exited
+}
+
+/// Wait on the current hart, which has no task to run, until an interrupt is pending. Interrupts stay disabled, since
+/// wfi returns once an interrupt enabled in sie is pending anyway, and every such interrupt is served here. Only the
+/// supervisor software interrupt is enabled, so this is a timer tick, which may wake up the tasks whose timers have
+/// expired, or a reschedule sent by the hart that made a task ready.
+fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi");
+    }
+    if sip::read().ssoft() {
+        take_software_interrupt();
+    }
+}
+
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
-        if let Some(task) = fetch_task() {
-            let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
-            let mut task_inner = task.inner_exclusive_access();
-            let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
-            task_inner.status = TaskStatus::Running;
-            drop(task_inner);
-            processor.current = Some(task);
-            drop(processor);
-            unsafe {
-                __switch(idle_task_cx_ptr, next_task_cx_ptr);
-            }
-        } else {
-            shutdown(false);
+        // The hart is idle before it looks for a task, so a task made ready after that wakes it up.
+        IDLE_HARTS.fetch_or(1 << hart_id(), Ordering::SeqCst);
+        let Some(task) = fetch_task() else {
+            wait_for_interrupt();
+            continue;
+        };
+        // A reschedule sent to this hart when it was idle is served by switching to the task.
+        IDLE_HARTS.fetch_and(!(1 << hart_id()), Ordering::SeqCst);
+        clear_reschedule();
+        if !claim(&task) {
+            continue;
+        }
+        set_current_satp(task.satp());
+        let mut processor = processor().exclusive_access();
+        let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+        let mut task_inner = task.inner_exclusive_access();
+        let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
+        task_inner.status = TaskStatus::Running;
+        drop(task_inner);
+        processor.current = Some(task);
+        drop(processor);
+        unsafe {
+            // The TLB of this hart may still map a freed kernel stack at the place of the kernel stack of the task,
+            // which another hart may have mapped, or map the pages of the task that were changed while this hart was
+            // not on its address space.
+            asm!("sfence.vma");
+            __switch(idle_task_cx_ptr, next_task_cx_ptr);
+            // The idle task runs on the kernel page table, since the kernel view of the last process may be freed
+            // when it exits.
+            satp::write(kernel_satp());
+            asm!("sfence.vma");
         }
+        set_current_satp(0);
+        // The context of the task is saved, so it can be switched to by any hart.
+        let task = take_current_task().unwrap();
+        task.on_cpu.store(false, Ordering::Release);
+        drop(task);
     }
 }
 
+/// Switch from the current task to the idle task of the hart, which takes it off the hart. The task may be resumed
+/// on another hart.
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = processor().exclusive_access();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
+    // The switched task resumes on the kernel view it was using.
+    let satp = satp::read().bits();
     unsafe {
         __switch(switched_task_cx_ptr, idle_task_cx_ptr);
+        satp::write(satp);
+        asm!("sfence.vma");
     }
 }
@@ -1,49 +1,92 @@
+use super::{
+    policy::{
+        FifoPolicy, MlfqPolicy, SchedPolicy, StridePolicy, SCHED_FIFO, SCHED_MLFQ, SCHED_STRIDE,
+    },
+    processor::notify_idle_hart,
+};
 use crate::{
-    sync::UPSafeCell,
+    config::{DEFAULT_SCHED_POLICY, SCHED_POLICY_FILE},
+    drivers::fw_cfg,
+    sync::SpinLock,
     task::{thread::TaskStatus, TaskControlBlock},
 };
-use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use alloc::{boxed::Box, sync::Arc};
+use core::cmp::min;
 use lazy_static::lazy_static;
 
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    policy: Box<dyn SchedPolicy>,
+    /// One of SCHED_FIFO, SCHED_STRIDE and SCHED_MLFQ.
+    policy_id: usize,
+    policy_name: &'static str,
 }
 
 impl TaskManager {
+    /// The policy is chosen at boot, since the task manager is created when initproc is added.
     pub fn new() -> Self {
+        let mut bytes = [0u8; 16];
+        let name = match fw_cfg::read_file(SCHED_POLICY_FILE, &mut bytes) {
+            Some(size) => core::str::from_utf8(&bytes[..min(size, bytes.len())])
+                .unwrap_or("")
+                .trim_end_matches(['\0', '\n']),
+            None => DEFAULT_SCHED_POLICY,
+        };
+        let (policy, policy_id, policy_name): (Box<dyn SchedPolicy>, usize, &'static str) =
+            match name {
+                "fifo" => (Box::new(FifoPolicy::new()), SCHED_FIFO, "fifo"),
+                "stride" => (Box::new(StridePolicy::new()), SCHED_STRIDE, "stride"),
+                "mlfq" => (Box::new(MlfqPolicy::new()), SCHED_MLFQ, "mlfq"),
+                _ => panic!("Unknown scheduling policy {}", name),
+            };
         Self {
-            ready_queue: VecDeque::new(),
+            policy: policy,
+            policy_id: policy_id,
+            policy_name: policy_name,
         }
     }
 
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task.clone());
+        self.policy.add(task);
+    }
+
+    pub fn add_preempted(&mut self, task: Arc<TaskControlBlock>) {
+        self.policy.add_preempted(task);
+    }
+
+    pub fn add_woken(&mut self, task: Arc<TaskControlBlock>) {
+        self.policy.add_woken(task);
     }
 
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        self.policy.fetch()
     }
 
     pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-        if let Some((id, _)) = self
-            .ready_queue
-            .iter()
-            .enumerate()
-            .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
-        {
-            self.ready_queue.remove(id);
-        }
+        self.policy.remove(&task);
     }
 }
 
 lazy_static! {
-    pub static ref TASK_MANAGER: UPSafeCell<TaskManager> = UPSafeCell::new(TaskManager::new());
+    pub static ref TASK_MANAGER: SpinLock<TaskManager> = SpinLock::new(TaskManager::new());
+}
+
+/// One of SCHED_FIFO, SCHED_STRIDE and SCHED_MLFQ.
+pub fn sched_policy() -> usize {
+    TASK_MANAGER.exclusive_access().policy_id
+}
+
+pub fn sched_policy_name() -> &'static str {
+    TASK_MANAGER.exclusive_access().policy_name
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.exclusive_access().add(task);
 }
 
+pub fn add_preempted_task(task: Arc<TaskControlBlock>) {
+    TASK_MANAGER.exclusive_access().add_preempted(task);
+}
+
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     TASK_MANAGER.exclusive_access().fetch()
 }
@@ -54,5 +97,6 @@ pub fn remove_task(task: Arc<TaskControlBlock>) {
 
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     task.inner_exclusive_access().status = TaskStatus::Ready;
-    add_task(task);
+    TASK_MANAGER.exclusive_access().add_woken(task);
+    notify_idle_hart();
 }
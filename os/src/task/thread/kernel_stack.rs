@@ -1,22 +1,38 @@
 use crate::{
     config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE},
     mm::{kernel_add_segment_framed, kernel_remove_segment_with_start_vpn, Permission, VirtAddr},
-    sync::UPSafeCell,
+    sync::SpinLock,
     task::RecycleAllocator,
 };
 use lazy_static::lazy_static;
 
 lazy_static! {
-    static ref KERNEL_STACK_ALLOCATOR: UPSafeCell<RecycleAllocator> =
-        UPSafeCell::new(RecycleAllocator::new());
+    static ref KERNEL_STACK_ALLOCATOR: SpinLock<RecycleAllocator> =
+        SpinLock::new(RecycleAllocator::new());
 }
 
 // return (bottom, top) of a kernel stack in kernel address space
+// Each kernel stack has an unmapped guard page below it, so that an overflow raises a page fault.
 pub fn kernel_stack_position(id: usize) -> (usize, usize) {
     let top = TRAMPOLINE - id * (KERNEL_STACK_SIZE + PAGE_SIZE);
     (top - KERNEL_STACK_SIZE, top)
 }
 
+/// Return the id of the kernel stack whose guard page contains addr, if any.
+pub fn kernel_stack_guarded_by(addr: usize) -> Option<usize> {
+    // The kernel stacks are placed in the higher half of the address space, below the trampoline.
+    if (addr as isize) >= 0 || addr >= TRAMPOLINE {
+        return None;
+    }
+    let id = (TRAMPOLINE - addr - 1) / (KERNEL_STACK_SIZE + PAGE_SIZE);
+    let (bottom, _) = kernel_stack_position(id);
+    if addr < bottom {
+        Some(id)
+    } else {
+        None
+    }
+}
+
 pub struct KernelStack {
     pub id: usize,
 }
@@ -1,15 +1,18 @@
 use crate::{
-    mm::PhysPageNum, sync::UPSafeCell, task::process::ProcessControlBlock, trap::TrapContext,
+    mm::PhysPageNum,
+    sync::{SpinLock, SpinLockGuard},
+    task::{process::ProcessControlBlock, scheduler::DEFAULT_PRIORITY},
+    trap::TrapContext,
 };
 use alloc::sync::{Arc, Weak};
-use core::cell::RefMut;
+use core::sync::atomic::AtomicBool;
 
 mod context;
 mod kernel_stack;
 mod user_resource;
 
 pub use context::TaskContext;
-pub use kernel_stack::{alloc_kernel_stack, KernelStack};
+pub use kernel_stack::{alloc_kernel_stack, kernel_stack_guarded_by, KernelStack};
 pub use user_resource::TaskUserResource;
 
 #[derive(Clone, Copy, PartialEq)]
@@ -25,12 +28,23 @@ pub struct TaskControlBlockInner {
     pub trap_cx_ppn: PhysPageNum,
     pub exit_code: Option<i32>,
     pub trap_cx_backup: Option<TrapContext>,
+    /// The weight of the task in the scheduler.
+    pub priority: usize,
+    /// How far the task has run in stride scheduling, which is used by StridePolicy.
+    pub pass: u64,
+    /// The ready queue of the task in MlfqPolicy, where 0 is the highest.
+    pub level: usize,
+    /// The timer ticks the task has run for at its level in MlfqPolicy.
+    pub ticks: usize,
 }
 
 pub struct TaskControlBlock {
     pub process: Weak<ProcessControlBlock>,
     pub kernel_stack: KernelStack,
-    inner: UPSafeCell<TaskControlBlockInner>,
+    /// Whether a hart is running the task. It is cleared once the task is switched out and its context is saved, and
+    /// other harts wait for that before switching to the task.
+    pub on_cpu: AtomicBool,
+    inner: SpinLock<TaskControlBlockInner>,
 }
 
 impl TaskControlBlockInner {
@@ -40,31 +54,37 @@ impl TaskControlBlockInner {
 }
 
 impl TaskControlBlock {
+    /// Return None if the process already has MAX_THREADS threads.
     pub fn new(
         process: Arc<ProcessControlBlock>,
         user_stack_base: usize,
         alloc_user_resource: bool,
-    ) -> Self {
+    ) -> Option<Self> {
         let user_resource =
-            TaskUserResource::new(process.clone(), user_stack_base, alloc_user_resource);
+            TaskUserResource::new(process.clone(), user_stack_base, alloc_user_resource)?;
         let trap_cx_ppn = user_resource.trap_cx_ppn();
         let kernel_stack = alloc_kernel_stack();
         let kernel_stack_top = kernel_stack.get_top();
-        Self {
+        Some(Self {
             process: Arc::downgrade(&process),
             kernel_stack: kernel_stack,
-            inner: UPSafeCell::new(TaskControlBlockInner {
+            on_cpu: AtomicBool::new(false),
+            inner: SpinLock::new(TaskControlBlockInner {
                 user_resource: Some(user_resource),
                 status: TaskStatus::Ready,
                 task_cx: TaskContext::goto_trap_return(kernel_stack_top),
                 trap_cx_ppn: trap_cx_ppn,
                 exit_code: None,
-                trap_cx_backup: None
+                trap_cx_backup: None,
+                priority: DEFAULT_PRIORITY,
+                pass: 0,
+                level: 0,
+                ticks: 0,
             }),
-        }
+        })
     }
 
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> SpinLockGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
 
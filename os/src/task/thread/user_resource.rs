@@ -1,5 +1,5 @@
 use crate::{
-    config::{PAGE_SIZE, TRAP_CONTEXT_BASE, USER_STACK_SIZE},
+    config::{MAX_THREADS, PAGE_SIZE, TRAP_CONTEXT_BASE, USER_STACK_LIMIT, USER_STACK_SIZE},
     mm::{Permission, PhysPageNum, VirtAddr},
     task::process::ProcessControlBlock,
 };
@@ -8,6 +8,8 @@ use alloc::sync::{Arc, Weak};
 pub struct TaskUserResource {
     pub tid: usize,
     pub user_stack_base: usize,
+    /// The user stack of the thread is the stack_slot-th one from user_stack_base.
+    pub stack_slot: usize,
     pub process: Weak<ProcessControlBlock>,
 }
 
@@ -15,37 +17,47 @@ fn trap_cx_bottom(tid: usize) -> usize {
     TRAP_CONTEXT_BASE - tid * PAGE_SIZE
 }
 
-fn user_stack_bottom(user_stack_base: usize, tid: usize) -> usize {
-    user_stack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
+/// Each slot reserves a guard page and USER_STACK_LIMIT bytes above it for a user stack.
+/// The stack starts with USER_STACK_SIZE bytes at the top, and grows down on demand.
+fn user_stack_top(user_stack_base: usize, stack_slot: usize) -> usize {
+    user_stack_base + (stack_slot + 1) * (PAGE_SIZE + USER_STACK_LIMIT)
 }
 
 impl TaskUserResource {
+    /// Return None if the process already has MAX_THREADS threads.
     pub fn new(
         process: Arc<ProcessControlBlock>,
         user_stack_base: usize,
         alloc_user_resource: bool,
-    ) -> Self {
-        let tid = process.inner_exclusive_access().alloc_tid();
+    ) -> Option<Self> {
+        let mut process_inner = process.inner_exclusive_access();
+        let tid = process_inner.alloc_tid();
+        if tid >= MAX_THREADS {
+            process_inner.dealloc_tid(tid);
+            return None;
+        }
+        let stack_slot = process_inner.address_space.stack_slot(tid);
+        drop(process_inner);
         let task_user_resource = Self {
             tid: tid,
             user_stack_base: user_stack_base,
+            stack_slot: stack_slot,
             process: Arc::downgrade(&process),
         };
         if alloc_user_resource {
             task_user_resource.alloc_user_resource();
         }
-        task_user_resource
+        Some(task_user_resource)
     }
 
     /// Allocate the user stack and the trap context in the address space of self.process.
     pub fn alloc_user_resource(&self) {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        let user_stack_bottom = user_stack_bottom(self.user_stack_base, self.tid);
-        process_inner.address_space.add_segment_framed(
-            user_stack_bottom.into(),
-            (user_stack_bottom + USER_STACK_SIZE).into(),
-            Permission::R | Permission::W | Permission::U,
+        let user_stack_top = user_stack_top(self.user_stack_base, self.stack_slot);
+        process_inner.address_space.add_segment_stack(
+            (user_stack_top - USER_STACK_SIZE).into(),
+            user_stack_top.into(),
         );
         let trap_cx_bottom = trap_cx_bottom(self.tid);
         process_inner.address_space.add_segment_framed(
@@ -59,10 +71,10 @@ impl TaskUserResource {
     fn dealloc_user_resource(&self) {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        let user_stack_bottom = user_stack_bottom(self.user_stack_base, self.tid);
+        let user_stack_top = user_stack_top(self.user_stack_base, self.stack_slot);
         process_inner
             .address_space
-            .remove_segment_with_start_vpn(user_stack_bottom.into());
+            .remove_segment_with_end_vpn(user_stack_top.into());
         let trap_cx_bottom = trap_cx_bottom(self.tid);
         process_inner
             .address_space
@@ -85,7 +97,7 @@ impl TaskUserResource {
     }
 
     pub fn user_stack_top(&self) -> usize {
-        user_stack_bottom(self.user_stack_base, self.tid) + USER_STACK_SIZE
+        user_stack_top(self.user_stack_base, self.stack_slot)
     }
 }
 
@@ -1,11 +1,16 @@
 use crate::{
-    board::MTIMECMP,
-    config::{CLOCK_FREQ, MTIME},
-    sync::UPSafeCell,
+    board::{MSIP, MTIMECMP},
+    config::{CLOCK_FREQ, MAX_HARTS, MTIME},
+    hart::hart_id,
+    sync::SpinLock,
     task::{wakeup_task, TaskControlBlock},
 };
 use alloc::{collections::binary_heap::BinaryHeap, sync::Arc};
-use core::{arch::global_asm, cmp::Ordering};
+use core::{
+    arch::global_asm,
+    cmp::Ordering,
+    sync::atomic::{self, AtomicUsize},
+};
 use lazy_static::lazy_static;
 use riscv::register::{mie, mscratch, mstatus, mtvec};
 
@@ -14,21 +19,38 @@ global_asm!(include_str!("timer_trap.S"));
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
 
+/// The scratch area of the M-mode trap of each hart. It holds the saved registers, the MTIMECMP and time interval
+/// of the hart, its msip, and whether the timer has ticked since S-mode last took the tick.
 #[link_section = ".bss.stack"]
-static mut TIMER_SCRATCH: [usize; 5] = [0; 5];
+static mut TIMER_SCRATCH: [[usize; 7]; MAX_HARTS] = [[0; 7]; MAX_HARTS];
 
+/// Start the timer of the current hart, which has its own MTIMECMP, and enable the IPIs sent to it.
 pub fn init() {
     unsafe extern "C" {
-        unsafe fn __timer_trap();
+        unsafe fn __machine_trap();
     }
+    let hart = hart_id();
+    let mtimecmp = MTIMECMP + hart * size_of::<usize>();
     unsafe {
-        TIMER_SCRATCH[3] = MTIMECMP;
-        TIMER_SCRATCH[4] = CLOCK_FREQ / TICKS_PER_SEC;
-        mtvec::write(__timer_trap as usize, mtvec::TrapMode::Direct);
-        mscratch::write(&raw mut TIMER_SCRATCH as usize);
+        TIMER_SCRATCH[hart][3] = mtimecmp;
+        TIMER_SCRATCH[hart][4] = CLOCK_FREQ / TICKS_PER_SEC;
+        TIMER_SCRATCH[hart][5] = MSIP + hart * size_of::<u32>();
+        mtvec::write(__machine_trap as usize, mtvec::TrapMode::Direct);
+        mscratch::write(&raw mut TIMER_SCRATCH[hart] as usize);
         mstatus::set_mie();
         mie::set_mtimer();
-        (MTIMECMP as *mut usize).write_volatile(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+        mie::set_msoft();
+        (mtimecmp as *mut usize).write_volatile(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+    }
+}
+
+/// Return whether the timer of the current hart has ticked since the last call. The supervisor software interrupt is
+/// raised by both the ticks and the IPIs, which this tells apart.
+pub fn take_tick() -> bool {
+    unsafe {
+        AtomicUsize::from_ptr(&raw mut TIMER_SCRATCH[hart_id()][6])
+            .swap(0, atomic::Ordering::Relaxed)
+            != 0
     }
 }
 
@@ -68,7 +90,7 @@ impl Ord for TimerCondVar {
 }
 
 lazy_static! {
-    static ref TIMERS: UPSafeCell<BinaryHeap<TimerCondVar>> = UPSafeCell::new(BinaryHeap::new());
+    static ref TIMERS: SpinLock<BinaryHeap<TimerCondVar>> = SpinLock::new(BinaryHeap::new());
 }
 
 pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
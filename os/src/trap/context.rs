@@ -12,6 +12,8 @@ pub struct TrapContext {
     /// the address of app kernel stack top in the kernel adress space
     pub kernel_stack_top: usize,
     pub trap_handler: usize,
+    /// The id of the hart the context is restored on, which __alltraps loads into tp.
+    pub hart_id: usize,
 }
 
 impl TrapContext {
@@ -33,6 +35,7 @@ impl TrapContext {
             kernel_satp: kernel_satp,
             kernel_stack_top: kernel_stack_top,
             trap_handler: trap_handler,
+            hart_id: 0,
         }
     }
 }
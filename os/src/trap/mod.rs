@@ -1,18 +1,22 @@
 use crate::{
-    config::TRAMPOLINE,
+    config::{BOOT_STACK_SIZE, MAX_HARTS, PAGE_SIZE, TRAMPOLINE},
+    hart::hart_id,
+    ipi::{handle_ipi, set_current_satp},
+    mm::{OutOfMemory, VirtAddr},
     println,
-    syscall::syscall,
+    syscall::{syscall, EFAULT},
     task::{
-        check_signals_of_current, current_add_signal, current_task_satp, current_task_trap_cx,
-        current_task_trap_cx_user_va, exit_current_and_run_next, handle_signals,
-        suspend_current_and_run_next, SignalFlags,
+        check_signals_of_current, current_add_signal, current_process, current_task,
+        current_task_satp, current_task_trap_cx, current_task_trap_cx_user_va,
+        exit_current_and_run_next, handle_signals, kernel_stack_guarded_by, out_of_memory,
+        preempt_current_and_run_next, suspend_current_and_run_next, try_current_task, SignalFlags,
     },
-    timer::check_timer,
+    timer::{check_timer, take_tick},
 };
 use core::arch::{asm, global_asm};
 use riscv::register::{
     scause::{self, Exception, Interrupt, Trap},
-    sepc, sie, sip, stval, stvec,
+    sepc, sie, sscratch, stval, stvec,
 };
 
 mod context;
@@ -21,15 +25,53 @@ pub use context::TrapContext;
 
 global_asm!(include_str!("trap.S"));
 
+/// Entered from __kernel_trap on the kernel trap stack.
 #[unsafe(no_mangle)]
 pub fn trap_from_kernel() -> ! {
-    println!("stval = {:#x}, sepc = {:#x}", stval::read(), sepc::read());
-    panic!("a trap {:?} from kernel!", scause::read().cause());
+    unsafe extern "C" {
+        unsafe fn boot_stack_bottom();
+    }
+    let scause = scause::read();
+    let stval = stval::read();
+    // __kernel_trap keeps the sp before entering the trap in sscratch.
+    println!(
+        "stval = {:#x}, sepc = {:#x}, sp = {:#x}",
+        stval,
+        sepc::read(),
+        sscratch::read()
+    );
+    if let Trap::Exception(
+        Exception::StorePageFault | Exception::LoadPageFault | Exception::InstructionPageFault,
+    ) = scause.cause()
+    {
+        if let Some(id) = kernel_stack_guarded_by(stval) {
+            // The processor may be borrowed when the overflow happens.
+            match try_current_task().and_then(|task| task.process.upgrade()) {
+                Some(process) => panic!(
+                    "stack overflow of kernel stack {} in process {}!",
+                    id,
+                    process.get_pid()
+                ),
+                None => panic!("stack overflow of kernel stack {}!", id),
+            }
+        }
+        for hart in 0..MAX_HARTS {
+            // The boot stack of hart is the hart-th from the top.
+            let guard = boot_stack_bottom as usize + (MAX_HARTS - 1 - hart) * BOOT_STACK_SIZE;
+            if (guard..guard + PAGE_SIZE).contains(&stval) {
+                panic!("stack overflow of the boot stack of hart {}!", hart);
+            }
+        }
+    }
+    panic!("a trap {:?} from kernel!", scause.cause());
 }
 
 fn set_kernel_trap_entry() {
+    unsafe extern "C" {
+        unsafe fn __kernel_trap();
+    }
     unsafe {
-        stvec::write(trap_from_kernel as usize, stvec::TrapMode::Direct);
+        stvec::write(__kernel_trap as usize, stvec::TrapMode::Direct);
     }
 }
 
@@ -39,11 +81,12 @@ fn set_user_trap_entry() {
     }
 }
 
+/// Only the supervisor software interrupt is enabled, which the machine-mode timer and IPIs raise. The devices are
+/// polled and nothing raises the supervisor timer interrupt, so no other interrupt is handled, neither in
+/// trap_handler nor on an idle hart, and enabling one would leave it pending forever.
 pub fn init() {
     set_kernel_trap_entry();
     unsafe {
-        sie::set_stimer();
-        sie::set_sext();
         sie::set_ssoft();
     }
 }
@@ -57,35 +100,75 @@ pub fn trap_handler() -> ! {
         Trap::Exception(Exception::UserEnvCall) => {
             let cx = current_task_trap_cx();
             cx.sepc += 4;
-            let result = syscall(cx.gprs[17], [cx.gprs[10], cx.gprs[11], cx.gprs[12]]) as usize;
-            // trap_cx is changed during sys_exec, so we cannot use cx any more
-            current_task_trap_cx().gprs[10] = result;
+            let result = syscall(
+                cx.gprs[17],
+                [
+                    cx.gprs[10],
+                    cx.gprs[11],
+                    cx.gprs[12],
+                    cx.gprs[13],
+                    cx.gprs[14],
+                    cx.gprs[15],
+                ],
+            ) as usize;
+            // A user buffer the syscall faulted in may have found no frame left, which is only handled here, since the
+            // victims cannot be chosen while the address space is locked.
+            let out_of_memory_in_syscall = current_process()
+                .inner_exclusive_access()
+                .address_space
+                .take_out_of_memory();
+            if out_of_memory_in_syscall {
+                out_of_memory();
+            }
+            if out_of_memory_in_syscall && result as isize == EFAULT {
+                // The buffers are faulted in before the syscall has any effect, so it is restarted like a faulting
+                // instruction.
+                current_task_trap_cx().sepc -= 4;
+            } else {
+                // trap_cx is changed during sys_exec, so we cannot use cx any more
+                current_task_trap_cx().gprs[10] = result;
+            }
+        }
+        Trap::Exception(
+            exception @ (Exception::StorePageFault
+            | Exception::LoadPageFault
+            | Exception::InstructionPageFault),
+        ) => {
+            let result = current_process()
+                .inner_exclusive_access()
+                .address_space
+                .handle_page_fault(
+                    VirtAddr::from(stval).floor(),
+                    exception == Exception::StorePageFault,
+                );
+            match result {
+                Ok(true) => {
+                    // lazy allocation/copy-on-write
+                }
+                Ok(false) => segmentation_fault(stval),
+                Err(OutOfMemory::Limit) => {
+                    println!(
+                        "[kernel] process {} exceeded its memory limit",
+                        current_process().get_pid()
+                    );
+                    current_add_signal(SignalFlags::SIGKILL);
+                }
+                // The faulting instruction is retried, unless the current process is killed.
+                Err(OutOfMemory::System) => out_of_memory(),
+            }
         }
         Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::LoadFault)
-        | Trap::Exception(Exception::LoadPageFault)
-        | Trap::Exception(Exception::InstructionFault)
-        | Trap::Exception(Exception::InstructionPageFault) => {
-            println!(
-                "[debug] [kernel] trap {:?}, stval = {:#x}, sepc = {:#x}",
-                scause.cause(),
-                stval::read(),
-                sepc::read()
-            );
-            current_add_signal(SignalFlags::SIGSEGV);
-        }
+        | Trap::Exception(Exception::InstructionFault) => segmentation_fault(stval),
         Trap::Exception(Exception::IllegalInstruction) => {
             current_add_signal(SignalFlags::SIGILL);
         }
-        Trap::Interrupt(Interrupt::SupervisorSoft) => {
-            let sip = sip::read().bits();
-            unsafe {
-                asm!("csrw sip, {new_sip}", new_sip = in(reg) sip ^ 2);
-            }
-            check_timer();
-            suspend_current_and_run_next();
-        }
+        Trap::Interrupt(Interrupt::SupervisorSoft) => match take_software_interrupt() {
+            Reschedule::No => {}
+            Reschedule::Yield => suspend_current_and_run_next(),
+            // Only a tick counts against the task, e.g. to be demoted by the multi-level feedback queue.
+            Reschedule::Preempt => preempt_current_and_run_next(),
+        },
         _ => {
             panic!(
                 "Unsupported trap {:?}, stval = {:#x}!",
@@ -102,11 +185,73 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+/// How the current task should give up its hart after a supervisor software interrupt.
+pub enum Reschedule {
+    /// Keep running.
+    No,
+    /// Switch to another task, e.g. one made ready on another hart, keeping the place of the current one.
+    Yield,
+    /// The time slice of the current task is used up.
+    Preempt,
+}
+
+/// Serve the timer ticks and IPIs, which raise the supervisor software interrupt.
+pub fn take_software_interrupt() -> Reschedule {
+    // SSIP is cleared before the ticks and IPIs are taken, so that the ones arriving later raise it again.
+    unsafe {
+        asm!("csrc sip, {ssip}", ssip = in(reg) 2);
+    }
+    let tick = take_tick();
+    if tick {
+        check_timer();
+    }
+    let reschedule = handle_ipi();
+    if tick {
+        Reschedule::Preempt
+    } else if reschedule {
+        Reschedule::Yield
+    } else {
+        Reschedule::No
+    }
+}
+
+/// Report an illegal access to stval by the current thread.
+fn segmentation_fault(stval: usize) {
+    println!(
+        "[debug] [kernel] trap {:?}, stval = {:#x}, sepc = {:#x}",
+        scause::read().cause(),
+        stval,
+        sepc::read()
+    );
+    let process = current_process();
+    if process
+        .inner_exclusive_access()
+        .address_space
+        .is_stack_overflow(VirtAddr::from(stval).floor())
+    {
+        println!(
+            "[kernel] stack overflow in process {}, thread {}",
+            process.get_pid(),
+            current_task().get_tid()
+        );
+    }
+    current_add_signal(SignalFlags::SIGSEGV);
+}
+
 #[unsafe(no_mangle)]
 pub fn trap_return() -> ! {
     set_user_trap_entry();
+    // The traps from a process are served on its kernel view, on the hart the task runs on now.
+    let trap_cx = current_task_trap_cx();
+    trap_cx.kernel_satp = current_process()
+        .inner_exclusive_access()
+        .address_space
+        .kernel_view_satp();
+    trap_cx.hart_id = hart_id();
     let trap_cx_user_va = current_task_trap_cx_user_va();
     let user_satp = current_task_satp();
+    // The address space may have been replaced by exec. The TLB is flushed by __restore.
+    set_current_satp(user_satp);
     unsafe extern "C" {
         unsafe fn __alltraps();
         unsafe fn __restore();
@@ -0,0 +1,8 @@
+fn main() {
+    // The pie test is linked as a position-independent executable. The code is not compiled with -fPIC, so the
+    // loader has to relocate read-only segments (-znotext), and nothing is made read-only after relocation.
+    for arg in ["-pie", "-znotext", "-znorelro"] {
+        println!("cargo:rustc-link-arg-bin=pie={}", arg);
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
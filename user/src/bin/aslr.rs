@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{
+    close, exec, exec_with_flags, exit, fork, mmap, pipe, read, sbrk, thread_create, waitpid,
+    waittid, write, ExecFlags, MmapFlags, MmapProt,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+const USER_STACK_BASE: usize = 0x8_0000_0000;
+const MMAP_BASE: usize = 0x10_0000_0000;
+/// A local variable of the main thread, the program break, a mapping and a local variable of another thread.
+const LAYOUT_LEN: usize = 4;
+
+static THREAD_LOCAL: AtomicUsize = AtomicUsize::new(0);
+
+fn thread_main() -> ! {
+    let local = 0usize;
+    THREAD_LOCAL.store(&local as *const usize as usize, Ordering::SeqCst);
+    exit(0)
+}
+
+/// Write the layout of this process to fd.
+fn report(fd: usize) -> i32 {
+    let local = 0usize;
+    let brk = sbrk(0) as usize;
+    // The heap works wherever it is placed.
+    assert_eq!(sbrk(PAGE_SIZE as isize) as usize, brk);
+    unsafe {
+        (brk as *mut usize).write_volatile(brk);
+    }
+    let start = mmap(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(start > 0);
+    let tid = thread_create(thread_main as usize, 0);
+    assert!(tid > 0);
+    assert_eq!(waittid(tid as usize), 0);
+    let layout: [usize; LAYOUT_LEN] = [
+        &local as *const usize as usize,
+        brk,
+        start as usize,
+        THREAD_LOCAL.load(Ordering::SeqCst),
+    ];
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            layout.as_ptr() as *const u8,
+            core::mem::size_of_val(&layout),
+        )
+    };
+    assert_eq!(write(fd, bytes), bytes.len() as isize);
+    0
+}
+
+/// Run this program in a child process, with ASLR if aslr, and return the layout it reports.
+fn child_layout(aslr: bool) -> [usize; LAYOUT_LEN] {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        let fd_arg = format!("{}\0", pipe_fd[1]);
+        let args = ["aslr\0".as_ptr(), fd_arg.as_ptr(), core::ptr::null::<u8>()];
+        if aslr {
+            exec_with_flags("aslr\0", &args, ExecFlags::ASLR);
+        } else {
+            exec("aslr\0", &args);
+        }
+        panic!("exec failed!");
+    }
+    close(pipe_fd[1]);
+    let mut layout = [0usize; LAYOUT_LEN];
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(
+            layout.as_mut_ptr() as *mut u8,
+            core::mem::size_of_val(&layout),
+        )
+    };
+    let mut len = 0;
+    while len < bytes.len() {
+        let size = read(pipe_fd[0], &mut bytes[len..]);
+        assert!(size > 0);
+        len += size as usize;
+    }
+    close(pipe_fd[0]);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    layout
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc == 2 {
+        return report(argv[1].parse().unwrap());
+    }
+    // Without ASLR, every run has the same layout.
+    let fixed = child_layout(false);
+    assert_eq!(child_layout(false), fixed);
+    // With ASLR, the stacks, the heap and the mappings move between runs.
+    let first = child_layout(true);
+    let second = child_layout(true);
+    for i in 0..LAYOUT_LEN {
+        println!(
+            "aslr: fixed = {:#x}, random = {:#x}, {:#x}",
+            fixed[i], first[i], second[i]
+        );
+        assert_ne!(first[i], second[i]);
+    }
+    for layout in [fixed, first, second] {
+        assert!(USER_STACK_BASE <= layout[0] && layout[0] < MMAP_BASE);
+        assert!(layout[1] < USER_STACK_BASE);
+        assert!(layout[2] >= MMAP_BASE);
+        assert!(USER_STACK_BASE <= layout[3] && layout[3] < MMAP_BASE);
+    }
+    println!("aslr passed!");
+    0
+}
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, mmap, mprotect, munmap, open, pipe, read, write, MmapFlags, MmapProt, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+const EFAULT: isize = -14;
+const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = mmap(
+        0,
+        2 * PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    ) as usize;
+    assert!(start > 0);
+    // The first page is read-only and the second one is unmapped.
+    let read_only = start;
+    let unmapped = start + PAGE_SIZE;
+    unsafe {
+        (read_only as *mut u8).write_volatile(b'a');
+    }
+    assert_eq!(mprotect(read_only, PAGE_SIZE, MmapProt::READ), 0);
+    assert_eq!(munmap(unmapped, PAGE_SIZE), 0);
+
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    assert_eq!(write(pipe_fd[1], b"hello"), 5);
+    // Reading into a read-only page or across the end of a mapping fails without consuming the data.
+    let buf = unsafe { core::slice::from_raw_parts_mut(read_only as *mut u8, 5) };
+    assert_eq!(read(pipe_fd[0], buf), EFAULT);
+    let buf = unsafe { core::slice::from_raw_parts_mut((unmapped - 2) as *mut u8, 5) };
+    assert_eq!(read(pipe_fd[0], buf), EFAULT);
+    let mut buf = [0u8; 5];
+    assert_eq!(read(pipe_fd[0], &mut buf), 5);
+    assert_eq!(&buf, b"hello");
+    // Writing from a read-only page is fine, but not from an unmapped one.
+    let buf = unsafe { core::slice::from_raw_parts(read_only as *const u8, 1) };
+    assert_eq!(write(pipe_fd[1], buf), 1);
+    let buf = unsafe { core::slice::from_raw_parts(unmapped as *const u8, 1) };
+    assert_eq!(write(pipe_fd[1], buf), EFAULT);
+    // The trampoline is mapped, but not accessible in user mode.
+    let buf = unsafe { core::slice::from_raw_parts(TRAMPOLINE as *const u8, 1) };
+    assert_eq!(write(pipe_fd[1], buf), EFAULT);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    let bad_fds = unsafe { core::slice::from_raw_parts_mut(read_only as *mut usize, 2) };
+    assert_eq!(pipe(bad_fds), EFAULT);
+    let bad_path = unsafe {
+        core::str::from_utf8_unchecked(core::slice::from_raw_parts(unmapped as *const u8, 1))
+    };
+    assert_eq!(open(bad_path, OpenFlags::RDONLY), EFAULT);
+    println!("bad_address passed!");
+    0
+}
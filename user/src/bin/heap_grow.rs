@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::sbrk;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let brk = sbrk(0);
+    // Much larger than the initial heap.
+    let len = 1 << 18;
+    let mut v: Vec<usize> = Vec::with_capacity(len);
+    for i in 0..len {
+        v.push(i);
+    }
+    assert!(v.iter().enumerate().all(|(i, x)| i == *x));
+    assert!(sbrk(0) >= brk + (len * core::mem::size_of::<usize>()) as isize);
+    drop(v);
+    println!("heap_grow passed!");
+    0
+}
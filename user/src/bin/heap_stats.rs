@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{close, heap_stats, pipe, slab_stats, user_heap_stats, HeapStats, SlabStats};
+
+fn check(stats: &HeapStats) {
+    assert!(stats.requested <= stats.allocated);
+    assert!(stats.allocated <= stats.peak_allocated);
+    assert_eq!(stats.free + stats.allocated, stats.total);
+    assert_eq!(stats.free_by_class.iter().sum::<usize>(), stats.free);
+    assert!(stats.largest_free_block <= stats.free);
+    assert!(stats.dealloc_count <= stats.alloc_count);
+}
+
+fn print(name: &str, stats: &HeapStats) {
+    println!(
+        "{}: {}KiB total, {}B allocated for {}B requested, {}B at peak",
+        name,
+        stats.total >> 10,
+        stats.allocated,
+        stats.requested,
+        stats.peak_allocated
+    );
+    println!(
+        "{}: {} allocs, {} deallocs, {} failures",
+        name, stats.alloc_count, stats.dealloc_count, stats.failure_count
+    );
+    // The share of the free memory outside the largest free block, in percent.
+    let fragmentation = match stats.free {
+        0 => 0,
+        free => (free - stats.largest_free_block) * 100 / free,
+    };
+    println!(
+        "{}: {}B free, largest free block {}B, {}% fragmented",
+        name, stats.free, stats.largest_free_block, fragmentation
+    );
+    for (class, free) in stats.free_by_class.iter().enumerate() {
+        if *free > 0 {
+            println!(
+                "{}:   {} free blocks of {}B",
+                name,
+                free >> class,
+                1usize << class
+            );
+        }
+    }
+}
+
+const MAX_CACHES: usize = 32;
+
+/// Return the statistics of the kernel cache with name, after checking those of every cache.
+fn slab_cache(name: &str) -> Option<SlabStats> {
+    let mut caches = [SlabStats::default(); MAX_CACHES];
+    let count = slab_stats(&mut caches);
+    assert!(count > 0 && count as usize <= MAX_CACHES);
+    for stats in caches[..count as usize].iter() {
+        assert_eq!(stats.alloc_count - stats.free_count, stats.objects_in_use);
+        assert!(stats.objects_in_use * stats.object_size <= stats.slab_count * stats.slab_size);
+    }
+    caches[..count as usize]
+        .iter()
+        .find(|stats| stats.name() == name)
+        .copied()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut kernel = HeapStats::default();
+    assert_eq!(heap_stats(&mut kernel), 0);
+    check(&kernel);
+    assert!(kernel.total > 0);
+    print("kernel heap", &kernel);
+
+    // The ring buffer of a pipe comes from its own cache.
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let before = slab_cache("pipe_ring_buffer").unwrap();
+    assert!(before.objects_in_use >= 1);
+    let mut other_fd = [0usize; 2];
+    assert_eq!(pipe(&mut other_fd), 0);
+    let after = slab_cache("pipe_ring_buffer").unwrap();
+    assert_eq!(after.objects_in_use, before.objects_in_use + 1);
+    assert_eq!(after.alloc_count, before.alloc_count + 1);
+    for fd in pipe_fd.iter().chain(other_fd.iter()) {
+        assert_eq!(close(*fd), 0);
+    }
+    let after = slab_cache("pipe_ring_buffer").unwrap();
+    assert_eq!(after.objects_in_use, before.objects_in_use - 1);
+    println!(
+        "pipe_ring_buffer: {} objects of {}B in {} slabs of {}B",
+        after.objects_in_use, after.object_size, after.slab_count, after.slab_size
+    );
+
+    // Allocations are rounded up to powers of 2.
+    let before = user_heap_stats();
+    let v: Vec<u8> = Vec::with_capacity(100);
+    let after = user_heap_stats();
+    assert_eq!(after.requested, before.requested + 100);
+    assert_eq!(after.allocated, before.allocated + 128);
+    assert_eq!(after.alloc_count, before.alloc_count + 1);
+    check(&after);
+    drop(v);
+    let after = user_heap_stats();
+    assert_eq!(after.requested, before.requested);
+    assert_eq!(after.allocated, before.allocated);
+    assert_eq!(after.dealloc_count, before.dealloc_count + 1);
+    check(&after);
+    print("user heap", &after);
+    println!("heap_stats passed!");
+    0
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mmap, munmap, waitpid, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+const HUGE_PAGE_SIZE: usize = 0x200000;
+const LEN: usize = 2 * HUGE_PAGE_SIZE;
+
+fn pattern(offset: usize) -> u8 {
+    (offset / PAGE_SIZE + offset) as u8
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = mmap(
+        0,
+        LEN,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::HUGETLB,
+    ) as usize;
+    assert!(start > 0);
+    assert_eq!(start % HUGE_PAGE_SIZE, 0);
+    // A huge mapping cannot be placed at an unaligned address.
+    let unaligned = mmap(
+        start + LEN + PAGE_SIZE,
+        HUGE_PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::HUGETLB | MmapFlags::FIXED,
+    );
+    assert_eq!(unaligned, -1);
+
+    let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, LEN) };
+    assert!(buf.iter().step_by(PAGE_SIZE).all(|byte| *byte == 0));
+    for (offset, byte) in buf.iter_mut().enumerate() {
+        *byte = pattern(offset);
+    }
+    assert!(buf
+        .iter()
+        .enumerate()
+        .all(|(offset, byte)| *byte == pattern(offset)));
+
+    // The huge pages are copied on write after fork.
+    let pid = fork();
+    if pid == 0 {
+        assert!(buf
+            .iter()
+            .enumerate()
+            .all(|(offset, byte)| *byte == pattern(offset)));
+        buf[HUGE_PAGE_SIZE + 1] = !pattern(HUGE_PAGE_SIZE + 1);
+        assert_eq!(buf[HUGE_PAGE_SIZE + 1], !pattern(HUGE_PAGE_SIZE + 1));
+        return 0;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(buf[HUGE_PAGE_SIZE + 1], pattern(HUGE_PAGE_SIZE + 1));
+
+    // A huge page cannot be split.
+    assert_eq!(munmap(start, PAGE_SIZE), -1);
+    assert_eq!(munmap(start + PAGE_SIZE, HUGE_PAGE_SIZE), -1);
+    assert_eq!(munmap(start + HUGE_PAGE_SIZE, HUGE_PAGE_SIZE), 0);
+    assert_eq!(buf[HUGE_PAGE_SIZE - 1], pattern(HUGE_PAGE_SIZE - 1));
+    assert_eq!(munmap(start, HUGE_PAGE_SIZE), 0);
+    println!("huge_page passed!");
+    0
+}
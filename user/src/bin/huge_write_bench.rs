@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{close, exit, fork, get_time, open, pipe, read, waitpid, write, OpenFlags};
+
+/// The bytes moved in each measurement of a file and a pipe. The pipe has a small buffer, so it switches tasks for
+/// every few bytes.
+const FILE_LEN: usize = 2 << 20;
+const PIPE_LEN: usize = 256 << 10;
+const CHUNK_LENS: [usize; 3] = [1 << 10, 16 << 10, 256 << 10];
+
+fn byte_at(position: usize) -> u8 {
+    (position % 251) as u8
+}
+
+/// Print the throughput of moving len bytes since start.
+fn report(what: &str, chunk_len: usize, len: usize, start: isize) {
+    let time_ms = core::cmp::max(get_time() - start, 1) as usize;
+    println!(
+        "{} with {}KiB buffers: {}ms, {}KiB/s",
+        what,
+        chunk_len >> 10,
+        time_ms,
+        (len >> 10) * 1000 / time_ms
+    );
+}
+
+fn bench_file(chunk_len: usize) {
+    let mut buffer = vec![0u8; chunk_len];
+    let fd = open("huge_write_bench\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let start = get_time();
+    for offset in (0..FILE_LEN).step_by(chunk_len) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = byte_at(offset + i);
+        }
+        assert_eq!(write(fd, &buffer), chunk_len as isize);
+    }
+    close(fd);
+    report("file write", chunk_len, FILE_LEN, start);
+
+    let fd = open("huge_write_bench\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let start = get_time();
+    for offset in (0..FILE_LEN).step_by(chunk_len) {
+        assert_eq!(read(fd, &mut buffer), chunk_len as isize);
+        for (i, byte) in buffer.iter().enumerate() {
+            assert_eq!(*byte, byte_at(offset + i));
+        }
+    }
+    assert_eq!(read(fd, &mut buffer), 0);
+    close(fd);
+    report("file read", chunk_len, FILE_LEN, start);
+}
+
+fn bench_pipe(chunk_len: usize) {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let start = get_time();
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        let mut buffer = vec![0u8; chunk_len];
+        for offset in (0..PIPE_LEN).step_by(chunk_len) {
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                *byte = byte_at(offset + i);
+            }
+            assert_eq!(write(pipe_fd[1], &buffer), chunk_len as isize);
+        }
+        close(pipe_fd[1]);
+        exit(0);
+    }
+    close(pipe_fd[1]);
+    let mut buffer = vec![0u8; chunk_len];
+    let mut offset = 0;
+    loop {
+        let len = read(pipe_fd[0], &mut buffer);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        for byte in buffer[..len as usize].iter() {
+            assert_eq!(*byte, byte_at(offset));
+            offset += 1;
+        }
+    }
+    assert_eq!(offset, PIPE_LEN);
+    close(pipe_fd[0]);
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    report("pipe", chunk_len, PIPE_LEN, start);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    for chunk_len in CHUNK_LENS {
+        bench_file(chunk_len);
+        bench_pipe(chunk_len);
+    }
+    println!("huge_write_bench passed!");
+    0
+}
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user_lib::{exit, mmap, mprotect, thread_create, waittid, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+/// `li a0, 42` and `ret`.
+const CODE: [u32; 2] = [0x02a00513, 0x00008067];
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Wait until the code at entry is made executable, then exit with what it returns.
+fn runner(entry: usize) -> ! {
+    while !READY.load(Ordering::Acquire) {}
+    let code: fn() -> i32 = unsafe { core::mem::transmute(entry) };
+    exit(code())
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("Into Test icache_sync, a thread runs code written by another thread...");
+    let start = mmap(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    ) as usize;
+    // The runner may be on another hart, whose instruction cache is synced when the page is made executable.
+    let tid = thread_create(runner as usize, start);
+    let code = start as *mut u32;
+    for (i, instruction) in CODE.iter().enumerate() {
+        unsafe {
+            code.add(i).write_volatile(*instruction);
+        }
+    }
+    if mprotect(start, PAGE_SIZE, MmapProt::READ | MmapProt::EXEC) != 0 {
+        println!("Failed to make the page executable!");
+        return 1;
+    }
+    READY.store(true, Ordering::Release);
+    let exit_code = waittid(tid as usize);
+    if exit_code != 42 {
+        println!("The code returned {} instead of 42!", exit_code);
+        return 1;
+    }
+    println!("icache_sync passed!");
+    0
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect, munmap, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS;
+    let start = mmap(0, 4 * PAGE_SIZE, MmapProt::READ | MmapProt::WRITE, flags);
+    assert!(start > 0);
+    let start = start as usize;
+    let buffer = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, 4 * PAGE_SIZE) };
+    // Anonymous mappings are zero-filled.
+    assert!(buffer.iter().all(|byte| *byte == 0));
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    // Split the mapping into three parts and merge them back.
+    assert_eq!(mprotect(start + PAGE_SIZE, PAGE_SIZE, MmapProt::READ), 0);
+    assert_eq!(
+        mprotect(start, 4 * PAGE_SIZE, MmapProt::READ | MmapProt::WRITE),
+        0
+    );
+    assert!(buffer.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+    // Punch a hole in the middle and map it again at the hinted address.
+    assert_eq!(munmap(start + PAGE_SIZE, 2 * PAGE_SIZE), 0);
+    assert_eq!(
+        mprotect(start, 4 * PAGE_SIZE, MmapProt::READ),
+        -1,
+        "mprotect should fail on unmapped pages"
+    );
+    assert_eq!(
+        mmap(start + PAGE_SIZE, PAGE_SIZE, MmapProt::READ, flags),
+        (start + PAGE_SIZE) as isize
+    );
+    assert_eq!(buffer[PAGE_SIZE], 0);
+    assert_eq!(buffer[3 * PAGE_SIZE], (3 * PAGE_SIZE) as u8);
+    assert_eq!(munmap(start, 4 * PAGE_SIZE), 0);
+    // Generate code at runtime: "li a0, 42; ret".
+    let code = mmap(0, PAGE_SIZE, MmapProt::READ | MmapProt::WRITE, flags) as usize;
+    let instructions: [u32; 2] = [0x02a00513, 0x00008067];
+    unsafe {
+        core::ptr::copy_nonoverlapping(instructions.as_ptr(), code as *mut u32, 2);
+    }
+    assert_eq!(
+        mprotect(code, PAGE_SIZE, MmapProt::READ | MmapProt::EXEC),
+        0
+    );
+    unsafe {
+        core::arch::asm!("fence.i");
+    }
+    let f: extern "C" fn() -> usize = unsafe { core::mem::transmute(code) };
+    assert_eq!(f(), 42);
+    assert_eq!(munmap(code, PAGE_SIZE), 0);
+    println!("mmap passed!");
+    0
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fork, mmap_file, mprotect, msync, munmap, open, pipe, read, waitpid, write, MmapFlags,
+    MmapProt, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+const FILE_SIZE: usize = 2 * PAGE_SIZE + 100;
+
+fn pattern(offset: usize) -> u8 {
+    (offset % 251) as u8
+}
+
+/// Return the byte at offset of the file, which is read through a new file descriptor.
+fn read_byte(name: &str, offset: usize) -> u8 {
+    let fd = open(name, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buf = [0u8; FILE_SIZE];
+    assert_eq!(read(fd as usize, &mut buf), FILE_SIZE as isize);
+    close(fd as usize);
+    buf[offset]
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let name = "mmap_filea\0";
+    let fd = open(name, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let data: [u8; FILE_SIZE] = core::array::from_fn(pattern);
+    assert_eq!(write(fd, &data), FILE_SIZE as isize);
+
+    // Only files can be mapped, from aligned offsets.
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    let prot = MmapProt::READ | MmapProt::WRITE;
+    assert_eq!(
+        mmap_file(0, PAGE_SIZE, prot, MmapFlags::SHARED, pipe_fd[0], 0),
+        -1
+    );
+    assert_eq!(mmap_file(0, PAGE_SIZE, prot, MmapFlags::SHARED, fd, 1), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    let read_only_fd = open(name, OpenFlags::RDONLY) as usize;
+    assert_eq!(
+        mmap_file(0, PAGE_SIZE, prot, MmapFlags::SHARED, read_only_fd, 0),
+        -1
+    );
+    // Nor can a read-only shared mapping of it be made writable later, while a private one can.
+    let read_only = mmap_file(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ,
+        MmapFlags::SHARED,
+        read_only_fd,
+        0,
+    );
+    assert!(read_only > 0);
+    assert_eq!(mprotect(read_only as usize, PAGE_SIZE, prot), -1);
+    assert_eq!(munmap(read_only as usize, PAGE_SIZE), 0);
+    let read_only = mmap_file(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ,
+        MmapFlags::PRIVATE,
+        read_only_fd,
+        0,
+    );
+    assert!(read_only > 0);
+    assert_eq!(mprotect(read_only as usize, PAGE_SIZE, prot), 0);
+    assert_eq!(munmap(read_only as usize, PAGE_SIZE), 0);
+    close(read_only_fd);
+
+    let shared = mmap_file(0, 3 * PAGE_SIZE, prot, MmapFlags::SHARED, fd, 0);
+    assert!(shared > 0);
+    let shared = unsafe { core::slice::from_raw_parts_mut(shared as *mut u8, 3 * PAGE_SIZE) };
+    assert_eq!(&shared[..FILE_SIZE], &data);
+    // The rest of the last page is zero-filled.
+    assert!(shared[FILE_SIZE..].iter().all(|byte| *byte == 0));
+
+    // A private mapping is a copy of the file.
+    let private = mmap_file(0, PAGE_SIZE, prot, MmapFlags::PRIVATE, fd, PAGE_SIZE);
+    assert!(private > 0);
+    let private = unsafe { core::slice::from_raw_parts_mut(private as *mut u8, PAGE_SIZE) };
+    assert_eq!(private, &data[PAGE_SIZE..2 * PAGE_SIZE]);
+    private[0] = !pattern(PAGE_SIZE);
+    assert_eq!(shared[PAGE_SIZE], pattern(PAGE_SIZE));
+    assert_eq!(read_byte(name, PAGE_SIZE), pattern(PAGE_SIZE));
+
+    // The writes to a shared mapping are visible to read, to other processes and to write.
+    shared[1] = !pattern(1);
+    assert_eq!(read_byte(name, 1), !pattern(1));
+    assert_eq!(msync(shared.as_ptr() as usize, PAGE_SIZE), 0);
+    let pid = fork();
+    if pid == 0 {
+        shared[PAGE_SIZE + 1] = !pattern(PAGE_SIZE + 1);
+        return 0;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(shared[PAGE_SIZE + 1], !pattern(PAGE_SIZE + 1));
+    assert_eq!(read_byte(name, PAGE_SIZE + 1), !pattern(PAGE_SIZE + 1));
+    shared[2] = !pattern(2);
+    assert_eq!(munmap(shared.as_ptr() as usize, 3 * PAGE_SIZE), 0);
+    assert_eq!(munmap(private.as_ptr() as usize, PAGE_SIZE), 0);
+    close(fd);
+    assert_eq!(read_byte(name, 2), !pattern(2));
+    println!("mmap_file passed!");
+    0
+}
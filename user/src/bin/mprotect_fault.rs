@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("Into Test mprotect_fault, we will write to a read-only mapping...");
+    println!("Kernel should kill this application!");
+    let start = mmap(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    ) as usize;
+    unsafe {
+        (start as *mut u8).write_volatile(1);
+    }
+    mprotect(start, PAGE_SIZE, MmapProt::READ);
+    unsafe {
+        (start as *mut u8).write_volatile(2);
+    }
+    0
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    fork, getpid, getrlimit, memory_usage, mmap, munmap, setrlimit, waitpid, MemoryUsage,
+    MmapFlags, MmapProt, RLimit, RLIMIT_RSS,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+// Larger than the physical memory of the 128MB guest and the 64MB swap area together.
+const HUGE_LEN: usize = 256 << 20;
+
+/// Map len bytes and write to each page of them.
+fn touch(len: usize) -> usize {
+    let start = mmap(
+        0,
+        len,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(start > 0);
+    let start = start as usize;
+    for page in 0..len / PAGE_SIZE {
+        unsafe {
+            ((start + page * PAGE_SIZE) as *mut usize).write_volatile(page);
+        }
+    }
+    start
+}
+
+fn usage() -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    assert_eq!(memory_usage(getpid() as usize, &mut usage), 0);
+    usage
+}
+
+/// Run f in a child process, and return its exit code.
+fn run_child(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        panic!("The child should be killed!");
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let before = usage();
+    println!(
+        "memory usage: rss = {}, swap = {}, page_table = {}",
+        before.rss, before.swap, before.page_table
+    );
+    assert!(before.rss > 0 && before.page_table > 0);
+    let start = touch(64 * PAGE_SIZE);
+    let after = usage();
+    assert!(after.rss + after.swap >= before.rss + before.swap + 64);
+    assert_eq!(munmap(start, 64 * PAGE_SIZE), 0);
+
+    let mut rlimit = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_RSS, &mut rlimit), 0);
+    assert_eq!(rlimit.cur, usize::MAX);
+
+    // A process exceeding its memory limit is killed.
+    let exit_code = run_child(|| {
+        let usage = usage();
+        let used = usage.rss + usage.swap + usage.page_table;
+        let rlimit = RLimit {
+            cur: (used + 64) * PAGE_SIZE,
+            max: (used + 128) * PAGE_SIZE,
+        };
+        assert_eq!(setrlimit(RLIMIT_RSS, &rlimit), 0);
+        let raised = RLimit {
+            cur: rlimit.cur,
+            max: usize::MAX,
+        };
+        assert_eq!(setrlimit(RLIMIT_RSS, &raised), -1);
+        touch(32 * PAGE_SIZE);
+        touch(256 * PAGE_SIZE);
+    });
+    assert_eq!(exit_code, -9);
+
+    // A process using up the memory is killed instead of the kernel panicking.
+    let exit_code = run_child(|| {
+        touch(HUGE_LEN);
+    });
+    assert_eq!(exit_code, -9);
+
+    // The memory of the killed processes is freed.
+    let start = touch(1024 * PAGE_SIZE);
+    assert_eq!(munmap(start, 1024 * PAGE_SIZE), 0);
+    println!("oom passed!");
+    0
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, exec, exec_with_flags, fork, getauxval, open, waitpid, write, ExecFlags, OpenFlags,
+    AT_ENTRY, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM,
+};
+
+/// This program is linked as a PIE, which is loaded at PIE_BASE, or above it with ASLR.
+const PIE_BASE: usize = 0x4000_0000;
+const PT_DYNAMIC: u32 = 2;
+
+extern "C" {
+    /// The dynamic section, which is defined by the linker.
+    static _DYNAMIC: u8;
+}
+
+fn double(x: usize) -> usize {
+    x * 2
+}
+
+fn square(x: usize) -> usize {
+    x * x
+}
+
+// The pointers in these statics are only right after the loader applies the relocations.
+static NAMES: [&str; 2] = ["double", "square"];
+static FUNCTIONS: [fn(usize) -> usize; 2] = [double, square];
+
+/// Check that the program is relocated and the auxiliary vector describes it.
+fn check() {
+    let entry = getauxval(AT_ENTRY).unwrap();
+    assert_eq!(entry, user_lib::_start as usize);
+    assert!(entry >= PIE_BASE);
+    assert_eq!(getauxval(AT_PAGESZ), Some(0x1000));
+    assert_eq!(NAMES[0].len() + NAMES[1].len(), 12);
+    assert_eq!(FUNCTIONS[0](3) + FUNCTIONS[1](3), 15);
+    // The dynamic program header is found at the address of the dynamic section after adding the load bias.
+    let phdr = getauxval(AT_PHDR).unwrap();
+    let phent = getauxval(AT_PHENT).unwrap();
+    let phnum = getauxval(AT_PHNUM).unwrap();
+    let dynamic = (0..phnum)
+        .map(|i| phdr + i * phent)
+        .find(|ph| unsafe { (*ph as *const u32).read() } == PT_DYNAMIC)
+        .unwrap();
+    // p_vaddr follows p_type, p_flags and p_offset.
+    let dynamic_vaddr = unsafe { ((dynamic + 16) as *const usize).read() };
+    let bias = unsafe { &_DYNAMIC as *const u8 as usize } - dynamic_vaddr;
+    assert_eq!(bias % 0x1000, 0);
+    assert!(bias >= PIE_BASE - 0x10000);
+    // AT_RANDOM points to 16 random bytes.
+    let random =
+        unsafe { core::slice::from_raw_parts(getauxval(AT_RANDOM).unwrap() as *const u8, 16) };
+    assert!(random.iter().any(|byte| *byte != 0));
+    assert_eq!(getauxval(0x1234), None);
+}
+
+#[no_mangle]
+pub fn main(argc: usize, _argv: &[&str]) -> i32 {
+    check();
+    if argc == 2 {
+        return 0;
+    }
+    println!(
+        "pie: {} at {:#x}, {} at {:#x}",
+        NAMES[0], FUNCTIONS[0] as usize, NAMES[1], FUNCTIONS[1] as usize
+    );
+    // The program works wherever it is loaded.
+    for _ in 0..2 {
+        let pid = fork();
+        if pid == 0 {
+            let args = [
+                "pie\0".as_ptr(),
+                "child\0".as_ptr(),
+                core::ptr::null::<u8>(),
+            ];
+            exec_with_flags("pie\0", &args, ExecFlags::ASLR);
+            panic!("exec failed!");
+        }
+        let mut exit_code: i32 = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    // A file which is not an executable is refused, and the process goes on.
+    let fd = open("pie_bad\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"not an elf file"), 15);
+    close(fd);
+    assert_eq!(exec("pie_bad\0", &[core::ptr::null::<u8>()]), -1);
+    println!("pie passed!");
+    0
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fork, get_time, getpid, gettid, pipe, read, sched_info, set_priority, waitpid, write,
+    SchedInfo, MAX_PRIORITY, PRIO_PROCESS, PRIO_THREAD, SCHED_STRIDE,
+};
+
+/// The CPU-bound children should get shares of the CPU in proportion to their priorities, under the stride policy
+/// on one hart. The other policies ignore the priorities, and with more harts each child may get a hart of its own.
+const PRIORITIES: [usize; 3] = [5, 10, 20];
+/// The children start counting at the same time, after all of them are forked.
+const START_DELAY_MS: isize = 100;
+const DURATION_MS: isize = 2000;
+/// How far the share of each child may be from the expected one, in percent.
+const TOLERANCE: usize = 25;
+
+/// Count how many times the time can be read in the measured period.
+fn count(start: isize) -> usize {
+    while get_time() < start {}
+    let mut count = 0;
+    while get_time() < start + DURATION_MS {
+        count += 1;
+    }
+    count
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(set_priority(PRIO_THREAD, gettid() as usize, 0), -1);
+    assert_eq!(
+        set_priority(PRIO_THREAD, gettid() as usize, MAX_PRIORITY + 1),
+        -1
+    );
+    assert_eq!(set_priority(PRIO_THREAD, 100, 8), -1);
+    assert_eq!(set_priority(PRIO_PROCESS, 100000, 8), -1);
+    assert_eq!(set_priority(2, getpid() as usize, 8), -1);
+
+    let mut info = SchedInfo::default();
+    assert_eq!(sched_info(&mut info), 0);
+    if info.policy != SCHED_STRIDE || info.harts != 1 {
+        println!(
+            "priority: the shares of the CPU are not checked under policy {} on {} harts",
+            info.policy, info.harts
+        );
+        println!("priority passed!");
+        return 0;
+    }
+
+    let start = get_time() + START_DELAY_MS;
+    let mut children = [(0isize, 0usize); PRIORITIES.len()];
+    for (i, priority) in PRIORITIES.iter().enumerate() {
+        let mut pipe_fd = [0usize; 2];
+        assert_eq!(pipe(&mut pipe_fd), 0);
+        let pid = fork();
+        if pid == 0 {
+            close(pipe_fd[0]);
+            // The first child sets its own priority, and the parent sets those of the others.
+            if i == 0 {
+                assert_eq!(set_priority(PRIO_THREAD, gettid() as usize, *priority), 0);
+            }
+            let count = count(start);
+            assert_eq!(write(pipe_fd[1], &count.to_ne_bytes()), 8);
+            close(pipe_fd[1]);
+            return 0;
+        }
+        close(pipe_fd[1]);
+        if i > 0 {
+            assert_eq!(set_priority(PRIO_PROCESS, pid as usize, *priority), 0);
+        }
+        children[i] = (pid, pipe_fd[0]);
+    }
+
+    let mut counts = [0usize; PRIORITIES.len()];
+    for (i, (pid, fd)) in children.iter().enumerate() {
+        let mut bytes = [0u8; 8];
+        assert_eq!(read(*fd, &mut bytes), 8);
+        close(*fd);
+        counts[i] = usize::from_ne_bytes(bytes);
+        let mut exit_code = 0;
+        assert_eq!(waitpid(*pid as usize, &mut exit_code), *pid);
+        assert_eq!(exit_code, 0);
+    }
+    let total_count: usize = counts.iter().sum();
+    let total_priority: usize = PRIORITIES.iter().sum();
+    for (count, priority) in counts.iter().zip(PRIORITIES.iter()) {
+        // The shares in per mille.
+        let share = count * 1000 / total_count;
+        let expected = priority * 1000 / total_priority;
+        println!(
+            "priority {}: {} counts, {}/1000 of the CPU, {}/1000 expected",
+            priority, count, share, expected
+        );
+        assert!(share * 100 >= expected * (100 - TOLERANCE));
+        assert!(share * 100 <= expected * (100 + TOLERANCE));
+    }
+    println!("priority passed!");
+    0
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, get_time, sched_info, sleep, waitpid, SchedInfo, SCHED_MLFQ};
+
+/// The CPU-bound children keep the CPU busy while the parent sleeps over and over, like an interactive task.
+const HOGS: usize = 3;
+const HOG_DURATION_MS: isize = 1500;
+const SLEEPS: usize = 50;
+const SLEEP_MS: usize = 10;
+/// The running task is preempted every tick, and a sleeping one is woken up at the first tick after its timer expires.
+const TICK_MS: isize = 10;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let end = get_time() + HOG_DURATION_MS;
+    let mut pids = [0isize; HOGS];
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            while get_time() < end {}
+            return 0;
+        }
+    }
+
+    // How much later than asked the parent runs again after each sleep.
+    let mut total_latency = 0;
+    let mut max_latency = 0;
+    for _ in 0..SLEEPS {
+        let start = get_time();
+        sleep(SLEEP_MS);
+        let latency = (get_time() - start - SLEEP_MS as isize).max(0);
+        total_latency += latency;
+        max_latency = max_latency.max(latency);
+    }
+    println!(
+        "wakeup latency under {} CPU-bound tasks: {}ms on average, {}ms at most",
+        HOGS,
+        total_latency / SLEEPS as isize,
+        max_latency
+    );
+
+    // Waking up takes up to a tick, and then the parent runs at the next one. The multi-level feedback queue favors it
+    // over the hogs, and the other policies may run each hog for a tick before it.
+    let mut info = SchedInfo::default();
+    assert_eq!(sched_info(&mut info), 0);
+    let bound = match info.policy {
+        SCHED_MLFQ => 2 * TICK_MS,
+        _ => (HOGS as isize + 2) * TICK_MS,
+    };
+    assert!(
+        total_latency / SLEEPS as isize <= bound,
+        "the average wakeup latency is over {}ms",
+        bound
+    );
+
+    for pid in pids.iter() {
+        let mut exit_code = 0;
+        assert_eq!(waitpid(*pid as usize, &mut exit_code), *pid);
+        assert_eq!(exit_code, 0);
+    }
+    println!("sched_latency passed!");
+    0
+}
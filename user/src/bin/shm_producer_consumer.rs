@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use user_lib::{exit, fork, shmat, shmdt, shmget, waitpid, yield_};
+
+const SHM_KEY: usize = 0x5348;
+const CAPACITY: usize = 16;
+const ITEM_COUNT: usize = 1000;
+
+/// A single-producer single-consumer ring buffer placed in the shared memory.
+#[repr(C)]
+struct Ring {
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    items: [usize; CAPACITY],
+}
+
+fn attach() -> &'static mut Ring {
+    let id = shmget(SHM_KEY, core::mem::size_of::<Ring>());
+    assert!(id >= 0);
+    let addr = shmat(id as usize, 0);
+    assert!(addr > 0);
+    unsafe { &mut *(addr as *mut Ring) }
+}
+
+fn produce(ring: &mut Ring) {
+    for item in 0..ITEM_COUNT {
+        while ring.tail.load(Ordering::Acquire) - ring.head.load(Ordering::Acquire) == CAPACITY {
+            yield_();
+        }
+        let tail = ring.tail.load(Ordering::Relaxed);
+        ring.items[tail % CAPACITY] = item;
+        ring.tail.store(tail + 1, Ordering::Release);
+    }
+}
+
+fn consume(ring: &mut Ring) {
+    for item in 0..ITEM_COUNT {
+        while ring.tail.load(Ordering::Acquire) == ring.head.load(Ordering::Acquire) {
+            yield_();
+        }
+        let head = ring.head.load(Ordering::Relaxed);
+        assert_eq!(ring.items[head % CAPACITY], item);
+        ring.head.store(head + 1, Ordering::Release);
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // The consumer attaches by key, while the producer inherits the mapping through fork.
+    let ring = attach();
+    let pid = fork();
+    if pid == 0 {
+        produce(ring);
+        exit(0);
+    }
+    let consumer_ring = attach();
+    consume(consumer_ring);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(shmdt(consumer_ring as *mut Ring as usize), 0);
+    assert_eq!(shmdt(ring as *mut Ring as usize), 0);
+    // The shared memory is freed after the last detach, so it is created again with zeros.
+    let ring = attach();
+    assert_eq!(ring.tail.load(Ordering::Relaxed), 0);
+    assert_eq!(shmdt(ring as *mut Ring as usize), 0);
+    println!("shm_producer_consumer passed!");
+    0
+}
@@ -0,0 +1,13 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::shutdown;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("Shutting down...");
+    shutdown(0)
+}
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::hint::black_box;
+use user_lib::{fork, getrlimit, setrlimit, waitpid, RLimit, RLIMIT_STACK};
+
+const FRAME_SIZE: usize = 0x1000;
+
+/// Use about depth * FRAME_SIZE bytes of stack, and return the sum of the bytes written.
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; FRAME_SIZE];
+    for (i, byte) in frame.iter_mut().enumerate() {
+        *byte = (i + depth) as u8;
+    }
+    let frame = black_box(frame);
+    let sum: usize = frame.iter().map(|byte| *byte as usize).sum();
+    if depth == 0 {
+        sum
+    } else {
+        sum + recurse(depth - 1)
+    }
+}
+
+fn expected_sum(depth: usize) -> usize {
+    (0..=depth)
+        .map(|d| {
+            (0..FRAME_SIZE)
+                .map(|i| ((i + d) as u8) as usize)
+                .sum::<usize>()
+        })
+        .sum()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut rlimit = RLimit::default();
+    assert_eq!(getrlimit(RLIMIT_STACK, &mut rlimit), 0);
+    println!(
+        "stack limit: cur = {:#x}, max = {:#x}",
+        rlimit.cur, rlimit.max
+    );
+    // The stack grows far beyond its initial size.
+    assert_eq!(recurse(256), expected_sum(256));
+
+    // The hard limit cannot be raised.
+    let raised = RLimit {
+        cur: rlimit.max,
+        max: rlimit.max + FRAME_SIZE,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &raised), -1);
+
+    // The stack already grown is kept, but cannot grow beyond the lowered limit.
+    let lowered = RLimit {
+        cur: 0x10000,
+        max: rlimit.max,
+    };
+    assert_eq!(setrlimit(RLIMIT_STACK, &lowered), 0);
+    assert_eq!(recurse(128), expected_sum(128));
+    let pid = fork();
+    if pid == 0 {
+        recurse(512);
+        panic!("The stack should overflow!");
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, -11);
+    println!("stack_grow passed!");
+    0
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+// Larger than the physical memory of the 128MB guest, so part of it has to be swapped out.
+const LEN: usize = 144 << 20;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let start = mmap(
+        0,
+        LEN,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    );
+    assert!(start > 0);
+    let start = start as usize;
+    for round in 0..2 {
+        for page in 0..LEN / PAGE_SIZE {
+            let p = (start + page * PAGE_SIZE) as *mut usize;
+            unsafe {
+                if round == 1 {
+                    assert_eq!(p.read_volatile(), page);
+                }
+                p.write_volatile(page);
+            }
+        }
+        println!("swap: round {} done", round);
+    }
+    assert_eq!(munmap(start, LEN), 0);
+    println!("swap passed!");
+    0
+}
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, mmap, mprotect, thread_create, MmapFlags, MmapProt};
+
+const PAGE_SIZE: usize = 0x1000;
+const WAIT_MS: isize = 100;
+
+/// Keep writing to the page at counter, until the write faults once it is read-only.
+fn writer(counter: *mut usize) -> ! {
+    let mut count = 0;
+    loop {
+        count += 1;
+        unsafe {
+            counter.write_volatile(count);
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("Into Test tlb_shootdown, a thread keeps writing to a page made read-only...");
+    println!("Kernel should kill this application!");
+    let start = mmap(
+        0,
+        PAGE_SIZE,
+        MmapProt::READ | MmapProt::WRITE,
+        MmapFlags::PRIVATE | MmapFlags::ANONYMOUS,
+    ) as usize;
+    let counter = start as *mut usize;
+    thread_create(writer as usize, start);
+    while unsafe { counter.read_volatile() } == 0 {}
+    mprotect(start, PAGE_SIZE, MmapProt::READ);
+    // The writer may run on another hart, which must not keep writing through its TLB.
+    let count = unsafe { counter.read_volatile() };
+    let end = get_time() + WAIT_MS;
+    while get_time() < end {}
+    if unsafe { counter.read_volatile() } != count {
+        println!("The page is written after it is made read-only!");
+        return 1;
+    }
+    unsafe {
+        counter.write_volatile(0);
+    }
+    0
+}
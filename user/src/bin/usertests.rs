@@ -5,7 +5,7 @@
 extern crate user_lib;
 
 // not in SUCC_TESTS & FAIL_TESTS
-// count_lines, infloop, user_shell, usertests
+// count_lines, infloop, shutdown, user_shell, usertests
 
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
@@ -14,6 +14,8 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("adder_mutex_spin\0", "\0", "\0", "\0", 0),
     ("adder_peterson_spin\0", "\0", "\0", "\0", 0),
     ("adder_peterson_yield\0", "\0", "\0", "\0", 0),
+    ("aslr\0", "\0", "\0", "\0", 0),
+    ("bad_address\0", "\0", "\0", "\0", 0),
     ("barrier_condvar\0", "\0", "\0", "\0", 0),
     ("barrier_fail\0", "\0", "\0", "\0", 0),
     ("filetest_simple\0", "\0", "\0", "\0", 0),
@@ -30,22 +32,36 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("forktest\0", "\0", "\0", "\0", 0),
     ("forktest2\0", "\0", "\0", "\0", 0),
     ("forktree\0", "\0", "\0", "\0", 0),
+    ("heap_grow\0", "\0", "\0", "\0", 0),
+    ("heap_stats\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
+    ("icache_sync\0", "\0", "\0", "\0", 0),
+    ("huge_page\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
+    ("huge_write_bench\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("mmap\0", "\0", "\0", "\0", 0),
+    ("mmap_file\0", "\0", "\0", "\0", 0),
     ("mpsc_sem\0", "\0", "\0", "\0", 0),
+    ("oom\0", "\0", "\0", "\0", 0),
     ("peterson\0", "\0", "\0", "\0", 0),
+    ("pie\0", "\0", "\0", "\0", 0),
     ("phil_din_mutex\0", "\0", "\0", "\0", 0),
     ("pipe_large_test\0", "\0", "\0", "\0", 0),
     ("pipetest\0", "\0", "\0", "\0", 0),
+    ("priority\0", "\0", "\0", "\0", 0),
     ("run_pipe_test\0", "\0", "\0", "\0", 0),
+    ("sched_latency\0", "\0", "\0", "\0", 0),
+    ("shm_producer_consumer\0", "\0", "\0", "\0", 0),
     ("sig_simple\0", "\0", "\0", "\0", 0),
     ("sig_simple2\0", "\0", "\0", "\0", 0),
     ("sig_tests\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
+    ("stack_grow\0", "\0", "\0", "\0", 0),
     ("stackful_coroutine\0", "\0", "\0", "\0", 0),
     ("stackless_coroutine\0", "\0", "\0", "\0", 0),
+    ("swap\0", "\0", "\0", "\0", 0),
     ("sync_sem\0", "\0", "\0", "\0", 0),
     ("threads_arg\0", "\0", "\0", "\0", 0),
     ("threads\0", "\0", "\0", "\0", 0),
@@ -57,10 +73,12 @@ static FAIL_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("adder_simple_spin\0", "\0", "\0", "\0", -6),
     ("adder_simple_yield\0", "\0", "\0", "\0", -6),
     ("adder\0", "\0", "\0", "\0", -6),
+    ("mprotect_fault\0", "\0", "\0", "\0", -11),
     ("priv_csr\0", "\0", "\0", "\0", -4),
     ("priv_inst\0", "\0", "\0", "\0", -4),
     ("stack_overflow\0", "\0", "\0", "\0", -11),
     ("store_fault\0", "\0", "\0", "\0", -11),
+    ("tlb_shootdown\0", "\0", "\0", "\0", -11),
     ("until_timeout\0", "\0", "\0", "\0", -6),
 ];
 
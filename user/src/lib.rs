@@ -6,7 +6,13 @@ extern crate alloc;
 
 use alloc::vec::Vec;
 use bitflags::bitflags;
-use buddy_allocator::LockedBuddyAllocator;
+pub use buddy_allocator::HeapStats;
+use buddy_allocator::{BuddyAllocator, LockedBuddyAllocator};
+use core::{
+    alloc::Layout,
+    cmp::max,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use syscall::*;
 
 pub mod console;
@@ -14,11 +20,26 @@ pub mod console;
 mod lang_items;
 mod syscall;
 
-const USER_HEAP_SIZE: usize = 0x8000;
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// The heap grows by at least USER_HEAP_GROWTH bytes each time.
+const USER_HEAP_GROWTH: usize = 0x8000;
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::empty();
+static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::with_rescue(grow_heap);
+
+fn grow_heap(heap: &mut BuddyAllocator, layout: &Layout) {
+    // The new memory begins with the bitmaps of the heap, which take less than 1/32 of it, and the rest is split into
+    // aligned blocks, so three times the size is enough to hold an aligned block for layout.
+    let size = 3 * max(
+        max(layout.size(), layout.align()).next_power_of_two(),
+        USER_HEAP_GROWTH,
+    );
+    let start = sbrk(size as isize);
+    if start != -1 {
+        unsafe {
+            heap.init(start as usize, size);
+        }
+    }
+}
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -28,11 +49,12 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".text.entry")]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    unsafe {
-        HEAP_ALLOCATOR
-            .lock()
-            .init(&raw const HEAP_SPACE as usize, USER_HEAP_SIZE);
+    // The auxiliary vector follows argv and envp, which end with nullptrs.
+    let mut envp = argv + (argc + 1) * core::mem::size_of::<usize>();
+    while unsafe { (envp as *const usize).read_volatile() } != 0 {
+        envp += core::mem::size_of::<usize>();
     }
+    AUXV.store(envp + core::mem::size_of::<usize>(), Ordering::Relaxed);
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start =
@@ -51,6 +73,32 @@ pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     exit(main(argc, v.as_slice()));
 }
 
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_ENTRY: usize = 9;
+pub const AT_RANDOM: usize = 25;
+
+/// The address of the auxiliary vector passed by the kernel on the user stack.
+static AUXV: AtomicUsize = AtomicUsize::new(0);
+
+/// Return the value of the entry of type in the auxiliary vector, or None if there is no such entry.
+pub fn getauxval(type_: usize) -> Option<usize> {
+    let mut entry = AUXV.load(Ordering::Relaxed) as *const [usize; 2];
+    loop {
+        let [key, value] = unsafe { entry.read_volatile() };
+        if key == AT_NULL {
+            return None;
+        }
+        if key == type_ {
+            return Some(value);
+        }
+        entry = entry.wrapping_add(1);
+    }
+}
+
 #[linkage = "weak"]
 #[unsafe(no_mangle)]
 fn main(_argc: usize, _argv: &[&str]) -> i32 {
@@ -143,6 +191,86 @@ impl Default for SignalAction {
     }
 }
 
+/// The limit of the size of each user stack, which grows on demand.
+pub const RLIMIT_STACK: usize = 3;
+/// The limit of the memory used by the process, including the pages swapped out and its page table. A process
+/// exceeding it is killed.
+pub const RLIMIT_RSS: usize = 5;
+
+/// The targets of set_priority.
+pub const PRIO_PROCESS: usize = 0;
+pub const PRIO_THREAD: usize = 1;
+/// A thread gets a share of the CPU proportional to its priority, which is DEFAULT_PRIORITY unless it is set, and is
+/// inherited by new threads and forked processes.
+pub const DEFAULT_PRIORITY: usize = 16;
+pub const MAX_PRIORITY: usize = 1024;
+
+/// The scheduling policies of the kernel.
+pub const SCHED_FIFO: usize = 0;
+pub const SCHED_STRIDE: usize = 1;
+pub const SCHED_MLFQ: usize = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedInfo {
+    /// One of SCHED_FIFO, SCHED_STRIDE and SCHED_MLFQ.
+    pub policy: usize,
+    /// The number of harts running tasks.
+    pub harts: usize,
+}
+
+/// cur is enforced, and can be raised up to max. max can only be lowered.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+/// The memory used by a process in pages. The pages shared with other processes are counted in each of them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryUsage {
+    /// The pages in memory.
+    pub rss: usize,
+    /// The pages swapped out.
+    pub swap: usize,
+    /// The frames of the page table.
+    pub page_table: usize,
+}
+
+/// The statistics of a slab cache of the kernel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlabStats {
+    /// The name of the cache, padded with zeros.
+    pub name: [u8; 24],
+    pub object_size: usize,
+    pub slab_size: usize,
+    pub slab_count: usize,
+    pub objects_in_use: usize,
+    pub alloc_count: usize,
+    pub free_count: usize,
+}
+
+impl SlabStats {
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap()
+    }
+}
+
+bitflags! {
+    pub struct ExecFlags: usize {
+        /// Place the user stacks, the user heap and the mmap area of the program at random addresses.
+        const ASLR = 1 << 0;
+    }
+}
+
 bitflags! {
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
@@ -153,6 +281,26 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct MmapProt: usize {
+        const NONE = 0;
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+        /// Use 2MiB huge pages, so addr should be aligned to 2MiB.
+        const HUGETLB = 1 << 18;
+    }
+}
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
@@ -181,6 +329,11 @@ pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code)
 }
 
+/// Shut down the machine, reporting a failure if exit_code is not 0.
+pub fn shutdown(exit_code: i32) -> ! {
+    sys_shutdown(exit_code)
+}
+
 pub fn sleep(sleep_ms: usize) {
     sys_sleep(sleep_ms);
 }
@@ -189,6 +342,13 @@ pub fn yield_() -> isize {
     sys_yield()
 }
 
+/// Set the priority of all threads of the process who if which is PRIO_PROCESS, or of the thread who of this process
+/// if which is PRIO_THREAD. Return -1 if the priority is not in [1, MAX_PRIORITY] or there is no such process or
+/// thread.
+pub fn set_priority(which: usize, who: usize, priority: usize) -> isize {
+    sys_set_priority(which, who, priority)
+}
+
 pub fn kill(pid: usize, signum: i32) -> isize {
     sys_kill(pid, signum)
 }
@@ -213,6 +373,11 @@ pub fn sigreturn() -> isize {
     sys_sigreturn()
 }
 
+/// Write the scheduling policy of the kernel and the number of harts running tasks to info.
+pub fn sched_info(info: &mut SchedInfo) -> isize {
+    sys_sched_info(info)
+}
+
 pub fn get_time() -> isize {
     sys_get_time()
 }
@@ -221,12 +386,36 @@ pub fn getpid() -> isize {
     sys_getpid()
 }
 
+pub fn memory_usage(pid: usize, usage: &mut MemoryUsage) -> isize {
+    sys_memory_usage(pid, usage)
+}
+
+/// Write the usage of the kernel heap to stats.
+pub fn heap_stats(stats: &mut HeapStats) -> isize {
+    sys_heap_stats(stats)
+}
+
+/// Write the statistics of the slab caches of the kernel to stats, and return the number of caches, which may be more
+/// than stats.len().
+pub fn slab_stats(stats: &mut [SlabStats]) -> isize {
+    sys_slab_stats(stats.as_mut_ptr(), stats.len())
+}
+
+/// Return the usage of the heap of this process.
+pub fn user_heap_stats() -> HeapStats {
+    HEAP_ALLOCATOR.stats()
+}
+
 pub fn fork() -> isize {
     sys_fork()
 }
 
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
-    sys_exec(path, args)
+    sys_exec(path, args, 0)
+}
+
+pub fn exec_with_flags(path: &str, args: &[*const u8], flags: ExecFlags) -> isize {
+    sys_exec(path, args, flags.bits)
 }
 
 pub fn wait(exit_code: &mut i32) -> isize {
@@ -255,6 +444,85 @@ pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
     sys_waitpid(pid as isize, exit_code as *mut _)
 }
 
+/// shmget always creates a new shared memory for IPC_PRIVATE.
+pub const IPC_PRIVATE: usize = 0;
+
+/// Return the id of the shared memory with key, which is created with size bytes if it does not exist.
+/// The shared memory is freed when its creator exits and the last process attached to it detaches or exits.
+/// Creating it fails if it exceeds the memory limit of the process, or the limit of all shared memories.
+pub fn shmget(key: usize, size: usize) -> isize {
+    sys_shmget(key, size)
+}
+
+/// Attach the shared memory at addr, or at an address chosen by the kernel if addr is 0.
+/// Return the address it is attached at, or -1 if fails.
+pub fn shmat(shm_id: usize, addr: usize) -> isize {
+    sys_shmat(shm_id, addr)
+}
+
+pub fn shmdt(addr: usize) -> isize {
+    sys_shmdt(addr)
+}
+
+/// Return 0 if succeeds, or -1 if fails.
+pub fn brk(addr: usize) -> isize {
+    if sys_brk(addr) == addr as isize {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Return the old program break, or -1 if fails.
+pub fn sbrk(increment: isize) -> isize {
+    let old_brk = sys_brk(0);
+    let new_brk = old_brk + increment;
+    if sys_brk(new_brk as usize) == new_brk {
+        old_brk
+    } else {
+        -1
+    }
+}
+
+pub fn getrlimit(resource: usize, rlimit: &mut RLimit) -> isize {
+    sys_getrlimit(resource, rlimit)
+}
+
+pub fn setrlimit(resource: usize, rlimit: &RLimit) -> isize {
+    sys_setrlimit(resource, rlimit)
+}
+
+/// Return the start address of the mapping, or -1 if fails. Anonymous mappings must be private.
+pub fn mmap(addr: usize, len: usize, prot: MmapProt, flags: MmapFlags) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, usize::MAX, 0)
+}
+
+/// Like mmap, but the file fd is mapped from offset, which must be aligned to the page size.
+pub fn mmap_file(
+    addr: usize,
+    len: usize,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
+}
+
+/// Write the dirty pages of the shared file mappings in [addr, addr + len) back to the files.
+pub fn msync(addr: usize, len: usize) -> isize {
+    sys_msync(addr, len, 0)
+}
+
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mprotect(addr, len, prot.bits)
+}
+
+/// Return the tid of the new thread, or -1 if the process already has 1024 threads or memory runs short.
 pub fn thread_create(entry: usize, arg: usize) -> isize {
     sys_thread_create(entry, arg)
 }
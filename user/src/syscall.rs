@@ -1,4 +1,4 @@
-use crate::SignalAction;
+use crate::{HeapStats, MemoryUsage, RLimit, SchedInfo, SignalAction, SlabStats};
 use core::arch::asm;
 
 const SYSCALL_DUP: usize = 24;
@@ -14,10 +14,22 @@ const SYSCALL_KILL: usize = 129;
 const SYSCALL_SIGACTION: usize = 134;
 const SYSCALL_SIGPROCMASK: usize = 135;
 const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_SHUTDOWN: usize = 142;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
@@ -31,6 +43,10 @@ const SYSCALL_SEMAPHORE_DOWN: usize = 1022;
 const SYSCALL_CONDVAR_CREATE: usize = 1030;
 const SYSCALL_CONDVAR_SIGNAL: usize = 1031;
 const SYSCALL_CONDVAR_WAIT: usize = 1032;
+const SYSCALL_MEMORY_USAGE: usize = 1040;
+const SYSCALL_HEAP_STATS: usize = 1041;
+const SYSCALL_SLAB_STATS: usize = 1042;
+const SYSCALL_SCHED_INFO: usize = 1050;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -46,6 +62,23 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => ret,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a7") id
+        );
+    }
+    ret
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
@@ -106,6 +139,23 @@ pub fn sys_sigreturn() -> isize {
     syscall(SYSCALL_SIGRETURN, [0, 0, 0])
 }
 
+pub fn sys_set_priority(which: usize, who: usize, priority: usize) -> isize {
+    syscall(SYSCALL_SET_PRIORITY, [which, who, priority])
+}
+
+pub fn sys_shutdown(exit_code: i32) -> ! {
+    syscall(SYSCALL_SHUTDOWN, [exit_code as usize, 0, 0]);
+    unreachable!();
+}
+
+pub fn sys_getrlimit(resource: usize, rlimit: *mut RLimit) -> isize {
+    syscall(SYSCALL_GETRLIMIT, [resource, rlimit as usize, 0])
+}
+
+pub fn sys_setrlimit(resource: usize, rlimit: *const RLimit) -> isize {
+    syscall(SYSCALL_SETRLIMIT, [resource, rlimit as usize, 0])
+}
+
 pub fn sys_get_time() -> isize {
     syscall(SYSCALL_GET_TIME, [0, 0, 0])
 }
@@ -118,10 +168,10 @@ pub fn sys_fork() -> isize {
     syscall(SYSCALL_FORK, [0, 0, 0])
 }
 
-pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
+pub fn sys_exec(path: &str, args: &[*const u8], flags: usize) -> isize {
     syscall(
         SYSCALL_EXEC,
-        [path.as_ptr() as usize, args.as_ptr() as usize, 0],
+        [path.as_ptr() as usize, args.as_ptr() as usize, flags],
     )
 }
 
@@ -129,6 +179,45 @@ pub fn sys_waitpid(pid: isize, exit_code: *mut i32) -> isize {
     syscall(SYSCALL_WAITPID, [pid as usize, exit_code as usize, 0])
 }
 
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, size, 0])
+}
+
+pub fn sys_shmat(shm_id: usize, addr: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [shm_id, addr, 0])
+}
+
+pub fn sys_shmdt(addr: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [addr, 0, 0])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    syscall6(SYSCALL_MMAP, [addr, len, prot, flags, fd, offset])
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    syscall(SYSCALL_MPROTECT, [addr, len, prot])
+}
+
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MSYNC, [addr, len, flags])
+}
+
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
 }
@@ -176,3 +265,19 @@ pub fn sys_condvar_signal(condvar_id: usize) -> isize {
 pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     syscall(SYSCALL_CONDVAR_WAIT, [condvar_id, mutex_id, 0])
 }
+
+pub fn sys_memory_usage(pid: usize, usage: *mut MemoryUsage) -> isize {
+    syscall(SYSCALL_MEMORY_USAGE, [pid, usage as usize, 0])
+}
+
+pub fn sys_heap_stats(stats: *mut HeapStats) -> isize {
+    syscall(SYSCALL_HEAP_STATS, [stats as usize, 0, 0])
+}
+
+pub fn sys_sched_info(info: *mut SchedInfo) -> isize {
+    syscall(SYSCALL_SCHED_INFO, [info as usize, 0, 0])
+}
+
+pub fn sys_slab_stats(stats: *mut SlabStats, count: usize) -> isize {
+    syscall(SYSCALL_SLAB_STATS, [stats as usize, count, 0])
+}